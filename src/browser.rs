@@ -3,10 +3,10 @@ use futures::Future;
 use wasm_bindgen::closure::WasmClosure;
 use wasm_bindgen::{closure::Closure, JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::js_sys::ArrayBuffer;
+use web_sys::js_sys::{ArrayBuffer, Date};
 use web_sys::{
-    CanvasRenderingContext2d, Document, Element, HtmlCanvasElement, HtmlElement, HtmlImageElement,
-    Response, Window,
+    CanvasRenderingContext2d, Document, Element, Gamepad, HtmlCanvasElement, HtmlElement,
+    HtmlImageElement, Response, Storage, UrlSearchParams, Window,
 };
 
 macro_rules! log {
@@ -143,6 +143,59 @@ pub fn now() -> Result<f64> {
         .now())
 }
 
+/**
+ * 接続されているゲームパッドの一覧を取得する。
+ */
+pub fn gamepads() -> Result<Vec<Gamepad>> {
+    Ok(window()?
+        .navigator()
+        .get_gamepads()
+        .map_err(|err| anyhow!("Could not get gamepads {:#?}", err))?
+        .iter()
+        // 切断されたゲームパッドの場所にはnullが入っている
+        .filter_map(|gamepad| gamepad.dyn_into::<Gamepad>().ok())
+        .filter(|gamepad| gamepad.connected())
+        .collect())
+}
+
+fn local_storage() -> Result<Storage> {
+    window()?
+        .local_storage()
+        .map_err(|err| anyhow!("Error getting local storage {:#?}", err))?
+        .ok_or_else(|| anyhow!("No Local Storage Found"))
+}
+
+/**
+ * localStorageから値を読み込む。保存されていなければNoneを返す。
+ */
+pub fn load_item(key: &str) -> Result<Option<String>> {
+    local_storage()?
+        .get_item(key)
+        .map_err(|err| anyhow!("Could not load {} from local storage {:#?}", key, err))
+}
+
+/**
+ * localStorageに値を保存する。
+ */
+pub fn save_item(key: &str, value: &str) -> Result<()> {
+    local_storage()?
+        .set_item(key, value)
+        .map_err(|err| anyhow!("Could not save {} to local storage {:#?}", key, err))
+}
+
+/**
+ * ページのURLのクエリ文字列から値を取り出す。指定されていなければNoneを返す。
+ */
+pub fn query_param(name: &str) -> Result<Option<String>> {
+    let search = window()?
+        .location()
+        .search()
+        .map_err(|err| anyhow!("Could not get query string {:#?}", err))?;
+    let params = UrlSearchParams::new_with_str(&search)
+        .map_err(|err| anyhow!("Could not parse query string {:#?}", err))?;
+    Ok(params.get(name))
+}
+
 fn find_ui() -> Result<Element> {
     document().and_then(|doc| {
         doc.get_element_by_id("ui")
@@ -181,23 +234,43 @@ pub fn draw_ui(html: &str) -> Result<Element> {
 }
 
 /**
- * UIを隠す。追加されたUIはた高々一つと仮定している。
+ * UIを隠す。追加されたUIをすべて取り除く。
  */
 pub fn hide_ui() -> Result<()> {
     let ui = find_ui()?;
 
-    if let Some(child) = ui.first_child() {
+    if ui.first_child().is_none() {
+        return Ok(());
+    }
+    while let Some(child) = ui.first_child() {
         ui.remove_child(&child)
-            .map(|_removed_child| ())
-            .map_err(|err| anyhow!("Failed to remove child {:#?}", err))
-            .and_then(|_unit| {
-                canvas()?
-                    .focus()
-                    .map_err(|err| anyhow!("Could not set focus to canvas! {:#?}", err))
-            })
-    } else {
-        Ok(())
+            .map_err(|err| anyhow!("Failed to remove child {:#?}", err))?;
     }
+    canvas()?
+        .focus()
+        .map_err(|err| anyhow!("Could not set focus to canvas! {:#?}", err))
+}
+
+/**
+ * ページの表示・非表示が切り替わるたびに、隠れたかどうかを`handler`に伝える。
+ */
+pub fn on_visibility_change(mut handler: impl FnMut(bool) + 'static) -> Result<()> {
+    let document = document()?;
+    let callback = closure_wrap(Box::new({
+        let document = document.clone();
+        move || handler(document.hidden())
+    }) as Box<dyn FnMut()>);
+    document.set_onvisibilitychange(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+    Ok(())
+}
+
+/**
+ * 今日の日付を（年, 月, 日）で返す。月は1から始まる。
+ */
+pub fn today() -> (u32, u32, u32) {
+    let date = Date::new_0();
+    (date.get_full_year(), date.get_month() + 1, date.get_date())
 }
 
 #[cfg(test)]
@@ -208,6 +281,7 @@ mod tests {
     wasm_bindgen_test_configure!(run_in_browser);
 
     #[wasm_bindgen_test]
+    #[allow(clippy::bool_assert_comparison)]
     async fn test_error_loading_json() {
         let json = fetch_json("not_therer.json").await;
 
@@ -1,9 +1,9 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::channel::oneshot::channel;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Mutex;
 use std::{cell::RefCell, rc::Rc};
 use wasm_bindgen::prelude::*;
@@ -13,9 +13,22 @@ use web_sys::{
 };
 
 use crate::browser::{self, LoopClosure};
+use crate::engine::debug::{DebugSettings, HotKeys};
+use crate::engine::gamepad::{BrowserGamepads, GamepadConnection, GamepadInput};
+use crate::engine::gesture::{Gesture, GestureRecognizer, PointerSample};
+use crate::engine::profiler::{FrameSample, Profiler, PROFILER_ENABLED};
+use crate::engine::time::{DebugTimeKeys, TimeControl};
 use crate::sound::{self, Looping};
 
-pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
+pub mod debug;
+pub mod gamepad;
+pub mod gesture;
+#[cfg(test)]
+pub mod headless;
+pub mod profiler;
+pub mod time;
+
+pub async fn load_image(source: &str) -> Result<ImageHandle> {
     let image = browser::new_image()?;
 
     let (complete_tx, complete_rx) = channel::<Result<()>>();
@@ -59,17 +72,27 @@ pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
 
     complete_rx.await??;
 
-    Ok(image)
+    Ok(ImageHandle::new(source, image))
 }
 
 #[async_trait(?Send)]
 pub trait Game {
     async fn intialize(&self) -> Result<Box<dyn Game>>;
-    fn update(&mut self, key_state: &KeyState);
-    fn draw(&self, renderer: &Renderer);
+    /// `time`でゲームの時間の速さを変えたり、ヒットストップで止めたりできる。
+    fn update(&mut self, key_state: &KeyState, time: &mut TimeControl);
+    /// 一時停止中も含めて、毎フレームの更新の前に呼ばれる。
+    /// 一時停止の切り替えのように、ゲームの時間が止まっていても受け付ける入力を扱う。
+    fn update_time(&mut self, key_state: &KeyState, time: &mut TimeControl);
+    /// `alpha`は前回の更新から次の更新までの経過の割合で、0.0から1.0まで。
+    /// 直前の2回の更新の間を補間して描けば、更新より速い画面でも滑らかに動く。
+    /// `debug`で選ばれた開発用の表示も一緒に描く。
+    fn draw(&self, renderer: &dyn Renderer, alpha: f32, debug: &DebugSettings);
 }
 
-const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0;
+pub(crate) const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0;
+/// 1フレームで追いつくために更新する最大の回数。
+/// 処理が遅れてこれを超えた分の時間は捨て、ゲームの方を遅くする。
+const MAX_UPDATES_PER_FRAME: u32 = 5;
 
 pub struct GameLoop {
     last_frame: f64,
@@ -78,6 +101,9 @@ pub struct GameLoop {
      * JavaScriptはf64のみだが、ここでは問題ない。
      */
     accumulated_delta: f32,
+    time: TimeControl,
+    profiler: Profiler,
+    debug: DebugSettings,
 }
 type SharedLoopClosure = Rc<RefCell<Option<LoopClosure>>>;
 
@@ -85,32 +111,84 @@ impl GameLoop {
     pub async fn start(game: impl Game + 'static) -> Result<()> {
         let mut keyevent_receiver = prepare_input()?;
         let mut game = game.intialize().await?;
-        let mut game_loop = GameLoop {
-            last_frame: browser::now()?,
-            accumulated_delta: 0.0,
-        };
-        let renderer = Renderer {
-            context: browser::context()?,
-        };
+        let mut game_loop = GameLoop::new(browser::now()?);
+        // タブが隠れている間は時間を溜めず、戻ってきた時にその間の更新をしない
+        let was_hidden = Rc::new(std::cell::Cell::new(false));
+        browser::on_visibility_change({
+            let was_hidden = Rc::clone(&was_hidden);
+            move |hidden| {
+                if hidden {
+                    was_hidden.set(true);
+                }
+            }
+        })?;
+        let renderer = CanvasRenderer::new(browser::context()?);
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
         let g = f.clone();
 
         let mut key_state = KeyState::new();
+        let mut gamepads = BrowserGamepads;
+        let mut gamepad_input = GamepadInput::new();
+        match gamepad::configured_deadzone() {
+            Ok(Some(deadzone)) => gamepad_input.set_deadzone(deadzone),
+            Ok(None) => {}
+            Err(err) => {
+                error!("Could not load gamepad deadzone {:#?}", err);
+            }
+        }
+        let mut gestures = GestureRecognizer::new();
+        let mut debug_time_keys = DebugTimeKeys::new();
+        let mut debug_hot_keys = HotKeys::new();
         *g.borrow_mut() = Some(browser::create_raf_closure(move |perf: f64| {
-            process_input(&mut key_state, &mut keyevent_receiver);
-
-            let frame_time = perf - game_loop.last_frame;
-            game_loop.accumulated_delta += frame_time as f32;
-            while game_loop.accumulated_delta > FRAME_SIZE {
-                game.update(&key_state);
-                game_loop.accumulated_delta -= FRAME_SIZE;
+            process_input(&mut key_state, &mut gestures, &mut keyevent_receiver, perf);
+            match gamepad_input.update(&mut gamepads, &mut key_state) {
+                Ok(connections) => connections.iter().for_each(|connection| match connection {
+                    GamepadConnection::Connected(index) => {
+                        log!(
+                            "Gamepad {} connected (connected: {:?})",
+                            index,
+                            gamepad_input.connected()
+                        );
+                    }
+                    GamepadConnection::Disconnected(index) => {
+                        log!(
+                            "Gamepad {} disconnected (connected: {:?})",
+                            index,
+                            gamepad_input.connected()
+                        );
+                    }
+                }),
+                Err(err) => {
+                    error!("Could not poll gamepads {:#?}", err);
+                }
             }
-            game_loop.last_frame = perf;
-            game.draw(&renderer);
 
+            if was_hidden.replace(false) {
+                game_loop.resume(perf);
+            }
             if cfg!(debug_assertions) {
-                unsafe {
-                    draw_frame_rate(&renderer, frame_time);
+                debug_time_keys.update(&key_state, &mut game_loop.time);
+            }
+            game_loop
+                .debug
+                .handle_hotkeys(&mut debug_hot_keys, &key_state);
+            let frame_time = (perf - game_loop.last_frame) as f32;
+            let update_start = profiler_now(perf);
+            let updates = game_loop.run_frame(perf, game.as_mut(), &mut key_state);
+            let updated = profiler_now(update_start);
+            game.draw(&renderer, game_loop.alpha(), &game_loop.debug);
+
+            if PROFILER_ENABLED {
+                let drawn = profiler_now(updated);
+                game_loop.profiler.record(FrameSample {
+                    frame_time,
+                    update_time: (updated - update_start) as f32,
+                    draw_time: (drawn - updated) as f32,
+                    updates,
+                });
+                game_loop.profiler.handle_toggle(&key_state);
+                if game_loop.profiler.is_visible() {
+                    game_loop.profiler.draw(&renderer);
                 }
             }
 
@@ -125,12 +203,97 @@ impl GameLoop {
         )?;
         Ok(())
     }
+
+    fn new(now: f64) -> Self {
+        GameLoop {
+            last_frame: now,
+            accumulated_delta: 0.0,
+            time: TimeControl::new(),
+            profiler: Profiler::new(),
+            debug: DebugSettings::default(),
+        }
+    }
+
+    /// このフレームの分だけゲームを更新する。
+    ///
+    /// 更新の途中でゲームがヒットストップを始めたら、このフレームの残りの更新はしない。
+    /// 実際に更新した回数を返す。
+    fn run_frame(&mut self, now: f64, game: &mut dyn Game, key_state: &mut KeyState) -> u32 {
+        game.update_time(key_state, &mut self.time);
+        let mut updates = 0;
+        for _ in 0..self.advance(now) {
+            game.update(key_state, &mut self.time);
+            key_state.tick();
+            updates += 1;
+            if self.time.is_hit_stopped() {
+                self.accumulated_delta = self.accumulated_delta.min(FRAME_SIZE);
+                break;
+            }
+        }
+        // 一時停止中の入力の変化は、再開した時に古くなっているので捨てる
+        if updates == 0 && self.time.is_paused() {
+            key_state.tick();
+        }
+        updates
+    }
+
+    /// 前のフレームからの時間を溜めて、このフレームで更新する回数を返す。
+    ///
+    /// 溜める時間は[`TimeControl`]の速さに合わせて伸び縮みする。
+    /// 一時停止中はコマ送りの分だけ更新する。
+    /// 更新は最大で[`MAX_UPDATES_PER_FRAME`]回で、追いつけない分の時間は捨てる。
+    fn advance(&mut self, now: f64) -> u32 {
+        let frame_time = (now - self.last_frame) as f32;
+        self.last_frame = now;
+        if self.time.is_paused() {
+            return self.time.take_steps().min(MAX_UPDATES_PER_FRAME);
+        }
+        self.accumulated_delta += self.time.elapse(frame_time);
+        let mut updates = 0;
+        while self.accumulated_delta > FRAME_SIZE {
+            if updates == MAX_UPDATES_PER_FRAME {
+                self.accumulated_delta = FRAME_SIZE;
+                break;
+            }
+            self.accumulated_delta -= FRAME_SIZE;
+            updates += 1;
+        }
+        updates
+    }
+
+    /// 最後の更新から次の更新までの経過の割合
+    fn alpha(&self) -> f32 {
+        (self.accumulated_delta / FRAME_SIZE).clamp(0.0, 1.0)
+    }
+
+    /// 止まっていた時間を捨てて、`now`から時間を数え直す
+    fn resume(&mut self, now: f64) {
+        self.last_frame = now;
+        self.accumulated_delta = 0.0;
+    }
 }
 
-pub struct Renderer {
+/// 描画先の抽象
+///
+/// ブラウザでは[`CanvasRenderer`]を使う。
+/// テストではブラウザなしで動く[`headless::RecordingRenderer`]を使い、描画命令の列を確認する。
+pub trait Renderer {
+    fn clear(&self, rect: &Rect);
+    fn draw_image(&self, image: &ImageHandle, frame: &Rect, destination: &Rect);
+    fn draw_entire_image(&self, image: &ImageHandle, position: &Point);
+    /// デバッグ時に衝突範囲のbouding_boxを描画する
+    fn draw_rect(&self, rect: &Rect, color: (u8, u8, u8));
+    /// デバッグ時に速度などの向きを描画する
+    fn draw_line(&self, from: &Point, to: &Point, color: (u8, u8, u8));
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()>;
+}
+
+/// canvasの2Dコンテキストに描画する[`Renderer`]
+pub struct CanvasRenderer {
     context: CanvasRenderingContext2d,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Rect {
     pub position: Point,
     pub width: i16,
@@ -172,10 +335,194 @@ impl Rect {
     pub fn right(&self) -> i16 {
         self.x() + self.width
     }
+
+    pub fn bottom(&self) -> i16 {
+        self.y() + self.height
+    }
+
+    /// `dx`, `dy`だけずらした矩形
+    pub fn translate(&self, dx: i16, dy: i16) -> Rect {
+        Rect::new_from_x_y(self.x() + dx, self.y() + dy, self.width, self.height)
+    }
+
+    /// `self`が`other`と重なっている時の、押し出すのに一番短い向きと深さ。
+    /// 深さが同じなら、上下に押し出す方を選ぶ。辺が触れるだけでは重なったことにしない
+    pub fn overlap(&self, other: &Rect) -> Option<Overlap> {
+        if !self.intersects(other) {
+            return None;
+        }
+        [
+            Point { x: 0, y: -1 },
+            Point { x: 0, y: 1 },
+            Point { x: -1, y: 0 },
+            Point { x: 1, y: 0 },
+        ]
+        .into_iter()
+        .filter_map(|normal| {
+            Some(Overlap {
+                normal,
+                depth: self.penetration(other, normal)?,
+            })
+        })
+        .min_by_key(|overlap| overlap.depth)
+    }
+
+    /// `self`を`normal`の向きに押し出して`other`と重ならなくするのに、動かす距離。
+    /// `normal`が上下左右どれかの長さ1の向きでなければ`None`
+    pub fn penetration(&self, other: &Rect, normal: Point) -> Option<i16> {
+        match (normal.x, normal.y) {
+            (0, -1) => Some(self.bottom() - other.y()),
+            (0, 1) => Some(other.bottom() - self.y()),
+            (-1, 0) => Some(self.right() - other.x()),
+            (1, 0) => Some(other.right() - self.x()),
+            _ => None,
+        }
+    }
+
+    /// `self`を`delta`だけまっすぐ動かす間に、止まっている`other`と重なり始める時を求める。
+    ///
+    /// 動き終わった位置だけを見る[`Rect::intersects`]と違い、1回で`other`を飛び越えてしまう
+    /// 速さでもぶつかったことがわかる。辺が触れるだけでは重なったことにしない。
+    pub fn sweep(&self, delta: Point, other: &Rect) -> Option<Sweep> {
+        let x = Rect::overlap_times(self.x(), self.width, other.x(), other.width, delta.x)?;
+        let y = Rect::overlap_times(self.y(), self.height, other.y(), other.height, delta.y)?;
+        let enter = x.0.max(y.0);
+        let exit = x.1.min(y.1);
+        if enter >= exit || exit <= 0.0 || enter >= 1.0 {
+            return None;
+        }
+        let normal = if enter < 0.0 {
+            Point { x: 0, y: 0 }
+        } else if x.0 > y.0 {
+            Point {
+                x: -delta.x.signum(),
+                y: 0,
+            }
+        } else {
+            Point {
+                x: 0,
+                y: -delta.y.signum(),
+            }
+        };
+        Some(Sweep {
+            time: enter.max(0.0),
+            normal,
+        })
+    }
+
+    /// 1つの軸で、`start`から`size`の区間を`delta`動かす時に`other_start`から`other_size`の
+    /// 区間と重なっている時の開区間。`delta`の何倍動いた時かで表す
+    fn overlap_times(
+        start: i16,
+        size: i16,
+        other_start: i16,
+        other_size: i16,
+        delta: i16,
+    ) -> Option<(f32, f32)> {
+        // ずらす量がこの間にあれば重なる
+        let low = f32::from(other_start) - f32::from(start) - f32::from(size);
+        let high = f32::from(other_start) + f32::from(other_size) - f32::from(start);
+        if delta == 0 {
+            return (low < 0.0 && 0.0 < high).then_some((f32::NEG_INFINITY, f32::INFINITY));
+        }
+        let (low, high) = (low / f32::from(delta), high / f32::from(delta));
+        Some((low.min(high), low.max(high)))
+    }
+}
+
+/// [`Rect::sweep`]でわかった、動く矩形がぶつかった時とぶつかった面
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sweep {
+    /// 動き始めを0、動き終わりを1とした、重なり始めた時
+    pub time: f32,
+    /// ぶつかった面の向き。上から乗った時は(0, -1)、右へ動いて左の面にぶつかった時は(-1, 0)。
+    /// 初めから重なっていた時は(0, 0)
+    pub normal: Point,
+}
+
+/// [`Rect::overlap`]でわかった、重なりを解く一番短い押し出し方
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overlap {
+    /// 押し出す向き。上の面に重なっていて上へ押し出す時は(0, -1)
+    pub normal: Point,
+    /// 押し出す距離
+    pub depth: i16,
 }
 
-impl Renderer {
-    pub fn clear(&self, rect: &Rect) {
+impl Overlap {
+    /// 重なりを解くために動かす量
+    pub fn translation(&self) -> Point {
+        Point {
+            x: self.normal.x * self.depth,
+            y: self.normal.y * self.depth,
+        }
+    }
+}
+
+/// すべての描画を`offset`だけずらして`renderer`に描く[`Renderer`]
+pub struct TranslatedRenderer<'a> {
+    renderer: &'a dyn Renderer,
+    offset: Point,
+}
+
+impl<'a> TranslatedRenderer<'a> {
+    pub fn new(renderer: &'a dyn Renderer, offset: Point) -> Self {
+        TranslatedRenderer { renderer, offset }
+    }
+
+    fn translate_point(&self, point: &Point) -> Point {
+        Point {
+            x: point.x + self.offset.x,
+            y: point.y + self.offset.y,
+        }
+    }
+
+    fn translate_rect(&self, rect: &Rect) -> Rect {
+        rect.translate(self.offset.x, self.offset.y)
+    }
+}
+
+impl Renderer for TranslatedRenderer<'_> {
+    fn clear(&self, rect: &Rect) {
+        self.renderer.clear(&self.translate_rect(rect));
+    }
+
+    fn draw_image(&self, image: &ImageHandle, frame: &Rect, destination: &Rect) {
+        self.renderer
+            .draw_image(image, frame, &self.translate_rect(destination));
+    }
+
+    fn draw_entire_image(&self, image: &ImageHandle, position: &Point) {
+        self.renderer
+            .draw_entire_image(image, &self.translate_point(position));
+    }
+
+    fn draw_rect(&self, rect: &Rect, color: (u8, u8, u8)) {
+        self.renderer.draw_rect(&self.translate_rect(rect), color);
+    }
+
+    fn draw_line(&self, from: &Point, to: &Point, color: (u8, u8, u8)) {
+        self.renderer.draw_line(
+            &self.translate_point(from),
+            &self.translate_point(to),
+            color,
+        );
+    }
+
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
+        self.renderer
+            .draw_text(text, &self.translate_point(location))
+    }
+}
+
+impl CanvasRenderer {
+    pub fn new(context: CanvasRenderingContext2d) -> Self {
+        CanvasRenderer { context }
+    }
+}
+
+impl Renderer for CanvasRenderer {
+    fn clear(&self, rect: &Rect) {
         self.context.clear_rect(
             rect.x().into(),
             rect.y().into(),
@@ -184,10 +531,14 @@ impl Renderer {
         )
     }
 
-    pub fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+    fn draw_image(&self, image: &ImageHandle, frame: &Rect, destination: &Rect) {
+        // 要素を持たない画像はブラウザ外で作られたものなので描画できない
+        let Some(element) = image.element() else {
+            return;
+        };
         self.context
             .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
-                image,
+                element,
                 frame.x().into(),
                 frame.y().into(),
                 frame.width.into(),
@@ -197,18 +548,31 @@ impl Renderer {
                 destination.width.into(),
                 destination.height.into(),
             )
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Drawing {} is throwing exceptions! Unrecoverable error. {:#?}",
+                    image.name(),
+                    err
+                )
+            });
     }
 
-    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
+    fn draw_entire_image(&self, image: &ImageHandle, position: &Point) {
+        let Some(element) = image.element() else {
+            return;
+        };
         self.context
-            .draw_image_with_html_image_element(image, position.x.into(), position.y.into())
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+            .draw_image_with_html_image_element(element, position.x.into(), position.y.into())
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Drawing {} is throwing exceptions! Unrecoverable error. {:#?}",
+                    image.name(),
+                    err
+                )
+            });
     }
 
-    /// デバッグ時に衝突範囲のbouding_boxを描画する
-    #[allow(dead_code)]
-    pub fn draw_rect(&self, rect: &Rect, color: (u8, u8, u8)) {
+    fn draw_rect(&self, rect: &Rect, color: (u8, u8, u8)) {
         let color_str = format!("rgb({}, {}, {})", color.0, color.1, color.2);
         self.context
             .set_stroke_style(&JsValue::from_str(&color_str));
@@ -226,61 +590,82 @@ impl Renderer {
         self.context.stroke();
     }
 
-    #[allow(dead_code)]
-    pub fn draw_text(&self, test: &str, location: &Point) -> Result<()> {
+    fn draw_line(&self, from: &Point, to: &Point, color: (u8, u8, u8)) {
+        let color_str = format!("rgb({}, {}, {})", color.0, color.1, color.2);
+        self.context
+            .set_stroke_style(&JsValue::from_str(&color_str));
+        self.context.begin_path();
+        self.context.move_to(from.x.into(), from.y.into());
+        self.context.line_to(to.x.into(), to.y.into());
+        self.context.stroke();
+    }
+
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
         self.context.set_font("16pt serif");
         self.context
-            .fill_text(test, location.x.into(), location.y.into())
+            .fill_text(text, location.x.into(), location.y.into())
             .map_err(|err| anyhow!("Error filling text {:#?}", err))?;
         Ok(())
     }
 }
 
-/// フレームレートを表示するデバッグ用の関数
-///
-/// この関数はマルチスレッドでは安全ではないのでunsafe
-/// ブラウザ環境ではこの関数がマルチスレッドで呼ばれることはない。
-/// それよりも速さが大切なので、unsafeで良い。
-unsafe fn draw_frame_rate(renderer: &Renderer, frame_time: f64) {
-    static mut FRAMES_COUNTED: i32 = 0;
-    static mut TOTAL_FRAME_TIME_MILLISECONDS: f64 = 0.0;
-    static mut FRAME_RATE: i32 = 0;
-
-    FRAMES_COUNTED += 1;
-    TOTAL_FRAME_TIME_MILLISECONDS += frame_time;
-    // 1秒が過ぎたら計測結果を更新
-    if TOTAL_FRAME_TIME_MILLISECONDS > 1000.0 {
-        FRAME_RATE = FRAMES_COUNTED;
-        TOTAL_FRAME_TIME_MILLISECONDS = 0.0;
-        FRAMES_COUNTED = 0;
-    }
-    if let Err(err) = renderer.draw_text(
-        &format!("Frame Rate {}", FRAME_RATE),
-        &Point { x: 400, y: 100 },
-    ) {
-        error!("Could not draw text {:#?}", err);
-    }
-}
-
-enum KeyPress {
+/// 計測用の現在時刻。計測しない時や取れない時は`fallback`を返す
+fn profiler_now(fallback: f64) -> f64 {
+    if PROFILER_ENABLED {
+        browser::now().unwrap_or(fallback)
+    } else {
+        fallback
+    }
+}
+
+enum InputEvent {
     KeyUp(web_sys::KeyboardEvent),
     KeyDown(web_sys::KeyboardEvent),
+    PointerDown(PointerSample),
+    PointerMove(PointerSample),
+    PointerUp(PointerSample),
+    PointerCancel(i32),
+}
+
+fn pointer_sample(evt: &web_sys::PointerEvent) -> PointerSample {
+    PointerSample {
+        id: evt.pointer_id(),
+        x: evt.offset_x() as f64,
+        y: evt.offset_y() as f64,
+        time: evt.time_stamp(),
+    }
 }
 
-fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
+/// ポインタのイベントを`to_input_event`で変換して送るクロージャ
+fn pointer_closure(
+    sender: Rc<RefCell<UnboundedSender<InputEvent>>>,
+    to_input_event: fn(&web_sys::PointerEvent) -> InputEvent,
+) -> Closure<dyn FnMut(web_sys::PointerEvent)> {
+    browser::closure_wrap(Box::new(move |evt: web_sys::PointerEvent| {
+        // タッチでページがスクロールしたり拡大したりしないようにする
+        evt.prevent_default();
+        sender
+            .borrow_mut()
+            .start_send(to_input_event(&evt))
+            .expect("can not send pointer message");
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>)
+}
+
+fn prepare_input() -> Result<UnboundedReceiver<InputEvent>> {
     let (keydown_sender, keyevent_receiver) = unbounded();
     let keydown_sender = Rc::new(RefCell::new(keydown_sender));
     let keyup_sender = Rc::clone(&keydown_sender);
+    let pointer_sender = Rc::clone(&keydown_sender);
     let onkeydown = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
         keydown_sender
             .borrow_mut()
-            .start_send(KeyPress::KeyDown(keycode))
+            .start_send(InputEvent::KeyDown(keycode))
             .expect("can not send keydown message");
     }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
     let onkeyup = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
         keyup_sender
             .borrow_mut()
-            .start_send(KeyPress::KeyUp(keycode))
+            .start_send(InputEvent::KeyUp(keycode))
             .expect("can not send keyup message");
     }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
     browser::canvas()
@@ -291,59 +676,192 @@ fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
         .set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
     onkeydown.forget();
     onkeyup.forget();
+
+    let canvas = browser::canvas()?;
+    let onpointerdown = pointer_closure(Rc::clone(&pointer_sender), |evt| {
+        InputEvent::PointerDown(pointer_sample(evt))
+    });
+    let onpointermove = pointer_closure(Rc::clone(&pointer_sender), |evt| {
+        InputEvent::PointerMove(pointer_sample(evt))
+    });
+    let onpointerup = pointer_closure(Rc::clone(&pointer_sender), |evt| {
+        InputEvent::PointerUp(pointer_sample(evt))
+    });
+    let onpointercancel = pointer_closure(pointer_sender, |evt| {
+        InputEvent::PointerCancel(evt.pointer_id())
+    });
+    canvas.set_onpointerdown(Some(onpointerdown.as_ref().unchecked_ref()));
+    canvas.set_onpointermove(Some(onpointermove.as_ref().unchecked_ref()));
+    canvas.set_onpointerup(Some(onpointerup.as_ref().unchecked_ref()));
+    canvas.set_onpointercancel(Some(onpointercancel.as_ref().unchecked_ref()));
+    onpointerdown.forget();
+    onpointermove.forget();
+    onpointerup.forget();
+    onpointercancel.forget();
     Ok(keyevent_receiver)
 }
 
+/// キーの入力状態
+///
+/// 押されているかどうかに加えて、直前のtickからの変化（押された、離された）と
+/// 押され続けているtick数を記録する。
+/// 変化はゲームの更新一回ごとに[`KeyState::tick`]で消えるので、
+/// 一回のキー押下にゲームが一度だけ反応できる。
 pub struct KeyState {
-    pressed_keys: HashMap<String, web_sys::KeyboardEvent>,
+    /// 押されているキーと、押されてから経過したtick数
+    pressed_keys: HashMap<String, u32>,
+    just_pressed_keys: HashSet<String>,
+    just_released_keys: HashSet<String>,
 }
 
 impl KeyState {
-    fn new() -> Self {
+    pub fn new() -> Self {
         KeyState {
             pressed_keys: HashMap::new(),
+            just_pressed_keys: HashSet::new(),
+            just_released_keys: HashSet::new(),
         }
     }
     pub fn is_pressed(&self, code: &str) -> bool {
         self.pressed_keys.contains_key(code)
     }
-    fn set_pressed(&mut self, code: &str, event: web_sys::KeyboardEvent) {
-        self.pressed_keys.insert(code.into(), event);
+    /// このtickの間に押されたか
+    ///
+    /// 同じtickの間に押されて離された場合もtrueになる。
+    pub fn is_just_pressed(&self, code: &str) -> bool {
+        self.just_pressed_keys.contains(code)
     }
-    fn set_released(&mut self, code: &str) {
-        self.pressed_keys.remove(code);
+    /// このtickの間に離されたか
+    pub fn is_just_released(&self, code: &str) -> bool {
+        self.just_released_keys.contains(code)
+    }
+    /// 押されてから経過したtick数。押されていなければ0。
+    pub fn held_ticks(&self, code: &str) -> u32 {
+        self.pressed_keys.get(code).copied().unwrap_or(0)
+    }
+    pub fn set_pressed(&mut self, code: &str) {
+        // キーを押し続けるとkeydownが繰り返し送られてくるので、最初の一回だけを押下とする
+        if !self.pressed_keys.contains_key(code) {
+            self.pressed_keys.insert(code.into(), 0);
+            self.just_pressed_keys.insert(code.into());
+        }
+    }
+    pub fn set_released(&mut self, code: &str) {
+        if self.pressed_keys.remove(code).is_some() {
+            self.just_released_keys.insert(code.into());
+        }
+    }
+    /// ゲームの更新が一回終わったことを伝える。
+    ///
+    /// 押された、離されたという変化を消し、押され続けているキーのtick数を進める。
+    pub fn tick(&mut self) {
+        self.just_pressed_keys.clear();
+        self.just_released_keys.clear();
+        self.pressed_keys
+            .values_mut()
+            .for_each(|held_ticks| *held_ticks += 1);
     }
 }
 
-fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver<KeyPress>) {
+fn process_input(
+    state: &mut KeyState,
+    gestures: &mut GestureRecognizer,
+    keyevent_receiver: &mut UnboundedReceiver<InputEvent>,
+    now: f64,
+) {
     loop {
         match keyevent_receiver.try_next() {
             Ok(None) => break,
             Err(_err) => break,
             Ok(Some(evt)) => match evt {
-                KeyPress::KeyUp(evt) => state.set_released(&evt.code()),
-                KeyPress::KeyDown(evt) => state.set_pressed(&evt.code(), evt),
+                InputEvent::KeyUp(evt) => state.set_released(&evt.code()),
+                InputEvent::KeyDown(evt) => state.set_pressed(&evt.code()),
+                InputEvent::PointerDown(sample) => {
+                    apply_gesture(state, gestures.pointer_down(sample))
+                }
+                InputEvent::PointerMove(sample) => {
+                    apply_gesture(state, gestures.pointer_move(sample))
+                }
+                InputEvent::PointerUp(sample) => apply_gesture(state, gestures.pointer_up(sample)),
+                InputEvent::PointerCancel(id) => apply_gesture(state, gestures.pointer_cancel(id)),
             },
         }
     }
+    apply_gesture(state, gestures.update(now));
 }
 
-#[derive(Clone, Copy)]
+fn apply_gesture(state: &mut KeyState, gesture: Option<Gesture>) {
+    if let Some(gesture) = gesture {
+        gesture.apply(state);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
 }
 
+/// 読み込み済みの画像
+///
+/// 幅と高さは読み込み時に控えておくので、ブラウザの外でも位置の計算ができる。
+/// `element`を持たない画像は[`ImageHandle::headless`]で作られたテスト用のもの。
+#[derive(Clone)]
+pub struct ImageHandle {
+    name: Rc<str>,
+    width: i16,
+    height: i16,
+    element: Option<HtmlImageElement>,
+}
+
+impl ImageHandle {
+    pub fn new(name: &str, element: HtmlImageElement) -> Self {
+        ImageHandle {
+            name: name.into(),
+            width: element.width() as i16,
+            height: element.height() as i16,
+            element: Some(element),
+        }
+    }
+
+    /// ブラウザなしで使う、大きさだけを持った画像
+    #[cfg(test)]
+    pub fn headless(name: &str, width: i16, height: i16) -> Self {
+        ImageHandle {
+            name: name.into(),
+            width,
+            height,
+            element: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn width(&self) -> i16 {
+        self.width
+    }
+
+    pub fn height(&self) -> i16 {
+        self.height
+    }
+
+    fn element(&self) -> Option<&HtmlImageElement> {
+        self.element.as_ref()
+    }
+}
+
 pub struct Image {
-    element: HtmlImageElement,
+    element: ImageHandle,
     position: Point,
 }
 
 impl Image {
-    pub fn new(element: HtmlImageElement, position: Point) -> Self {
+    pub fn new(element: ImageHandle, position: Point) -> Self {
         Self { element, position }
     }
-    pub fn draw(&self, renderer: &Renderer) {
+    pub fn draw(&self, renderer: &dyn Renderer) {
         renderer.draw_entire_image(&self.element, &self.position)
     }
     /// 水平方向に移動させる。
@@ -354,7 +872,7 @@ impl Image {
         self.position.x = x;
     }
     pub fn right(&self) -> i16 {
-        self.position.x + self.element.width() as i16
+        self.position.x + self.element.width()
     }
 }
 
@@ -374,8 +892,8 @@ impl Collider {
         let bounding_box = Rect::new_from_x_y(
             image.position.x,
             image.position.y,
-            image.element.width() as i16,
-            image.element.height() as i16,
+            image.element.width(),
+            image.element.height(),
         );
         Self {
             image,
@@ -383,9 +901,9 @@ impl Collider {
         }
     }
 
-    pub fn draw(&self, renderer: &Renderer) {
+    pub fn draw(&self, renderer: &dyn Renderer, debug: &DebugSettings) {
         self.image.draw(renderer);
-        if cfg!(debug_assertions) {
+        if debug.hitboxes {
             renderer.draw_rect(&self.bounding_box, (255, 0, 0));
         }
     }
@@ -442,35 +960,67 @@ impl Cell {
 
 pub struct SpriteSheet {
     sheet: Sheet,
-    image: HtmlImageElement,
+    image: ImageHandle,
 }
 
 impl SpriteSheet {
-    pub fn new(sheet: Sheet, image: HtmlImageElement) -> Self {
+    pub fn new(sheet: Sheet, image: ImageHandle) -> Self {
         SpriteSheet { sheet, image }
     }
     pub fn cell(&self, name: &str) -> Option<&Cell> {
         self.sheet.frames.get(name)
     }
-    pub fn draw(&self, renderer: &Renderer, source: &Rect, destination: &Rect) {
+    pub fn draw(&self, renderer: &dyn Renderer, source: &Rect, destination: &Rect) {
         renderer.draw_image(&self.image, source, destination);
     }
 }
 
-#[derive(Clone)]
-pub struct Audio {
-    context: AudioContext,
+/// 音声の再生先の抽象
+///
+/// ブラウザでは[`WebAudio`]を使う。
+/// テストでは[`headless::RecordingAudio`]を使い、再生命令の列を確認する。
+pub trait Audio {
+    fn play_sound(&self, sound: &Sound, looping: Looping) -> Result<()>;
+    /// 再生中の`sound`を止める
+    fn stop_sound(&self, sound: &Sound) -> Result<()>;
 }
 
+/// 読み込み済みの音声
+///
+/// `buffer`を持たない音声は[`Sound::headless`]で作られたテスト用のもの。
 #[derive(Clone)]
 pub struct Sound {
-    buffer: AudioBuffer,
+    name: Rc<str>,
+    buffer: Option<AudioBuffer>,
+}
+
+impl Sound {
+    /// ブラウザなしで使う、名前だけを持った音声
+    #[cfg(test)]
+    pub fn headless(name: &str) -> Self {
+        Sound {
+            name: name.into(),
+            buffer: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Web Audio APIで音声を再生する[`Audio`]
+pub struct WebAudio {
+    context: AudioContext,
+    /// 止めるために、再生中の音声のノードを名前で覚えておく
+    playing_nodes: RefCell<HashMap<Rc<str>, AudioBufferSourceNode>>,
 }
 
-impl Audio {
+impl WebAudio {
     pub fn new() -> Result<Self> {
-        Ok(Audio {
+        Ok(WebAudio {
             context: sound::creat_audio_context()?,
+            playing_nodes: RefCell::new(HashMap::new()),
         })
     }
 
@@ -478,12 +1028,61 @@ impl Audio {
         let array_buffer = browser::fetch_array_buffer(filename).await?;
         let audio_buffer = sound::decode_auto_data(&self.context, &array_buffer).await?;
         Ok(Sound {
-            buffer: audio_buffer,
+            name: filename.into(),
+            buffer: Some(audio_buffer),
         })
     }
+}
 
-    pub fn play_sound(&self, sound: &Sound, looping: Looping) -> Result<AudioBufferSourceNode> {
-        sound::play_sound(&self.context, &sound.buffer, looping)
+impl Audio for WebAudio {
+    fn play_sound(&self, sound: &Sound, looping: Looping) -> Result<()> {
+        let Some(buffer) = &sound.buffer else {
+            return Err(anyhow!("Sound {} has no audio buffer", sound.name()));
+        };
+        let node = sound::play_sound(&self.context, buffer, looping)?;
+        self.playing_nodes
+            .borrow_mut()
+            .insert(sound.name.clone(), node);
+        Ok(())
+    }
+
+    fn stop_sound(&self, sound: &Sound) -> Result<()> {
+        if let Some(node) = self.playing_nodes.borrow_mut().remove(sound.name()) {
+            node.stop()
+                .map_err(|err| anyhow!("Could not stop sound {} {:#?}", sound.name(), err))
+        } else {
+            log!("cannot find playing sound {}!", sound.name());
+            Ok(())
+        }
+    }
+}
+
+/// ゲーム画面の上に重ねるHTMLのUIの抽象
+///
+/// ブラウザでは[`HtmlUi`]を使う。
+/// テストでは[`headless::HeadlessUi`]を使い、ボタンのクリックを模倣する。
+pub trait Ui {
+    /// ボタンを表示し、そのクリックを受け取るreceiverを返す。
+    fn show_button(&self, id: &str, label: &str) -> Result<UnboundedReceiver<()>>;
+    /// 表示中のUIを隠す
+    fn hide(&self) -> Result<()>;
+}
+
+/// `ui`要素にHTMLを挿入する[`Ui`]
+pub struct HtmlUi;
+
+impl Ui for HtmlUi {
+    fn show_button(&self, id: &str, label: &str) -> Result<UnboundedReceiver<()>> {
+        browser::draw_ui(&format!(
+            "<button id=\"{}\" type=\"button\">{}</button>",
+            id, label
+        ))
+        .and_then(|_unit| browser::find_html_elemebt_by_id(id))
+        .map(add_click_handler)
+    }
+
+    fn hide(&self) -> Result<()> {
+        browser::hide_ui()
     }
 }
 
@@ -500,7 +1099,9 @@ pub fn add_click_handler(elem: HtmlElement) -> UnboundedReceiver<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn two_rects_that_intersect_on_the_left() {
         let rect1 = Rect {
             position: Point { x: 10, y: 10 },
@@ -517,6 +1118,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn two_rects_that_intersect_on_the_top() {
         let rect1 = Rect {
             position: Point { x: 10, y: 10 },
@@ -533,6 +1135,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn two_rects_that_not_intersect() {
         let rect1 = Rect {
             position: Point { x: 10, y: 10 },
@@ -547,4 +1150,461 @@ mod tests {
 
         assert_eq!(rect2.intersects(&rect1), false);
     }
+
+    #[test]
+    fn a_fast_rect_does_not_pass_through_a_thin_one() {
+        let falling = Rect::new_from_x_y(0, 0, 10, 10);
+        let thin = Rect::new_from_x_y(0, 50, 10, 2);
+
+        assert!(!falling.translate(0, 100).intersects(&thin));
+        assert_eq!(
+            falling.sweep(Point { x: 0, y: 100 }, &thin),
+            Some(Sweep {
+                time: 0.4,
+                normal: Point { x: 0, y: -1 },
+            })
+        );
+    }
+
+    #[test]
+    fn sweeping_into_a_side_reports_the_side() {
+        let running = Rect::new_from_x_y(0, 0, 10, 10);
+        let wall = Rect::new_from_x_y(20, -50, 5, 100);
+
+        let sweep = running.sweep(Point { x: 20, y: 3 }, &wall).unwrap();
+
+        assert_eq!(sweep.time, 0.5);
+        assert_eq!(sweep.normal, Point { x: -1, y: 0 });
+    }
+
+    #[test]
+    fn sweeping_along_an_edge_is_not_a_hit() {
+        let rect = Rect::new_from_x_y(0, 0, 10, 10);
+        let below = Rect::new_from_x_y(0, 10, 100, 10);
+
+        assert_eq!(rect.sweep(Point { x: 50, y: 0 }, &below), None);
+        assert_eq!(
+            rect.sweep(Point { x: 0, y: 0 }, &rect),
+            Some(Sweep {
+                time: 0.0,
+                normal: Point { x: 0, y: 0 },
+            })
+        );
+    }
+
+    #[test]
+    fn overlap_pushes_out_through_the_shallowest_side() {
+        let platform = Rect::new_from_x_y(0, 0, 100, 50);
+        let overlap = |x, y| Rect::new_from_x_y(x, y, 20, 20).overlap(&platform);
+
+        assert_eq!(
+            overlap(40, -15),
+            Some(Overlap {
+                normal: Point { x: 0, y: -1 },
+                depth: 5,
+            })
+        );
+        assert_eq!(
+            overlap(40, 45),
+            Some(Overlap {
+                normal: Point { x: 0, y: 1 },
+                depth: 5,
+            })
+        );
+        assert_eq!(
+            overlap(-17, 10),
+            Some(Overlap {
+                normal: Point { x: -1, y: 0 },
+                depth: 3,
+            })
+        );
+        assert_eq!(
+            overlap(97, 10),
+            Some(Overlap {
+                normal: Point { x: 1, y: 0 },
+                depth: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn overlap_at_a_corner_prefers_pushing_up_or_down() {
+        let platform = Rect::new_from_x_y(0, 0, 100, 50);
+
+        let top_left = Rect::new_from_x_y(-15, -15, 20, 20).overlap(&platform);
+        let bottom_right = Rect::new_from_x_y(95, 45, 20, 20).overlap(&platform);
+
+        assert_eq!(
+            top_left.map(|overlap| overlap.translation()),
+            Some(Point { x: 0, y: -5 })
+        );
+        assert_eq!(
+            bottom_right.map(|overlap| overlap.translation()),
+            Some(Point { x: 0, y: 5 })
+        );
+    }
+
+    #[test]
+    fn rects_touching_at_an_edge_or_a_corner_do_not_overlap() {
+        let platform = Rect::new_from_x_y(0, 0, 100, 50);
+
+        [
+            (-20, 10),
+            (100, 10),
+            (40, -20),
+            (40, 50),
+            (-20, -20),
+            (100, 50),
+        ]
+        .into_iter()
+        .for_each(|(x, y)| {
+            assert_eq!(Rect::new_from_x_y(x, y, 20, 20).overlap(&platform), None);
+        });
+    }
+
+    #[test]
+    fn a_rect_inside_another_is_pushed_out_of_the_nearest_side() {
+        let platform = Rect::new_from_x_y(0, 0, 100, 50);
+
+        let inside = Rect::new_from_x_y(75, 20, 20, 20);
+
+        assert_eq!(
+            inside.overlap(&platform),
+            Some(Overlap {
+                normal: Point { x: 1, y: 0 },
+                depth: 25,
+            })
+        );
+        assert_eq!(
+            platform
+                .overlap(&platform)
+                .map(|overlap| overlap.translation()),
+            Some(Point { x: 0, y: -50 })
+        );
+    }
+
+    #[test]
+    fn penetration_is_measured_towards_each_side() {
+        let rect = Rect::new_from_x_y(10, 20, 30, 40);
+        let other = Rect::new_from_x_y(0, 0, 50, 50);
+
+        assert_eq!(rect.penetration(&other, Point { x: 0, y: -1 }), Some(60));
+        assert_eq!(rect.penetration(&other, Point { x: 0, y: 1 }), Some(30));
+        assert_eq!(rect.penetration(&other, Point { x: -1, y: 0 }), Some(40));
+        assert_eq!(rect.penetration(&other, Point { x: 1, y: 0 }), Some(40));
+    }
+
+    #[test]
+    fn penetration_is_none_for_a_normal_that_is_not_a_side() {
+        let rect = Rect::new_from_x_y(10, 20, 30, 40);
+        let other = Rect::new_from_x_y(0, 0, 50, 50);
+
+        assert_eq!(rect.penetration(&other, Point { x: 0, y: 0 }), None);
+        assert_eq!(rect.penetration(&other, Point { x: 1, y: 1 }), None);
+        assert_eq!(rect.penetration(&other, Point { x: 0, y: -2 }), None);
+    }
+
+    #[test]
+    fn translate_moves_without_resizing() {
+        let rect = Rect::new_from_x_y(0, 0, 100, 50);
+
+        assert_eq!(rect.translate(-5, 7), Rect::new_from_x_y(-5, 7, 100, 50));
+    }
+
+    /// [`first_overlapping_step`]で、1回の動きを分ける数
+    const STEPS: i64 = 20000;
+
+    /// `rect`を`delta`の[`STEPS`]分の1ずつ動かして、`other`と初めて重なった回数
+    fn first_overlapping_step(rect: &Rect, delta: Point, other: &Rect) -> Option<i64> {
+        // 座標をSTEPS倍して、整数のまま比べる
+        let overlaps =
+            |start: i16, size: i16, other_start: i16, other_size: i16, delta: i16, step| {
+                let start = i64::from(start) * STEPS + i64::from(delta) * step;
+                start < (i64::from(other_start) + i64::from(other_size)) * STEPS
+                    && i64::from(other_start) * STEPS < start + i64::from(size) * STEPS
+            };
+        (0..=STEPS).find(|&step| {
+            overlaps(rect.x(), rect.width, other.x(), other.width, delta.x, step)
+                && overlaps(
+                    rect.y(),
+                    rect.height,
+                    other.y(),
+                    other.height,
+                    delta.y,
+                    step,
+                )
+        })
+    }
+
+    fn any_rect() -> impl Strategy<Value = Rect> {
+        (-200i16..200, -200i16..200, 1i16..100, 1i16..100)
+            .prop_map(|(x, y, width, height)| Rect::new_from_x_y(x, y, width, height))
+    }
+
+    proptest! {
+        #[test]
+        fn sweep_finds_the_same_hit_as_small_steps(
+            rect in any_rect(),
+            other in any_rect(),
+            dx in -100i16..100,
+            dy in -100i16..100,
+        ) {
+            let delta = Point { x: dx, y: dy };
+            let sweep = rect.sweep(delta, &other);
+            let step = first_overlapping_step(&rect, delta, &other);
+
+            prop_assert_eq!(sweep.is_some(), step.is_some());
+            if let (Some(sweep), Some(step)) = (sweep, step) {
+                // 細かく動かして見つかるのは、重なり始めてから1歩以内。f32の誤差も許す
+                let error = f64::from(sweep.time) - step as f64 / STEPS as f64;
+                prop_assert!(error.abs() <= 1.0 / STEPS as f64 + 1e-6, "{:?} {}", sweep, step);
+            }
+        }
+    }
+
+    #[test]
+    fn key_press_is_reported_for_one_tick_only() {
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed("ArrowUp");
+        assert!(key_state.is_pressed("ArrowUp"));
+        assert!(key_state.is_just_pressed("ArrowUp"));
+
+        key_state.tick();
+        assert!(key_state.is_pressed("ArrowUp"));
+        assert!(!key_state.is_just_pressed("ArrowUp"));
+        assert_eq!(key_state.held_ticks("ArrowUp"), 1);
+    }
+
+    #[test]
+    fn repeated_keydown_does_not_press_again() {
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowUp");
+        key_state.tick();
+        key_state.tick();
+
+        key_state.set_pressed("ArrowUp");
+
+        assert!(!key_state.is_just_pressed("ArrowUp"));
+        assert_eq!(key_state.held_ticks("ArrowUp"), 2);
+    }
+
+    #[test]
+    fn press_and_release_between_ticks_is_not_lost() {
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed("Enter");
+        key_state.set_released("Enter");
+
+        assert!(!key_state.is_pressed("Enter"));
+        assert!(key_state.is_just_pressed("Enter"));
+        assert!(key_state.is_just_released("Enter"));
+
+        key_state.tick();
+        assert!(!key_state.is_just_pressed("Enter"));
+        assert!(!key_state.is_just_released("Enter"));
+    }
+
+    #[test]
+    fn releasing_an_unpressed_key_is_ignored() {
+        let mut key_state = KeyState::new();
+
+        key_state.set_released("Enter");
+
+        assert!(!key_state.is_just_released("Enter"));
+        assert_eq!(key_state.held_ticks("Enter"), 0);
+    }
+
+    #[test]
+    fn image_is_drawn_at_its_position() {
+        let renderer = headless::RecordingRenderer::new();
+        let mut image = Image::new(
+            ImageHandle::headless("Stone.png", 90, 54),
+            Point { x: 10, y: 20 },
+        );
+        image.move_horisontally(-5);
+
+        image.draw(&renderer);
+
+        assert_eq!(
+            renderer.commands(),
+            vec![headless::DrawCommand::DrawEntireImage {
+                image: "Stone.png".into(),
+                position: Point { x: 5, y: 20 },
+            }]
+        );
+        assert_eq!(image.right(), 95);
+    }
+
+    #[test]
+    fn collider_bounding_box_follows_image_size() {
+        let mut collider = Collider::new(Image::new(
+            ImageHandle::headless("Stone.png", 90, 54),
+            Point { x: 100, y: 546 },
+        ));
+
+        collider.move_horisontally(-3);
+
+        assert_eq!(
+            collider.bounding_box(),
+            &Rect::new_from_x_y(97, 546, 90, 54)
+        );
+    }
+
+    #[test]
+    fn translated_renderer_shifts_every_position() {
+        let renderer = headless::RecordingRenderer::new();
+        let translated = TranslatedRenderer::new(&renderer, Point { x: 3, y: -2 });
+
+        translated.draw_entire_image(
+            &ImageHandle::headless("Stone.png", 90, 54),
+            &Point { x: 10, y: 20 },
+        );
+        translated.draw_rect(&Rect::new_from_x_y(0, 0, 5, 5), (255, 0, 0));
+        translated.draw_line(&Point { x: 0, y: 0 }, &Point { x: 4, y: 4 }, (0, 255, 0));
+
+        assert_eq!(
+            renderer.commands(),
+            vec![
+                headless::DrawCommand::DrawEntireImage {
+                    image: "Stone.png".into(),
+                    position: Point { x: 13, y: 18 },
+                },
+                headless::DrawCommand::DrawRect {
+                    rect: Rect::new_from_x_y(3, -2, 5, 5),
+                    color: (255, 0, 0),
+                },
+                headless::DrawCommand::DrawLine {
+                    from: Point { x: 3, y: -2 },
+                    to: Point { x: 7, y: 2 },
+                    color: (0, 255, 0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn game_loop_updates_once_per_elapsed_frame() {
+        let mut game_loop = GameLoop::new(0.0);
+
+        assert_eq!(game_loop.advance(10.0), 0);
+        assert_eq!(game_loop.advance(40.0), 2);
+        assert!((game_loop.alpha() - (40.0 - 2.0 * FRAME_SIZE) / FRAME_SIZE).abs() < 1e-4);
+    }
+
+    #[test]
+    fn game_loop_drops_time_it_can_not_catch_up_with() {
+        let mut game_loop = GameLoop::new(0.0);
+
+        assert_eq!(game_loop.advance(60_000.0), MAX_UPDATES_PER_FRAME);
+        assert_eq!(game_loop.advance(60_000.0 + FRAME_SIZE as f64), 1);
+    }
+
+    #[test]
+    fn resumed_game_loop_ignores_the_paused_time() {
+        let mut game_loop = GameLoop::new(0.0);
+        game_loop.advance(10.0);
+
+        game_loop.resume(60_000.0);
+
+        assert_eq!(game_loop.alpha(), 0.0);
+        assert_eq!(game_loop.advance(60_010.0), 0);
+    }
+
+    /// 更新の回数を数え、指定された回数目の更新でヒットストップを始めるゲーム
+    #[derive(Default)]
+    struct CountingGame {
+        updates: u32,
+        hit_stop_at: Option<u32>,
+    }
+
+    #[async_trait(?Send)]
+    impl Game for CountingGame {
+        async fn intialize(&self) -> Result<Box<dyn Game>> {
+            Ok(Box::<CountingGame>::default())
+        }
+
+        fn update(&mut self, _key_state: &KeyState, time: &mut TimeControl) {
+            self.updates += 1;
+            if self.hit_stop_at == Some(self.updates) {
+                time.hit_stop(100.0);
+            }
+        }
+
+        fn update_time(&mut self, key_state: &KeyState, time: &mut TimeControl) {
+            if key_state.is_just_pressed("KeyP") {
+                time.toggle_pause();
+            }
+        }
+
+        fn draw(&self, _renderer: &dyn Renderer, _alpha: f32, _debug: &DebugSettings) {}
+    }
+
+    #[test]
+    fn slowed_game_loop_updates_less_often() {
+        let mut game_loop = GameLoop::new(0.0);
+        game_loop.time.set_scale(0.5);
+
+        assert_eq!(game_loop.advance(FRAME_SIZE as f64 * 4.0 + 1.0), 2);
+    }
+
+    #[test]
+    fn paused_game_loop_only_runs_the_requested_steps() {
+        let mut game_loop = GameLoop::new(0.0);
+        let mut game = CountingGame::default();
+        let mut key_state = KeyState::new();
+        game_loop.time.pause();
+
+        game_loop.run_frame(1000.0, &mut game, &mut key_state);
+        assert_eq!(game.updates, 0);
+
+        game_loop.time.step();
+        game_loop.run_frame(1100.0, &mut game, &mut key_state);
+        game_loop.run_frame(1200.0, &mut game, &mut key_state);
+        assert_eq!(game.updates, 1);
+
+        game_loop.time.resume();
+        game_loop.run_frame(1200.0 + FRAME_SIZE as f64 + 1.0, &mut game, &mut key_state);
+        assert_eq!(game.updates, 2);
+    }
+
+    #[test]
+    fn game_can_resume_from_a_pause_it_started() {
+        let mut game_loop = GameLoop::new(0.0);
+        let mut game = CountingGame::default();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("KeyP");
+        key_state.set_released("KeyP");
+
+        game_loop.run_frame(1000.0, &mut game, &mut key_state);
+        game_loop.run_frame(2000.0, &mut game, &mut key_state);
+        assert!(game_loop.time.is_paused());
+        assert!(!key_state.is_just_pressed("KeyP"));
+
+        key_state.set_pressed("KeyP");
+        game_loop.run_frame(3000.0, &mut game, &mut key_state);
+        assert!(!game_loop.time.is_paused());
+    }
+
+    #[test]
+    fn hit_stop_skips_the_rest_of_the_frame_and_freezes() {
+        let mut game_loop = GameLoop::new(0.0);
+        let mut game = CountingGame {
+            updates: 0,
+            hit_stop_at: Some(2),
+        };
+        let mut key_state = KeyState::new();
+
+        assert_eq!(
+            game_loop.run_frame(FRAME_SIZE as f64 * 4.0 + 1.0, &mut game, &mut key_state),
+            2
+        );
+
+        game_loop.run_frame(FRAME_SIZE as f64 * 8.0, &mut game, &mut key_state);
+        assert_eq!(game.updates, 2);
+
+        game_loop.run_frame(200.0, &mut game, &mut key_state);
+        game_loop.run_frame(200.0 + FRAME_SIZE as f64, &mut game, &mut key_state);
+        assert_eq!(game.updates, 3);
+    }
 }
@@ -0,0 +1,117 @@
+//! 開発用の表示の設定
+//!
+//! [`super::GameLoop`]が[`DebugSettings`]を持ち、ゲームの描画に渡す。
+//! どの表示も最初は消えていて、ビルドの種類に関係なくホットキーで切り替える。
+
+use std::collections::HashSet;
+
+use super::KeyState;
+
+/// 当たり判定の矩形
+pub const HITBOXES_KEY: &str = "Digit1";
+/// 速度のベクトル
+pub const VELOCITIES_KEY: &str = "Digit2";
+/// RedHatBoyの状態の名前
+pub const STATE_NAMES_KEY: &str = "Digit3";
+/// 障害物の右端と、次の障害物を置く位置
+pub const MARKERS_KEY: &str = "Digit4";
+/// 障害物の組の境目
+pub const SEGMENT_BOUNDARIES_KEY: &str = "Digit5";
+
+/// 開発用の表示のうち、どれを描くか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DebugSettings {
+    pub hitboxes: bool,
+    pub velocities: bool,
+    pub state_names: bool,
+    pub markers: bool,
+    pub segment_boundaries: bool,
+}
+
+impl DebugSettings {
+    /// 押されたホットキーに対応する表示を切り替える
+    pub fn handle_hotkeys(&mut self, hot_keys: &mut HotKeys, key_state: &KeyState) {
+        [
+            (HITBOXES_KEY, &mut self.hitboxes),
+            (VELOCITIES_KEY, &mut self.velocities),
+            (STATE_NAMES_KEY, &mut self.state_names),
+            (MARKERS_KEY, &mut self.markers),
+            (SEGMENT_BOUNDARIES_KEY, &mut self.segment_boundaries),
+        ]
+        .into_iter()
+        .for_each(|(code, enabled)| {
+            if hot_keys.pressed(key_state, code) {
+                *enabled = !*enabled;
+            }
+        });
+    }
+}
+
+/// 開発用のキーが新しく押されたかを調べる
+///
+/// 一時停止中はtickが進まず[`KeyState`]の押された瞬間が消えないので、
+/// キーが押されたかどうかはフレームごとに自分で覚えておく。
+#[derive(Default)]
+pub struct HotKeys {
+    held: HashSet<&'static str>,
+}
+
+impl HotKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 前のフレームから新しく押されたか
+    pub fn pressed(&mut self, key_state: &KeyState, code: &'static str) -> bool {
+        if key_state.is_pressed(code) || key_state.is_just_pressed(code) {
+            self.held.insert(code)
+        } else {
+            self.held.remove(code);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_is_hidden_by_default() {
+        assert_eq!(
+            DebugSettings::default(),
+            DebugSettings {
+                hitboxes: false,
+                velocities: false,
+                state_names: false,
+                markers: false,
+                segment_boundaries: false,
+            }
+        );
+    }
+
+    #[test]
+    fn hotkeys_toggle_their_own_setting_once_per_press() {
+        let mut settings = DebugSettings::default();
+        let mut hot_keys = HotKeys::new();
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed(HITBOXES_KEY);
+        key_state.set_pressed(MARKERS_KEY);
+        settings.handle_hotkeys(&mut hot_keys, &key_state);
+        key_state.tick();
+        settings.handle_hotkeys(&mut hot_keys, &key_state);
+
+        assert!(settings.hitboxes);
+        assert!(settings.markers);
+        assert!(!settings.velocities);
+
+        key_state.set_released(HITBOXES_KEY);
+        settings.handle_hotkeys(&mut hot_keys, &key_state);
+        key_state.set_pressed(HITBOXES_KEY);
+        settings.handle_hotkeys(&mut hot_keys, &key_state);
+
+        assert!(!settings.hitboxes);
+        assert!(settings.markers);
+    }
+}
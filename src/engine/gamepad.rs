@@ -0,0 +1,366 @@
+//! ゲームパッドの入力
+//!
+//! ゲームパッドのボタンとスティックを仮想的なキーコードに変換し、キーボードと同じ[`KeyState`]に流し込む。
+//! ボタン`n`は`GamepadButton{n}`、スティックの軸`n`を負の方向に倒すと`GamepadAxis{n}-`、
+//! 正の方向に倒すと`GamepadAxis{n}+`というキーになる。
+//! そのためゲームパッドの操作もキーボードと同じように割り当てを変えられる。
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use wasm_bindgen::JsCast;
+use web_sys::GamepadButton;
+
+use super::KeyState;
+use crate::browser;
+
+/// スティックをどれだけ倒せば押されたことにするかの既定値
+pub const DEFAULT_DEADZONE: f64 = 0.4;
+
+const DEADZONE_STORAGE_KEY: &str = "gamepad-deadzone";
+
+/// 設定されたスティックの遊びを読む。
+///
+/// URLのクエリに`?deadzone=0.3`のように指定されていれば、それを保存して使う。
+/// 指定されていなければ前に保存した値を使い、どちらもなければ`None`を返す。
+pub fn configured_deadzone() -> Result<Option<f64>> {
+    match browser::query_param("deadzone")? {
+        Some(value) => {
+            let deadzone = parse_deadzone(&value)?;
+            browser::save_item(DEADZONE_STORAGE_KEY, &value)?;
+            Ok(Some(deadzone))
+        }
+        None => browser::load_item(DEADZONE_STORAGE_KEY)?
+            .map(|value| parse_deadzone(&value))
+            .transpose(),
+    }
+}
+
+/// 0.0から1.0までの数をスティックの遊びとして読む
+fn parse_deadzone(value: &str) -> Result<f64> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|deadzone| (0.0..=1.0).contains(deadzone))
+        .ok_or_else(|| anyhow!("Invalid deadzone {} (expected 0.0 to 1.0)", value))
+}
+
+/// ある瞬間のゲームパッド一つの状態
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GamepadSnapshot {
+    /// ゲームパッドを区別する番号
+    pub index: u32,
+    /// ボタンごとの押されているかどうか
+    pub buttons: Vec<bool>,
+    /// スティックの軸ごとの傾き。-1.0から1.0まで
+    pub axes: Vec<f64>,
+}
+
+/// ゲームパッドの状態の取得元
+///
+/// ブラウザでは[`BrowserGamepads`]を使う。
+/// テストでは作った状態をそのまま返すものを使う。
+pub trait GamepadSource {
+    /// 今接続されているゲームパッドの状態
+    fn poll(&mut self) -> Result<Vec<GamepadSnapshot>>;
+}
+
+/// Gamepad APIからゲームパッドの状態を取得する[`GamepadSource`]
+pub struct BrowserGamepads;
+
+impl GamepadSource for BrowserGamepads {
+    fn poll(&mut self) -> Result<Vec<GamepadSnapshot>> {
+        Ok(browser::gamepads()?
+            .iter()
+            .map(|gamepad| GamepadSnapshot {
+                index: gamepad.index(),
+                buttons: gamepad
+                    .buttons()
+                    .iter()
+                    .map(|button| {
+                        button
+                            .dyn_into::<GamepadButton>()
+                            .map(|button| button.pressed())
+                            .unwrap_or(false)
+                    })
+                    .collect(),
+                axes: gamepad
+                    .axes()
+                    .iter()
+                    .map(|axis| axis.as_f64().unwrap_or(0.0))
+                    .collect(),
+            })
+            .collect())
+    }
+}
+
+/// ゲームパッドの抜き差し
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadConnection {
+    Connected(u32),
+    Disconnected(u32),
+}
+
+/// ゲームパッドの状態を毎フレーム[`KeyState`]に反映する。
+pub struct GamepadInput {
+    deadzone: f64,
+    /// 前回の反映で押されていたキーを、ゲームパッドごとに覚えておく
+    pressed_codes: BTreeMap<u32, HashSet<String>>,
+}
+
+impl GamepadInput {
+    pub fn new() -> Self {
+        GamepadInput {
+            deadzone: DEFAULT_DEADZONE,
+            pressed_codes: BTreeMap::new(),
+        }
+    }
+
+    /// スティックの傾きがこの値以下なら押されていないとみなす。
+    pub fn set_deadzone(&mut self, deadzone: f64) {
+        self.deadzone = deadzone.clamp(0.0, 1.0);
+    }
+
+    /// 接続されているゲームパッドの番号
+    pub fn connected(&self) -> Vec<u32> {
+        self.pressed_codes.keys().copied().collect()
+    }
+
+    /// ゲームパッドの状態を取得して、前回との差分を`key_state`に押下と解放として伝える。
+    ///
+    /// 新しく繋がったゲームパッドは追加し、切断されたゲームパッドのキーはすべて離されたことにする。
+    /// 前回からの抜き差しを返す。
+    pub fn update(
+        &mut self,
+        source: &mut dyn GamepadSource,
+        key_state: &mut KeyState,
+    ) -> Result<Vec<GamepadConnection>> {
+        let pressed_codes: BTreeMap<u32, HashSet<String>> = source
+            .poll()?
+            .iter()
+            .map(|snapshot| (snapshot.index, self.codes(snapshot)))
+            .collect();
+        self.pressed_codes
+            .values()
+            .flatten()
+            .filter(|&code| !Self::is_held(&pressed_codes, code))
+            .for_each(|code| key_state.set_released(code));
+        pressed_codes
+            .values()
+            .flatten()
+            .filter(|&code| !Self::is_held(&self.pressed_codes, code))
+            .for_each(|code| key_state.set_pressed(code));
+        let disconnected = self
+            .pressed_codes
+            .keys()
+            .filter(|index| !pressed_codes.contains_key(index))
+            .map(|&index| GamepadConnection::Disconnected(index));
+        let connected = pressed_codes
+            .keys()
+            .filter(|index| !self.pressed_codes.contains_key(index))
+            .map(|&index| GamepadConnection::Connected(index));
+        let connections = disconnected.chain(connected).collect();
+        self.pressed_codes = pressed_codes;
+        Ok(connections)
+    }
+
+    /// どれか一つのゲームパッドで`code`が押されているか
+    fn is_held(pressed_codes: &BTreeMap<u32, HashSet<String>>, code: &str) -> bool {
+        pressed_codes.values().any(|codes| codes.contains(code))
+    }
+
+    /// ゲームパッドの状態を押されている仮想キーコードに変換する
+    fn codes(&self, snapshot: &GamepadSnapshot) -> HashSet<String> {
+        let buttons = snapshot
+            .buttons
+            .iter()
+            .enumerate()
+            .filter(|(_, &pressed)| pressed)
+            .map(|(button, _)| format!("GamepadButton{}", button));
+        let axes = snapshot
+            .axes
+            .iter()
+            .enumerate()
+            .filter_map(|(axis, &value)| {
+                if value < -self.deadzone {
+                    Some(format!("GamepadAxis{}-", axis))
+                } else if value > self.deadzone {
+                    Some(format!("GamepadAxis{}+", axis))
+                } else {
+                    None
+                }
+            });
+        buttons.chain(axes).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 決められた状態を順番に返すゲームパッド
+    struct FakeGamepads {
+        snapshots: Vec<GamepadSnapshot>,
+    }
+
+    impl GamepadSource for FakeGamepads {
+        fn poll(&mut self) -> Result<Vec<GamepadSnapshot>> {
+            Ok(self.snapshots.clone())
+        }
+    }
+
+    fn pad(index: u32, buttons: &[usize], axes: &[f64]) -> GamepadSnapshot {
+        let mut pressed = vec![false; 16];
+        buttons.iter().for_each(|&button| pressed[button] = true);
+        GamepadSnapshot {
+            index,
+            buttons: pressed,
+            axes: axes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn pressed_button_becomes_a_key_press() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[0], &[0.0, 0.0])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        let mut key_state = KeyState::new();
+
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+
+        assert!(key_state.is_just_pressed("GamepadButton0"));
+        assert!(!key_state.is_pressed("GamepadButton1"));
+    }
+
+    #[test]
+    fn held_button_is_not_pressed_again_on_the_next_poll() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[0], &[])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        let mut key_state = KeyState::new();
+
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+        key_state.tick();
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+
+        assert!(key_state.is_pressed("GamepadButton0"));
+        assert!(!key_state.is_just_pressed("GamepadButton0"));
+    }
+
+    #[test]
+    fn released_button_becomes_a_key_release() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[13], &[])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        let mut key_state = KeyState::new();
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+        key_state.tick();
+
+        gamepads.snapshots = vec![pad(0, &[], &[])];
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+
+        assert!(!key_state.is_pressed("GamepadButton13"));
+        assert!(key_state.is_just_released("GamepadButton13"));
+    }
+
+    #[test]
+    fn stick_inside_the_deadzone_is_ignored() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[], &[0.3, -0.39])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        let mut key_state = KeyState::new();
+
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+
+        assert!(!key_state.is_pressed("GamepadAxis0+"));
+        assert!(!key_state.is_pressed("GamepadAxis1-"));
+    }
+
+    #[test]
+    fn stick_outside_the_deadzone_is_a_direction_key() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[], &[0.0, -0.9, 0.8])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        let mut key_state = KeyState::new();
+
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+
+        assert!(key_state.is_pressed("GamepadAxis1-"));
+        assert!(key_state.is_pressed("GamepadAxis2+"));
+        assert!(!key_state.is_pressed("GamepadAxis1+"));
+    }
+
+    #[test]
+    fn deadzone_can_be_changed() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[], &[0.3])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        gamepad_input.set_deadzone(0.2);
+        let mut key_state = KeyState::new();
+
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+
+        assert!(key_state.is_pressed("GamepadAxis0+"));
+    }
+
+    #[test]
+    fn deadzone_must_be_between_zero_and_one() {
+        assert_eq!(parse_deadzone("0.25").unwrap(), 0.25);
+        assert_eq!(parse_deadzone("1").unwrap(), 1.0);
+        assert!(parse_deadzone("-0.1").is_err());
+        assert!(parse_deadzone("1.5").is_err());
+        assert!(parse_deadzone("NaN").is_err());
+        assert!(parse_deadzone("half").is_err());
+    }
+
+    #[test]
+    fn disconnecting_a_gamepad_releases_its_keys() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[0], &[]), pad(1, &[1], &[])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        let mut key_state = KeyState::new();
+        let connections = gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+        assert_eq!(
+            connections,
+            vec![
+                GamepadConnection::Connected(0),
+                GamepadConnection::Connected(1)
+            ]
+        );
+        key_state.tick();
+
+        gamepads.snapshots = vec![pad(1, &[1], &[])];
+        let connections = gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+
+        assert_eq!(connections, vec![GamepadConnection::Disconnected(0)]);
+        assert_eq!(gamepad_input.connected(), vec![1]);
+        assert!(key_state.is_just_released("GamepadButton0"));
+        assert!(key_state.is_pressed("GamepadButton1"));
+    }
+
+    #[test]
+    fn same_button_on_two_gamepads_is_released_when_both_let_go() {
+        let mut gamepads = FakeGamepads {
+            snapshots: vec![pad(0, &[0], &[]), pad(1, &[0], &[])],
+        };
+        let mut gamepad_input = GamepadInput::new();
+        let mut key_state = KeyState::new();
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+        key_state.tick();
+
+        gamepads.snapshots = vec![pad(0, &[], &[]), pad(1, &[0], &[])];
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+        assert!(key_state.is_pressed("GamepadButton0"));
+
+        gamepads.snapshots = vec![pad(0, &[], &[]), pad(1, &[], &[])];
+        gamepad_input.update(&mut gamepads, &mut key_state).unwrap();
+        assert!(!key_state.is_pressed("GamepadButton0"));
+    }
+}
@@ -0,0 +1,319 @@
+//! タッチやマウスによるポインタ操作の入力
+//!
+//! ポインタの動きをタップ、スワイプ、長押しというジェスチャーとして認識し、
+//! 仮想的なキーコードとして[`KeyState`]に流し込む。
+//! そのためジェスチャーもキーボードと同じように操作を割り当てられる。
+
+use super::KeyState;
+
+/// タップとみなす、押してから離すまでの最大の移動距離
+pub const TAP_MAX_DISTANCE: f64 = 10.0;
+/// タップとみなす、押してから離すまでの最長のミリ秒
+pub const TAP_MAX_DURATION: f64 = 250.0;
+/// スワイプとみなす最小の移動距離
+pub const SWIPE_MIN_DISTANCE: f64 = 30.0;
+/// 長押しとみなす、動かさずに押し続けるミリ秒
+pub const HOLD_MIN_DURATION: f64 = 400.0;
+
+/// ポインタの位置と時刻
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointerSample {
+    /// 同時に触れている指を区別する番号
+    pub id: i32,
+    pub x: f64,
+    pub y: f64,
+    /// ミリ秒
+    pub time: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap,
+    Swipe(Direction),
+    HoldStart,
+    HoldEnd,
+}
+
+impl Gesture {
+    /// ジェスチャーに対応する仮想的なキーコード
+    pub fn code(&self) -> &'static str {
+        match self {
+            Gesture::Tap => "TouchTap",
+            Gesture::Swipe(Direction::Up) => "TouchSwipeUp",
+            Gesture::Swipe(Direction::Down) => "TouchSwipeDown",
+            Gesture::Swipe(Direction::Left) => "TouchSwipeLeft",
+            Gesture::Swipe(Direction::Right) => "TouchSwipeRight",
+            Gesture::HoldStart | Gesture::HoldEnd => "TouchHold",
+        }
+    }
+
+    /// ジェスチャーをキーの押下と解放として`key_state`に伝える。
+    ///
+    /// タップとスワイプは一瞬で終わるので、押してすぐ離したことにする。
+    /// 長押しは終わるまで押され続ける。
+    pub fn apply(&self, key_state: &mut KeyState) {
+        match self {
+            Gesture::Tap | Gesture::Swipe(_) => {
+                key_state.set_pressed(self.code());
+                key_state.set_released(self.code());
+            }
+            Gesture::HoldStart => key_state.set_pressed(self.code()),
+            Gesture::HoldEnd => key_state.set_released(self.code()),
+        }
+    }
+}
+
+struct Touch {
+    start: PointerSample,
+    holding: bool,
+    /// スワイプを認識済みで、離しても何も起きない
+    finished: bool,
+}
+
+/// ポインタの動きからジェスチャーを認識する。
+///
+/// 最初に触れた指だけを追いかけ、それ以外の指は無視する。
+#[derive(Default)]
+pub struct GestureRecognizer {
+    touch: Option<Touch>,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pointer_down(&mut self, sample: PointerSample) -> Option<Gesture> {
+        if self.touch.is_none() {
+            self.touch = Some(Touch {
+                start: sample,
+                holding: false,
+                finished: false,
+            });
+        }
+        None
+    }
+
+    /// 十分に動いたら、離すのを待たずにスワイプとする。
+    pub fn pointer_move(&mut self, sample: PointerSample) -> Option<Gesture> {
+        let touch = self
+            .touch
+            .as_mut()
+            .filter(|touch| touch.start.id == sample.id)?;
+        if touch.holding || touch.finished {
+            return None;
+        }
+        let direction = swipe_direction(&touch.start, &sample)?;
+        touch.finished = true;
+        Some(Gesture::Swipe(direction))
+    }
+
+    pub fn pointer_up(&mut self, sample: PointerSample) -> Option<Gesture> {
+        if self.touch.as_ref()?.start.id != sample.id {
+            return None;
+        }
+        let touch = self.touch.take()?;
+        if touch.holding {
+            Some(Gesture::HoldEnd)
+        } else if touch.finished {
+            None
+        } else if distance(&touch.start, &sample) <= TAP_MAX_DISTANCE
+            && sample.time - touch.start.time <= TAP_MAX_DURATION
+        {
+            Some(Gesture::Tap)
+        } else {
+            // 移動のイベントが届く前に離された素早いスワイプ
+            swipe_direction(&touch.start, &sample).map(Gesture::Swipe)
+        }
+    }
+
+    /// ブラウザがポインタを奪った場合。ジェスチャーは成立しない。
+    pub fn pointer_cancel(&mut self, id: i32) -> Option<Gesture> {
+        if self.touch.as_ref()?.start.id != id {
+            return None;
+        }
+        let touch = self.touch.take()?;
+        touch.holding.then_some(Gesture::HoldEnd)
+    }
+
+    /// 時間の経過を伝える。動かさずに押し続けていれば長押しが始まる。
+    pub fn update(&mut self, now: f64) -> Option<Gesture> {
+        let touch = self.touch.as_mut()?;
+        if touch.holding || touch.finished || now - touch.start.time < HOLD_MIN_DURATION {
+            return None;
+        }
+        touch.holding = true;
+        Some(Gesture::HoldStart)
+    }
+}
+
+fn distance(from: &PointerSample, to: &PointerSample) -> f64 {
+    (to.x - from.x).hypot(to.y - from.y)
+}
+
+/// 十分に動いていれば、主に動いた方向を返す。
+fn swipe_direction(from: &PointerSample, to: &PointerSample) -> Option<Direction> {
+    if distance(from, to) < SWIPE_MIN_DISTANCE {
+        return None;
+    }
+    let dx = to.x - from.x;
+    let dy = to.y - from.y;
+    Some(if dx.abs() > dy.abs() {
+        if dx > 0.0 {
+            Direction::Right
+        } else {
+            Direction::Left
+        }
+    } else if dy > 0.0 {
+        Direction::Down
+    } else {
+        Direction::Up
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(x: f64, y: f64, time: f64) -> PointerSample {
+        PointerSample { id: 1, x, y, time }
+    }
+
+    /// ポインタの軌跡を押す、動かす…、離すとして流し込み、認識されたジェスチャーを返す。
+    fn recognize(trace: &[PointerSample]) -> Vec<Gesture> {
+        let mut recognizer = GestureRecognizer::new();
+        let (first, rest) = trace.split_first().unwrap();
+        let (last, moves) = rest.split_last().unwrap();
+        let mut gestures: Vec<Gesture> = recognizer.pointer_down(*first).into_iter().collect();
+        moves.iter().for_each(|&sample| {
+            gestures.extend(recognizer.update(sample.time));
+            gestures.extend(recognizer.pointer_move(sample));
+        });
+        gestures.extend(recognizer.update(last.time));
+        gestures.extend(recognizer.pointer_up(*last));
+        gestures
+    }
+
+    #[test]
+    fn short_press_without_moving_is_a_tap() {
+        let gestures = recognize(&[sample(100.0, 100.0, 0.0), sample(103.0, 98.0, 120.0)]);
+
+        assert_eq!(gestures, vec![Gesture::Tap]);
+    }
+
+    #[test]
+    fn press_that_is_too_long_for_a_tap_is_a_hold() {
+        let gestures = recognize(&[
+            sample(100.0, 100.0, 0.0),
+            sample(101.0, 100.0, 300.0),
+            sample(101.0, 101.0, 450.0),
+            sample(101.0, 101.0, 900.0),
+        ]);
+
+        assert_eq!(gestures, vec![Gesture::HoldStart, Gesture::HoldEnd]);
+    }
+
+    #[test]
+    fn slightly_long_press_is_neither_tap_nor_hold() {
+        let gestures = recognize(&[sample(100.0, 100.0, 0.0), sample(100.0, 100.0, 300.0)]);
+
+        assert!(gestures.is_empty());
+    }
+
+    #[test]
+    fn dragging_down_is_a_swipe_down_before_release() {
+        let mut recognizer = GestureRecognizer::new();
+        recognizer.pointer_down(sample(100.0, 100.0, 0.0));
+
+        assert_eq!(recognizer.pointer_move(sample(102.0, 115.0, 30.0)), None);
+        assert_eq!(
+            recognizer.pointer_move(sample(104.0, 140.0, 60.0)),
+            Some(Gesture::Swipe(Direction::Down))
+        );
+        assert_eq!(recognizer.pointer_move(sample(104.0, 200.0, 90.0)), None);
+        assert_eq!(recognizer.pointer_up(sample(104.0, 220.0, 120.0)), None);
+    }
+
+    #[test]
+    fn swipe_direction_follows_the_dominant_axis() {
+        assert_eq!(
+            recognize(&[sample(100.0, 100.0, 0.0), sample(60.0, 120.0, 80.0)]),
+            vec![Gesture::Swipe(Direction::Left)]
+        );
+        assert_eq!(
+            recognize(&[sample(100.0, 100.0, 0.0), sample(110.0, 40.0, 80.0)]),
+            vec![Gesture::Swipe(Direction::Up)]
+        );
+        assert_eq!(
+            recognize(&[sample(100.0, 100.0, 0.0), sample(150.0, 100.0, 80.0)]),
+            vec![Gesture::Swipe(Direction::Right)]
+        );
+    }
+
+    #[test]
+    fn second_finger_is_ignored() {
+        let mut recognizer = GestureRecognizer::new();
+        recognizer.pointer_down(sample(100.0, 100.0, 0.0));
+        recognizer.pointer_down(PointerSample {
+            id: 2,
+            x: 300.0,
+            y: 300.0,
+            time: 10.0,
+        });
+
+        assert_eq!(
+            recognizer.pointer_up(PointerSample {
+                id: 2,
+                x: 300.0,
+                y: 400.0,
+                time: 50.0,
+            }),
+            None
+        );
+        assert_eq!(
+            recognizer.pointer_up(sample(100.0, 100.0, 60.0)),
+            Some(Gesture::Tap)
+        );
+    }
+
+    #[test]
+    fn cancelled_hold_ends_the_hold() {
+        let mut recognizer = GestureRecognizer::new();
+        recognizer.pointer_down(sample(100.0, 100.0, 0.0));
+
+        assert_eq!(recognizer.update(500.0), Some(Gesture::HoldStart));
+        assert_eq!(recognizer.pointer_cancel(1), Some(Gesture::HoldEnd));
+        assert_eq!(recognizer.update(1000.0), None);
+    }
+
+    #[test]
+    fn tap_is_pressed_and_released_within_one_tick() {
+        let mut key_state = KeyState::new();
+
+        Gesture::Tap.apply(&mut key_state);
+
+        assert!(key_state.is_just_pressed("TouchTap"));
+        assert!(!key_state.is_pressed("TouchTap"));
+    }
+
+    #[test]
+    fn hold_is_pressed_until_it_ends() {
+        let mut key_state = KeyState::new();
+
+        Gesture::HoldStart.apply(&mut key_state);
+        key_state.tick();
+        assert!(key_state.is_pressed("TouchHold"));
+
+        Gesture::HoldEnd.apply(&mut key_state);
+        assert!(!key_state.is_pressed("TouchHold"));
+        assert!(key_state.is_just_released("TouchHold"));
+    }
+}
@@ -0,0 +1,193 @@
+//! ブラウザなしで動くバックエンド
+//!
+//! 描画などの命令を実行する代わりに記録する。
+//! `cargo test`でゲームの振る舞いを確かめるために使う。
+
+use std::cell::RefCell;
+
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+
+use super::{Audio, ImageHandle, Point, Rect, Renderer, Sound, Ui};
+use crate::sound::Looping;
+
+/// [`RecordingRenderer`]が記録する描画命令
+///
+/// 画像は[`ImageHandle::name`]で識別する。
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCommand {
+    Clear(Rect),
+    DrawImage {
+        image: String,
+        frame: Rect,
+        destination: Rect,
+    },
+    DrawEntireImage {
+        image: String,
+        position: Point,
+    },
+    DrawRect {
+        rect: Rect,
+        color: (u8, u8, u8),
+    },
+    DrawLine {
+        from: Point,
+        to: Point,
+        color: (u8, u8, u8),
+    },
+    DrawText {
+        text: String,
+        location: Point,
+    },
+}
+
+/// 描画命令を順番に記録するだけの[`Renderer`]
+#[derive(Default)]
+pub struct RecordingRenderer {
+    commands: RefCell<Vec<DrawCommand>>,
+}
+
+impl RecordingRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// これまでに記録した描画命令
+    pub fn commands(&self) -> Vec<DrawCommand> {
+        self.commands.borrow().clone()
+    }
+
+    fn record(&self, command: DrawCommand) {
+        self.commands.borrow_mut().push(command);
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    fn clear(&self, rect: &Rect) {
+        self.record(DrawCommand::Clear(*rect));
+    }
+
+    fn draw_image(&self, image: &ImageHandle, frame: &Rect, destination: &Rect) {
+        self.record(DrawCommand::DrawImage {
+            image: image.name().into(),
+            frame: *frame,
+            destination: *destination,
+        });
+    }
+
+    fn draw_entire_image(&self, image: &ImageHandle, position: &Point) {
+        self.record(DrawCommand::DrawEntireImage {
+            image: image.name().into(),
+            position: *position,
+        });
+    }
+
+    fn draw_rect(&self, rect: &Rect, color: (u8, u8, u8)) {
+        self.record(DrawCommand::DrawRect { rect: *rect, color });
+    }
+
+    fn draw_line(&self, from: &Point, to: &Point, color: (u8, u8, u8)) {
+        self.record(DrawCommand::DrawLine {
+            from: *from,
+            to: *to,
+            color,
+        });
+    }
+
+    fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
+        self.record(DrawCommand::DrawText {
+            text: text.into(),
+            location: *location,
+        });
+        Ok(())
+    }
+}
+
+/// [`RecordingAudio`]が記録する再生命令
+///
+/// 音声は[`Sound::name`]で識別する。
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioCommand {
+    Play { sound: String, looping: Looping },
+    Stop { sound: String },
+}
+
+/// 再生命令を順番に記録するだけの[`Audio`]
+#[derive(Default)]
+pub struct RecordingAudio {
+    commands: RefCell<Vec<AudioCommand>>,
+}
+
+impl RecordingAudio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// これまでに記録した再生命令
+    pub fn commands(&self) -> Vec<AudioCommand> {
+        self.commands.borrow().clone()
+    }
+}
+
+impl Audio for RecordingAudio {
+    fn play_sound(&self, sound: &Sound, looping: Looping) -> Result<()> {
+        self.commands.borrow_mut().push(AudioCommand::Play {
+            sound: sound.name().into(),
+            looping,
+        });
+        Ok(())
+    }
+
+    fn stop_sound(&self, sound: &Sound) -> Result<()> {
+        self.commands.borrow_mut().push(AudioCommand::Stop {
+            sound: sound.name().into(),
+        });
+        Ok(())
+    }
+}
+
+/// 表示中のボタンを覚えておき、クリックを模倣できる[`Ui`]
+#[derive(Default)]
+pub struct HeadlessUi {
+    buttons: RefCell<Vec<(String, UnboundedSender<()>)>>,
+}
+
+impl HeadlessUi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 表示中のボタンのid
+    pub fn visible_buttons(&self) -> Vec<String> {
+        self.buttons
+            .borrow()
+            .iter()
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// 表示中のボタンをクリックする
+    pub fn click(&self, id: &str) -> Result<()> {
+        let mut buttons = self.buttons.borrow_mut();
+        let (_, sender) = buttons
+            .iter_mut()
+            .find(|(button_id, _)| button_id == id)
+            .ok_or_else(|| anyhow!("Button {} is not visible", id))?;
+        sender
+            .start_send(())
+            .map_err(|err| anyhow!("Could not click button {} {:#?}", id, err))
+    }
+}
+
+impl Ui for HeadlessUi {
+    fn show_button(&self, id: &str, _label: &str) -> Result<UnboundedReceiver<()>> {
+        let (sender, receiver) = unbounded();
+        self.buttons.borrow_mut().push((id.into(), sender));
+        Ok(receiver)
+    }
+
+    fn hide(&self) -> Result<()> {
+        self.buttons.borrow_mut().clear();
+        Ok(())
+    }
+}
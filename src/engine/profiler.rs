@@ -0,0 +1,268 @@
+//! 性能の計測と表示
+//!
+//! [`super::GameLoop`]がフレームごとに更新と描画にかかった時間を[`Profiler`]に記録する。
+//! 集計はブラウザなしで動くので、描画とは別に確かめられる。
+//! 表示はデバッグビルドか、`profiler`フィーチャーを有効にしたリリースビルドで
+//! [`TOGGLE_KEY`]を押すと切り替わる。
+
+use std::collections::VecDeque;
+
+use super::{KeyState, Point, Rect, Renderer, FRAME_SIZE};
+
+/// 計測するかどうか。リリースビルドでは`profiler`フィーチャーで有効にする
+pub const PROFILER_ENABLED: bool = cfg!(any(debug_assertions, feature = "profiler"));
+/// 表示を切り替えるキー
+pub const TOGGLE_KEY: &str = "Backquote";
+/// 集計に使う直近のフレーム数
+pub const SAMPLE_COUNT: usize = 120;
+/// フレーム時間のヒストグラムの区間の幅（ミリ秒）
+pub const HISTOGRAM_BUCKET_MILLISECONDS: f32 = 5.0;
+/// ヒストグラムの区間の数。最後の区間はそれより長いフレームをすべて含む
+pub const HISTOGRAM_BUCKETS: usize = 10;
+/// これより長くかかったフレームは、60fpsの画面の書き換えに間に合わなかったとみなす
+pub const DROPPED_FRAME_MILLISECONDS: f32 = FRAME_SIZE * 1.5;
+
+const TEXT_X: i16 = 400;
+const TEXT_Y: i16 = 30;
+const LINE_HEIGHT: i16 = 24;
+const HISTOGRAM_BAR_WIDTH: i16 = 12;
+const HISTOGRAM_HEIGHT: i16 = 60;
+
+/// 1フレーム分の計測結果。時間はミリ秒
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameSample {
+    /// 前のフレームからの経過時間
+    pub frame_time: f32,
+    /// このフレームでゲームの更新にかかった時間
+    pub update_time: f32,
+    /// このフレームで描画にかかった時間
+    pub draw_time: f32,
+    /// このフレームでゲームを更新した回数
+    pub updates: u32,
+}
+
+impl FrameSample {
+    pub fn is_dropped(&self) -> bool {
+        self.frame_time > DROPPED_FRAME_MILLISECONDS
+    }
+}
+
+/// 直近のフレームの計測結果を集計する
+#[derive(Default)]
+pub struct Profiler {
+    samples: VecDeque<FrameSample>,
+    /// 計測を始めてから書き換えに間に合わなかったフレームの数
+    dropped_frames: u32,
+    visible: bool,
+    toggle_held: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, sample: FrameSample) {
+        if self.samples.len() == SAMPLE_COUNT {
+            self.samples.pop_front();
+        }
+        if sample.is_dropped() {
+            self.dropped_frames += 1;
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// 直近のフレームの1秒あたりのフレーム数
+    pub fn frame_rate(&self) -> f32 {
+        let total: f32 = self.samples.iter().map(|sample| sample.frame_time).sum();
+        if total > 0.0 {
+            self.samples.len() as f32 * 1000.0 / total
+        } else {
+            0.0
+        }
+    }
+
+    pub fn average_update_time(&self) -> f32 {
+        self.average(|sample| sample.update_time)
+    }
+
+    pub fn average_draw_time(&self) -> f32 {
+        self.average(|sample| sample.draw_time)
+    }
+
+    pub fn average_updates_per_frame(&self) -> f32 {
+        self.average(|sample| sample.updates as f32)
+    }
+
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped_frames
+    }
+
+    /// 直近のフレーム時間を[`HISTOGRAM_BUCKET_MILLISECONDS`]ごとに数えたもの
+    pub fn histogram(&self) -> [u32; HISTOGRAM_BUCKETS] {
+        let mut histogram = [0; HISTOGRAM_BUCKETS];
+        self.samples.iter().for_each(|sample| {
+            let bucket = (sample.frame_time / HISTOGRAM_BUCKET_MILLISECONDS) as usize;
+            histogram[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+        });
+        histogram
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// [`TOGGLE_KEY`]が押されたら表示を切り替える。押し続けても一度だけ切り替わる
+    pub fn handle_toggle(&mut self, key_state: &KeyState) {
+        let pressed = key_state.is_pressed(TOGGLE_KEY) || key_state.is_just_pressed(TOGGLE_KEY);
+        if pressed && !self.toggle_held {
+            self.visible = !self.visible;
+        }
+        self.toggle_held = pressed;
+    }
+
+    pub fn draw(&self, renderer: &dyn Renderer) {
+        let lines = [
+            format!("Frame Rate {:.0}", self.frame_rate()),
+            format!("Update {:.2}ms", self.average_update_time()),
+            format!("Draw {:.2}ms", self.average_draw_time()),
+            format!("Updates/Frame {:.2}", self.average_updates_per_frame()),
+            format!("Dropped {}", self.dropped_frames()),
+        ];
+        lines.iter().enumerate().for_each(|(row, line)| {
+            let location = Point {
+                x: TEXT_X,
+                y: TEXT_Y + LINE_HEIGHT * row as i16,
+            };
+            if let Err(err) = renderer.draw_text(line, &location) {
+                error!("Could not draw text {:#?}", err);
+            }
+        });
+
+        let bottom = TEXT_Y + LINE_HEIGHT * lines.len() as i16 + HISTOGRAM_HEIGHT;
+        let histogram = self.histogram();
+        let highest = histogram.iter().copied().max().unwrap_or(0).max(1);
+        histogram.iter().enumerate().for_each(|(bucket, &count)| {
+            let height = (count * HISTOGRAM_HEIGHT as u32 / highest) as i16;
+            renderer.draw_rect(
+                &Rect::new_from_x_y(
+                    TEXT_X + HISTOGRAM_BAR_WIDTH * bucket as i16,
+                    bottom - height,
+                    HISTOGRAM_BAR_WIDTH,
+                    height,
+                ),
+                (255, 255, 255),
+            );
+        });
+    }
+
+    fn average(&self, value: impl Fn(&FrameSample) -> f32) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.samples.iter().map(value).sum::<f32>() / self.samples.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::headless::{DrawCommand, RecordingRenderer};
+
+    fn sample(frame_time: f32) -> FrameSample {
+        FrameSample {
+            frame_time,
+            update_time: 2.0,
+            draw_time: 4.0,
+            updates: 1,
+        }
+    }
+
+    #[test]
+    fn steady_frames_give_their_frame_rate() {
+        let mut profiler = Profiler::new();
+        (0..60).for_each(|_| profiler.record(sample(20.0)));
+
+        assert!((profiler.frame_rate() - 50.0).abs() < 1e-3);
+        assert_eq!(profiler.average_update_time(), 2.0);
+        assert_eq!(profiler.average_draw_time(), 4.0);
+        assert_eq!(profiler.average_updates_per_frame(), 1.0);
+        assert_eq!(profiler.dropped_frames(), 0);
+    }
+
+    #[test]
+    fn only_recent_frames_are_averaged() {
+        let mut profiler = Profiler::new();
+        profiler.record(FrameSample {
+            updates: 5,
+            ..sample(16.0)
+        });
+        (0..SAMPLE_COUNT).for_each(|_| profiler.record(sample(16.0)));
+
+        assert_eq!(profiler.average_updates_per_frame(), 1.0);
+    }
+
+    #[test]
+    fn slow_frames_are_counted_as_dropped_and_kept_in_the_last_bucket() {
+        let mut profiler = Profiler::new();
+        profiler.record(sample(16.0));
+        profiler.record(sample(40.0));
+        profiler.record(sample(500.0));
+
+        assert_eq!(profiler.dropped_frames(), 2);
+        let histogram = profiler.histogram();
+        assert_eq!(histogram[3], 1);
+        assert_eq!(histogram[8], 1);
+        assert_eq!(histogram[HISTOGRAM_BUCKETS - 1], 1);
+        assert_eq!(histogram.iter().sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn empty_profiler_reports_zero() {
+        let profiler = Profiler::new();
+
+        assert_eq!(profiler.frame_rate(), 0.0);
+        assert_eq!(profiler.average_draw_time(), 0.0);
+    }
+
+    #[test]
+    fn toggle_key_switches_once_per_press() {
+        let mut profiler = Profiler::new();
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed(TOGGLE_KEY);
+        profiler.handle_toggle(&key_state);
+        key_state.tick();
+        profiler.handle_toggle(&key_state);
+        assert!(profiler.is_visible());
+
+        key_state.set_released(TOGGLE_KEY);
+        profiler.handle_toggle(&key_state);
+        key_state.set_pressed(TOGGLE_KEY);
+        profiler.handle_toggle(&key_state);
+        assert!(!profiler.is_visible());
+    }
+
+    #[test]
+    fn histogram_bars_are_scaled_to_the_most_common_frame_time() {
+        let renderer = RecordingRenderer::new();
+        let mut profiler = Profiler::new();
+        (0..4).for_each(|_| profiler.record(sample(16.0)));
+        (0..2).for_each(|_| profiler.record(sample(33.0)));
+
+        profiler.draw(&renderer);
+
+        let bars: Vec<i16> = renderer
+            .commands()
+            .into_iter()
+            .filter_map(|command| match command {
+                DrawCommand::DrawRect { rect, .. } => Some(rect.height),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bars.len(), HISTOGRAM_BUCKETS);
+        assert_eq!(bars[3], HISTOGRAM_HEIGHT);
+        assert_eq!(bars[6], HISTOGRAM_HEIGHT / 2);
+        assert_eq!(bars[0], 0);
+    }
+}
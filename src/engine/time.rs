@@ -0,0 +1,250 @@
+//! ゲームの時間の進め方
+//!
+//! [`TimeControl`]は[`super::GameLoop`]が持ち、ゲームの更新のたびに[`super::Game`]へ渡される。
+//! ゲームはこれでスローモーションやヒットストップを起こせる。
+//! 一時停止はゲームが[`crate::input::Action::Pause`]で切り替える。
+//! デバッグ用のキーでコマ送りやスローモーションもできる。
+
+use super::{debug::HotKeys, KeyState};
+
+/// 一時停止中に1tickだけ進める
+pub const STEP_KEY: &str = "KeyN";
+/// コマ送りのキーをこのtick数だけ押し続けると、毎フレーム1tickずつ進める
+pub const STEP_REPEAT_TICKS: u32 = 30;
+/// 時間の速さを[`SLOW_MOTION_SCALES`]の順に切り替える
+pub const SLOW_MOTION_KEY: &str = "KeyM";
+pub const SLOW_MOTION_SCALES: [f32; 3] = [1.0, 0.5, 0.25];
+/// 時間の速さの上限。1フレームの更新が増えすぎないようにする
+pub const MAX_TIME_SCALE: f32 = 4.0;
+
+/// 時間の速さ、一時停止、コマ送り、ヒットストップ
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeControl {
+    scale: f32,
+    paused: bool,
+    /// 一時停止中に進めるtick数
+    pending_steps: u32,
+    /// ヒットストップの残りのミリ秒
+    hit_stop: f32,
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        TimeControl {
+            scale: 1.0,
+            paused: false,
+            pending_steps: 0,
+            hit_stop: 0.0,
+        }
+    }
+}
+
+impl TimeControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 実際の時間に対するゲームの時間の速さ。1.0で等速
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(0.0, MAX_TIME_SCALE);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.pending_steps = 0;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    /// 一時停止して、次のフレームで1tickだけ進める
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.pending_steps += 1;
+    }
+
+    /// 実際の時間で`milliseconds`の間、ゲームを止める
+    pub fn hit_stop(&mut self, milliseconds: f32) {
+        self.hit_stop = self.hit_stop.max(milliseconds);
+    }
+
+    pub fn is_hit_stopped(&self) -> bool {
+        self.hit_stop > 0.0
+    }
+
+    /// 実際に経過した`frame_time`ミリ秒を、ゲームの時間に変換する
+    pub(super) fn elapse(&mut self, frame_time: f32) -> f32 {
+        if self.paused {
+            0.0
+        } else if self.hit_stop > 0.0 {
+            self.hit_stop -= frame_time;
+            0.0
+        } else {
+            frame_time * self.scale
+        }
+    }
+
+    /// 一時停止中に進めるtick数を取り出す
+    pub(super) fn take_steps(&mut self) -> u32 {
+        std::mem::take(&mut self.pending_steps)
+    }
+}
+
+/// デバッグ用のキーで[`TimeControl`]を操作する
+#[derive(Default)]
+pub struct DebugTimeKeys {
+    hot_keys: HotKeys,
+}
+
+impl DebugTimeKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, key_state: &KeyState, time: &mut TimeControl) {
+        if self.hot_keys.pressed(key_state, STEP_KEY)
+            || key_state.held_ticks(STEP_KEY) >= STEP_REPEAT_TICKS
+        {
+            time.step();
+        }
+        if self.hot_keys.pressed(key_state, SLOW_MOTION_KEY) {
+            let next = SLOW_MOTION_SCALES
+                .iter()
+                .position(|&scale| scale == time.scale())
+                .map_or(0, |index| (index + 1) % SLOW_MOTION_SCALES.len());
+            time.set_scale(SLOW_MOTION_SCALES[next]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_time_runs_slower() {
+        let mut time = TimeControl::new();
+        time.set_scale(0.5);
+
+        assert_eq!(time.elapse(16.0), 8.0);
+    }
+
+    #[test]
+    fn scale_is_limited() {
+        let mut time = TimeControl::new();
+
+        time.set_scale(100.0);
+        assert_eq!(time.scale(), MAX_TIME_SCALE);
+        time.set_scale(-1.0);
+        assert_eq!(time.scale(), 0.0);
+    }
+
+    #[test]
+    fn paused_time_does_not_elapse_until_a_step() {
+        let mut time = TimeControl::new();
+        time.pause();
+
+        assert_eq!(time.elapse(16.0), 0.0);
+        assert_eq!(time.take_steps(), 0);
+
+        time.step();
+        time.step();
+        assert_eq!(time.take_steps(), 2);
+        assert_eq!(time.take_steps(), 0);
+        assert!(time.is_paused());
+    }
+
+    #[test]
+    fn hit_stop_freezes_for_real_time() {
+        let mut time = TimeControl::new();
+        time.hit_stop(30.0);
+
+        assert!(time.is_hit_stopped());
+        assert_eq!(time.elapse(20.0), 0.0);
+        assert_eq!(time.elapse(20.0), 0.0);
+        assert!(!time.is_hit_stopped());
+        assert_eq!(time.elapse(20.0), 20.0);
+    }
+
+    #[test]
+    fn debug_keys_act_once_per_press() {
+        let mut debug_keys = DebugTimeKeys::new();
+        let mut time = TimeControl::new();
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed(STEP_KEY);
+        debug_keys.update(&key_state, &mut time);
+        debug_keys.update(&key_state, &mut time);
+        assert_eq!(time.take_steps(), 1);
+
+        key_state.set_released(STEP_KEY);
+        debug_keys.update(&key_state, &mut time);
+        key_state.tick();
+        debug_keys.update(&key_state, &mut time);
+        key_state.set_pressed(STEP_KEY);
+        debug_keys.update(&key_state, &mut time);
+        assert_eq!(time.take_steps(), 1);
+        assert!(time.is_paused());
+    }
+
+    #[test]
+    fn holding_the_step_key_repeats_steps() {
+        let mut debug_keys = DebugTimeKeys::new();
+        let mut time = TimeControl::new();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed(STEP_KEY);
+
+        let steps: Vec<u32> = (0..STEP_REPEAT_TICKS + 2)
+            .map(|_| {
+                debug_keys.update(&key_state, &mut time);
+                key_state.tick();
+                time.take_steps()
+            })
+            .collect();
+
+        assert_eq!(steps[0], 1);
+        assert!(steps[1..STEP_REPEAT_TICKS as usize]
+            .iter()
+            .all(|&step| step == 0));
+        assert!(steps[STEP_REPEAT_TICKS as usize..]
+            .iter()
+            .all(|&step| step == 1));
+    }
+
+    #[test]
+    fn slow_motion_key_cycles_the_scales() {
+        let mut debug_keys = DebugTimeKeys::new();
+        let mut time = TimeControl::new();
+        let mut key_state = KeyState::new();
+
+        let scales: Vec<f32> = (0..3)
+            .map(|_| {
+                key_state.set_pressed(SLOW_MOTION_KEY);
+                debug_keys.update(&key_state, &mut time);
+                key_state.set_released(SLOW_MOTION_KEY);
+                key_state.tick();
+                debug_keys.update(&key_state, &mut time);
+                time.scale()
+            })
+            .collect();
+
+        assert_eq!(scales, vec![0.5, 0.25, 1.0]);
+    }
+}
@@ -1,41 +1,59 @@
-// #[cfg(test)]
-// mod test_browser;
-// #[cfg(not(test))]
-// use crate::browser;
-// #[cfg(test)]
-// use test_browser as browser;
-
 use crate::{
     browser,
     engine::{
-        self, Audio, Cell, Collider, Game, Image, KeyState, Point, Rect, Renderer, Sheet, Sound,
-        SpriteSheet,
+        self, debug::DebugSettings, time::TimeControl, Audio, Cell, Collider, Game, HtmlUi, Image,
+        ImageHandle, KeyState, Overlap, Point, Rect, Renderer, Sheet, Sound, SpriteSheet, Sweep,
+        TranslatedRenderer, Ui, WebAudio,
     },
+    input::{Action, ActionState, KeyBindings},
+    replay::{LocalStorageReplays, Replay, ReplayPlayer, ReplayStore},
+    score::{self, LocalStorageScores, ScoreStore},
     segment::{
-        platform_and_platform, platform_and_platform_and2, stone_and_platform, stone_and_platform2,
-        stone_and_platform3, stone_and_platform4,
+        generator::SegmentGenerator,
+        selection::{DifficultyPolicy, SegmentPolicy, SelectionContext},
+        stone_and_platform, SegmentLibrary, SegmentSource,
     },
 };
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use futures::channel::mpsc::UnboundedReceiver;
 use gloo_utils::format::JsValueSerdeExt;
-use rand::{thread_rng, Rng};
-use std::collections::HashMap;
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
 use std::rc::Rc;
-use web_sys::{AudioBufferSourceNode, HtmlImageElement};
 
 use self::red_hat_boy_states::{
-    Falling, Idle, Jumping, KnockedOut, RedHatBoyContext, RedHatBoyState, Running, Sliding,
+    Airborne, Falling, Idle, Jumping, KnockedOut, RedHatBoyContext, RedHatBoyState, Running,
+    Sliding,
 };
+/// 障害物の組を作る時に、ジャンプで届く距離や高さを計算するために使う
+pub(crate) use self::red_hat_boy_states::{GRAVITY, JUMPING_SPEED, RUNNING_SPEED};
+use self::speed::SpeedCurve;
+
+#[cfg(feature = "dev-tools")]
+mod dev_tools;
+#[cfg(test)]
+mod solvability;
+mod speed;
 
 const CANVAS_SIZE: i16 = 600;
 
 ///
 pub struct WalkTheDog {
     machine: Option<WalkTheDogStateMachine>,
+    key_bindings: KeyBindings,
+    replays: Rc<dyn ReplayStore>,
+    /// 今のプレイの記録。ゲームオーバーになったら保存する
+    recording: Replay,
+    /// 記録を再生している間は、キー入力の代わりに記録された入力を使う
+    playback: Option<ReplayPlayer>,
+    scores: Rc<dyn ScoreStore>,
+    /// 前のフレームで一時停止の操作が押されていたか。押し続けても切り替えは一回だけにする
+    pause_held: bool,
 }
 
+#[allow(clippy::large_enum_variant)]
 enum WalkTheDogStateMachine {
     Ready(WalkTheDogState<Ready>),
     Walking(WalkTheDogState<Walking>),
@@ -46,28 +64,92 @@ struct WalkTheDogState<T> {
     _state: T,
     scene: Scene,
 }
-struct Ready;
+/// 遊び方を選ぶメニューを表示して、走り始めるのを待つ
+struct Ready {
+    endless_event: UnboundedReceiver<()>,
+    daily_event: UnboundedReceiver<()>,
+}
 struct Walking;
 struct GameOver {
     new_game_event: UnboundedReceiver<()>,
+    /// この遊び方での最高記録
+    best_score: i32,
+}
+
+/// 遊び方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GameMode {
+    /// 毎回違うコースを走る
+    Endless,
+    /// その日は誰でも同じコースを走る
+    Daily,
+}
+
+impl GameMode {
+    fn label(self) -> &'static str {
+        match self {
+            GameMode::Endless => "Endless",
+            GameMode::Daily => "Daily Run",
+        }
+    }
+
+    /// メニューでの次の選択肢
+    fn toggle(self) -> Self {
+        match self {
+            GameMode::Endless => GameMode::Daily,
+            GameMode::Daily => GameMode::Endless,
+        }
+    }
 }
 
+/// 日付から、その日のコースのシードを作る。
+/// シードは日付を数字で並べたもので、`?seed=`にそのまま使える。
+fn daily_seed((year, month, day): (u32, u32, u32)) -> u64 {
+    u64::from(year) * 10000 + u64::from(month) * 100 + u64::from(day)
+}
+
+const ENDLESS_BUTTON_ID: &str = "endless-mode";
+const DAILY_BUTTON_ID: &str = "daily-mode";
+
 impl WalkTheDogStateMachine {
-    fn update(self, key_state: &KeyState) -> Self {
+    fn update(self, action_state: &ActionState) -> Self {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => state.update(action_state).into(),
+            WalkTheDogStateMachine::Walking(state) => state.update(action_state).into(),
+            WalkTheDogStateMachine::GameOver(state) => state.update(action_state).into(),
+        }
+    }
+
+    /// 止まっている状態では補間しない
+    fn draw(&self, renderer: &dyn Renderer, alpha: f32, debug: &DebugSettings) {
         match self {
-            WalkTheDogStateMachine::Ready(state) => state.update(key_state).into(),
-            WalkTheDogStateMachine::Walking(state) => state.update(key_state).into(),
-            WalkTheDogStateMachine::GameOver(state) => state.update(key_state).into(),
+            WalkTheDogStateMachine::Ready(state) => {
+                state.draw(renderer, 1.0, debug);
+                state.draw_menu(renderer);
+            }
+            WalkTheDogStateMachine::Walking(state) => state.draw(renderer, alpha, debug),
+            WalkTheDogStateMachine::GameOver(state) => {
+                state.draw(renderer, 1.0, debug);
+                state.draw_seed(renderer);
+            }
         }
     }
 
-    fn draw(&self, renderer: &Renderer) {
+    fn scene(&self) -> &Scene {
         match self {
-            WalkTheDogStateMachine::Ready(state) => state.draw(renderer),
-            WalkTheDogStateMachine::Walking(state) => state.draw(renderer),
-            WalkTheDogStateMachine::GameOver(state) => state.draw(renderer),
+            WalkTheDogStateMachine::Ready(state) => &state.scene,
+            WalkTheDogStateMachine::Walking(state) => &state.scene,
+            WalkTheDogStateMachine::GameOver(state) => &state.scene,
         }
     }
+
+    fn is_ready(&self) -> bool {
+        matches!(self, WalkTheDogStateMachine::Ready(_))
+    }
+
+    fn is_game_over(&self) -> bool {
+        matches!(self, WalkTheDogStateMachine::GameOver(_))
+    }
 }
 
 impl From<WalkTheDogState<Ready>> for WalkTheDogStateMachine {
@@ -86,6 +168,7 @@ impl From<WalkTheDogState<GameOver>> for WalkTheDogStateMachine {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 enum ReadyEndState {
     Complete(WalkTheDogState<Walking>),
     Continue(WalkTheDogState<Ready>),
@@ -101,14 +184,75 @@ impl From<ReadyEndState> for WalkTheDogStateMachine {
 }
 
 impl WalkTheDogState<Ready> {
-    fn update(mut self, key_state: &KeyState) -> ReadyEndState {
+    /// 遊び方を選ぶボタンを表示する
+    fn new(scene: Scene) -> Self {
+        let endless_event = scene
+            .ui
+            .show_button(ENDLESS_BUTTON_ID, GameMode::Endless.label())
+            .expect("Can not show endless mode button");
+        let daily_event = scene
+            .ui
+            .show_button(DAILY_BUTTON_ID, GameMode::Daily.label())
+            .expect("Can not show daily mode button");
+        WalkTheDogState {
+            _state: Ready {
+                endless_event,
+                daily_event,
+            },
+            scene,
+        }
+    }
+
+    /// ボタンを押すとその遊び方で走り始める。
+    /// キーではジャンプとスライディングで遊び方を選び、開始で走り始める。
+    fn update(mut self, action_state: &ActionState) -> ReadyEndState {
         self.scene.rhb.update();
-        if key_state.is_pressed("ArrowRight") {
+        if self._state.endless_pressed() {
+            self.scene.select_mode(GameMode::Endless);
+            ReadyEndState::Complete(self.start_running())
+        } else if self._state.daily_pressed() {
+            self.scene.select_mode(GameMode::Daily);
+            ReadyEndState::Complete(self.start_running())
+        } else if action_state.is_just_pressed(Action::Start) {
             ReadyEndState::Complete(self.start_running())
         } else {
+            if action_state.is_just_pressed(Action::Jump)
+                || action_state.is_just_pressed(Action::Slide)
+            {
+                let mode = self.scene.mode.toggle();
+                self.scene.select_mode(mode);
+            }
             ReadyEndState::Continue(self)
         }
     }
+
+    fn draw_menu(&self, renderer: &dyn Renderer) {
+        [GameMode::Endless, GameMode::Daily]
+            .iter()
+            .zip(0..)
+            .for_each(|(&mode, row)| {
+                let marker = if mode == self.scene.mode { "▶" } else { "  " };
+                renderer
+                    .draw_text(
+                        &format!("{} {}", marker, mode.label()),
+                        &Point {
+                            x: 20,
+                            y: 80 + row * 30,
+                        },
+                    )
+                    .expect("can not draw menu");
+            });
+    }
+}
+
+impl Ready {
+    fn endless_pressed(&mut self) -> bool {
+        matches!(self.endless_event.try_next(), Ok(Some(())))
+    }
+
+    fn daily_pressed(&mut self) -> bool {
+        matches!(self.daily_event.try_next(), Ok(Some(())))
+    }
 }
 
 impl From<WalkingEndState> for WalkTheDogStateMachine {
@@ -123,11 +267,8 @@ impl From<WalkingEndState> for WalkTheDogStateMachine {
 impl WalkTheDogState<Ready> {
     fn start_running(self) -> WalkTheDogState<Walking> {
         let mut scene = self.scene;
-        scene.rhb.run_right(
-            scene.audio.clone(),
-            scene.background_music(),
-            &mut scene.sound_nodes,
-        );
+        scene.ui.hide().expect("Can not hide UI elements");
+        scene.rhb.run_right();
         WalkTheDogState {
             _state: Walking,
             scene,
@@ -142,22 +283,39 @@ enum WalkingEndState {
 }
 
 impl WalkTheDogState<Walking> {
-    fn update(self, key_state: &KeyState) -> WalkingEndState {
+    fn update(self, action_state: &ActionState) -> WalkingEndState {
         let mut scene = self.scene;
         scene.count += 1;
+        scene.rhb.set_running_speed(scene.running_speed());
         let horizontal_velocity = scene.horizontal_velocity();
-        if key_state.is_pressed("ArrowUp") {
-            scene.rhb.jump(scene.audio.clone(), scene.jumping_sound());
+        scene.distance -= i32::from(horizontal_velocity);
+        scene.scroll = horizontal_velocity;
+        scene.previous_rhb_y = scene.rhb.pos_y();
+        if action_state.is_just_pressed(Action::Jump) {
+            scene.rhb.jump();
         }
-        if key_state.is_pressed("ArrowDown") {
+        // スワイプは同じtickの間に押されて離されるので、押された瞬間も見る
+        if action_state.is_pressed(Action::Slide) || action_state.is_just_pressed(Action::Slide) {
             scene.rhb.slide();
         }
         scene.rhb.update();
         scene.obstacles.retain(|obstacle| obstacle.right() > 0);
         scene.obstacles.iter_mut().for_each(|obstacle| {
             obstacle.move_horisontally(horizontal_velocity);
-            obstacle.check_intersection(&mut scene.rhb, scene.sound_nodes.clone());
+            obstacle.check_intersection(&mut scene.rhb);
         });
+        if !scene
+            .obstacles
+            .iter()
+            .any(|obstacle| obstacle.supports(&scene.rhb))
+        {
+            scene.rhb.lose_support();
+        }
+        scene.segment_starts.retain(|&start| start > 0);
+        scene
+            .segment_starts
+            .iter_mut()
+            .for_each(|start| *start += horizontal_velocity);
         let [background1, background2] = &mut scene.backgrounds;
         background1.move_horisontally(horizontal_velocity);
         background2.move_horisontally(horizontal_velocity);
@@ -168,20 +326,20 @@ impl WalkTheDogState<Walking> {
             background2.set_x(background1.right());
         }
 
-        if scene.timeline < TIMELINE_MINIMUM {
+        if scene.timeline < scene.timeline_minimum() {
             scene.generate_next_segment();
         } else {
             scene.timeline += horizontal_velocity;
         }
         if let RedHatBoyStateMachine::KnockedOut(_) = scene.rhb.state_machine {
-            let receiver =
-                browser::draw_ui("<button id=\"new-game\" type=\"button\">New Game</button>")
-                    .and_then(|_unit| browser::find_html_elemebt_by_id("new-game"))
-                    .map(engine::add_click_handler)
-                    .unwrap();
+            let receiver = scene
+                .ui
+                .show_button(NEW_GAME_BUTTON_ID, "New Game")
+                .expect("Can not show new game button");
             WalkingEndState::Complete(WalkTheDogState {
                 _state: GameOver {
                     new_game_event: receiver,
+                    best_score: scene.count,
                 },
                 scene,
             })
@@ -210,8 +368,8 @@ impl From<GameOverEndState> for WalkTheDogStateMachine {
 }
 
 impl WalkTheDogState<GameOver> {
-    fn update(mut self, key_state: &KeyState) -> GameOverEndState {
-        if self._state.new_game_pressed() || key_state.is_pressed("Enter") {
+    fn update(mut self, action_state: &ActionState) -> GameOverEndState {
+        if self._state.new_game_pressed() || action_state.is_just_pressed(Action::Confirm) {
             GameOverEndState::Complete(self.new_game())
         } else {
             GameOverEndState::Continue(self)
@@ -219,11 +377,35 @@ impl WalkTheDogState<GameOver> {
     }
 
     fn new_game(self) -> WalkTheDogState<Ready> {
-        browser::hide_ui().expect("Can not hide UI elements");
-        WalkTheDogState {
-            _state: Ready,
-            scene: Scene::reset(self.scene),
-        }
+        self.scene.ui.hide().expect("Can not hide UI elements");
+        let seed = self.scene.next_seed();
+        WalkTheDogState::<Ready>::new(Scene::reset(self.scene, seed))
+    }
+
+    /// 同じコースを遊べるように、このゲームのシードを表示する。
+    /// あわせて遊び方と最高記録も表示する。
+    fn draw_seed(&self, renderer: &dyn Renderer) {
+        [
+            format!("Seed: {}", self.scene.seed),
+            format!(
+                "{} Best: {}",
+                self.scene.mode.label(),
+                format_time(self._state.best_score)
+            ),
+        ]
+        .iter()
+        .zip(0..)
+        .for_each(|(text, row)| {
+            renderer
+                .draw_text(
+                    text,
+                    &Point {
+                        x: 20,
+                        y: 80 + row * 30,
+                    },
+                )
+                .expect("can not draw seed");
+        });
     }
 }
 
@@ -237,145 +419,334 @@ pub struct Scene {
     rhb: RedHatBoy,
     backgrounds: [Image; 2],
     obstacle_sheet: Rc<SpriteSheet>,
+    /// 次の障害物の組を決まった組から選ぶか、部品から作るか
+    segment_source: SegmentSource,
+    /// [`Scene::generate_next_segment`]が選べる障害物の組
+    segments: Rc<SegmentLibrary>,
+    /// 次に置く障害物の組の選び方
+    policy: Box<dyn SegmentPolicy>,
+    /// 最後に選んだ障害物の組
+    previous_segment: Option<usize>,
     obstacles: Vec<Box<dyn Obstacle<RedHatBoy>>>,
     timeline: i16,
-    stone_element: HtmlImageElement,
-    audio: Rc<Audio>,
-    sound_collection: HashMap<String, Rc<Sound>>,
-    sound_nodes: HashMap<String, Rc<AudioBufferSourceNode>>,
+    stone_element: ImageHandle,
+    ui: Rc<dyn Ui>,
     count: i32,
+    /// 走り始めてから進んだ距離
+    distance: i32,
+    /// 進んだ距離ごとの走る速さ
+    speed_curve: SpeedCurve,
+    /// 障害物の選択に使う乱数のシード
+    seed: u64,
+    rng: ChaCha8Rng,
+    /// [`SegmentSource::Generated`]の時に組を作る。シードは[`Scene::seed`]と同じ
+    generator: SegmentGenerator,
+    /// URLで選ばれたシード。選ばれていれば、新しいゲームも同じシードで始めて同じコースを走る
+    course_seed: Option<u64>,
+    mode: GameMode,
+    /// ページを開いた日のコースのシード
+    daily_seed: u64,
+    /// 最後の更新で障害物と背景が動いた距離
+    scroll: i16,
+    /// 最後の更新の前のRedHatBoyの高さ
+    previous_rhb_y: i16,
+    /// 画面に残っている障害物の組の左端
+    segment_starts: Vec<i16>,
+    #[cfg(feature = "dev-tools")]
+    cheats: dev_tools::Cheats,
 }
 
 const JUMPING_SOUND_FILENAME: &str = "sounds/SFX_Jump_23.mp3";
 const BACKGROUND_MUSIC_FILENAME: &str = "sounds/background_song.mp3";
-const BACKGROUND_MUSIC_NODENAME: &str = "background_music";
+const NEW_GAME_BUTTON_ID: &str = "new-game";
 impl Scene {
+    fn new(
+        rhb: RedHatBoy,
+        background_element: ImageHandle,
+        stone_element: ImageHandle,
+        obstacle_sheet: Rc<SpriteSheet>,
+        segments: Rc<SegmentLibrary>,
+        ui: Rc<dyn Ui>,
+        seed: u64,
+    ) -> Self {
+        let background_width = background_element.width();
+        let background1 = Image::new(background_element.clone(), Point { x: 0, y: 0 });
+        let background2 = Image::new(
+            background_element,
+            Point {
+                x: background_width,
+                y: 0,
+            },
+        );
+        let starting_obstacles =
+            stone_and_platform(stone_element.clone(), obstacle_sheet.clone(), CANVAS_SIZE);
+        let timeline = rightmost(&starting_obstacles);
+        Scene {
+            previous_rhb_y: rhb.pos_y(),
+            rhb,
+            backgrounds: [background1, background2],
+            obstacle_sheet,
+            segment_source: SegmentSource::Library,
+            segments,
+            policy: Box::new(DifficultyPolicy),
+            previous_segment: None,
+            obstacles: starting_obstacles,
+            timeline,
+            stone_element,
+            ui,
+            count: 0,
+            distance: 0,
+            speed_curve: SpeedCurve::default(),
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            generator: SegmentGenerator::new(seed),
+            course_seed: None,
+            mode: GameMode::Endless,
+            daily_seed: 0,
+            scroll: 0,
+            segment_starts: vec![CANVAS_SIZE],
+            #[cfg(feature = "dev-tools")]
+            cheats: dev_tools::Cheats::default(),
+        }
+    }
+
+    /// 次のゲームのシード
+    fn next_seed(&self) -> u64 {
+        match self.mode {
+            GameMode::Endless => self.course_seed.unwrap_or_else(|| thread_rng().gen()),
+            GameMode::Daily => self.daily_seed,
+        }
+    }
+
+    /// 遊び方を選び、そのコースのシードに切り替える
+    fn select_mode(&mut self, mode: GameMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.seed = self.next_seed();
+            self.rng = ChaCha8Rng::seed_from_u64(self.seed);
+            self.generator = SegmentGenerator::new(self.seed);
+        }
+    }
+
+    /// 最高記録を保存するキー。デイリーランは日ごとに別の記録になる
+    fn score_key(&self) -> String {
+        match self.mode {
+            GameMode::Endless => "best-score-endless".into(),
+            GameMode::Daily => format!("best-score-daily-{}", self.daily_seed),
+        }
+    }
+
+    /// 今の距離での走る速さ
+    fn running_speed(&self) -> i16 {
+        #[cfg(feature = "dev-tools")]
+        if let Some(speed) = self.cheats.running_speed {
+            return speed;
+        }
+        self.speed_curve.speed_at(self.distance)
+    }
+
     /// シーンの水平方向への移動速度
     fn horizontal_velocity(&self) -> i16 {
         -self.rhb.walking_speed()
     }
 
-    fn jumping_sound(&self) -> Rc<Sound> {
-        self.sound_collection
-            .get(JUMPING_SOUND_FILENAME)
-            .unwrap()
-            .clone()
+    /// 障害物の組の間隔。速く走るほど広げて、組の間の時間を保つ
+    fn obstacle_buffer(&self) -> i16 {
+        self.speed_curve.scale(OBSTACLE_BUFFER, self.distance)
     }
 
-    fn background_music(&self) -> Rc<Sound> {
-        self.sound_collection
-            .get(BACKGROUND_MUSIC_FILENAME)
-            .unwrap()
-            .clone()
+    /// 最後の障害物がこれより左に来たら次の組を置く。速く走るほど早めに置く
+    fn timeline_minimum(&self) -> i16 {
+        self.speed_curve.scale(TIMELINE_MINIMUM, self.distance)
     }
 
     /// 障害物を生成して環境に追加する
     fn generate_next_segment(&mut self) {
-        let mut rng = thread_rng();
-        let next_segment = rng.gen_range(0..6);
-        let mut next_obstacles = match next_segment {
-            0 => stone_and_platform(
-                self.stone_element.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            1 => stone_and_platform2(
-                self.stone_element.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            2 => stone_and_platform3(
-                self.stone_element.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            3 => stone_and_platform4(
-                self.stone_element.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            4 => platform_and_platform(
-                self.stone_element.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            5 => platform_and_platform_and2(
+        let offset_x = self.timeline + self.obstacle_buffer();
+        self.segment_starts.push(offset_x);
+        let mut next_obstacles = match self.segment_source {
+            SegmentSource::Library => self.next_library_segment(offset_x),
+            SegmentSource::Generated => self.generator.next_segment(
                 self.stone_element.clone(),
                 self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
+                offset_x,
             ),
-            _ => vec![],
         };
         self.timeline = rightmost(&next_obstacles);
         self.obstacles.append(&mut next_obstacles);
     }
 
-    fn draw(&self, renderer: &Renderer) {
+    /// [`Scene::segments`]から次の組を選び、左端を`offset_x`にして作る
+    fn next_library_segment(&mut self, offset_x: i16) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
+        let segment = self.choose_segment();
+        // チートで固定しても乱数は進め、チートを外した時と同じ順番を保つ
+        #[cfg(feature = "dev-tools")]
+        let segment = self.cheats.segment.unwrap_or(segment);
+        match self.segments.instantiate(
+            segment,
+            self.stone_element.clone(),
+            self.obstacle_sheet.clone(),
+            offset_x,
+        ) {
+            Ok(obstacles) => obstacles,
+            Err(err) => {
+                // 選び方の誤りなので、開発中は止めて気付けるようにする
+                error!("Could not place the next segment {:#?}", err);
+                debug_assert!(false, "Could not place the next segment {:#?}", err);
+                vec![]
+            }
+        }
+    }
+
+    /// 次に置く障害物の組の番号を、[`Scene::policy`]でシードから決まる順番で選ぶ
+    fn choose_segment(&mut self) -> usize {
+        let context = SelectionContext {
+            distance: self.distance,
+            previous: self.previous_segment,
+        };
+        let segment = self.policy.choose(&self.segments, &context, &mut self.rng);
+        self.previous_segment = Some(segment);
+        segment
+    }
+
+    /// 最後の更新の前と後の位置を`alpha`で補間して描く
+    fn draw(&self, renderer: &dyn Renderer, alpha: f32, debug: &DebugSettings) {
         renderer.clear(&Rect::new_from_x_y(0, 0, CANVAS_SIZE, CANVAS_SIZE));
+        let scrolled = TranslatedRenderer::new(
+            renderer,
+            Point {
+                x: interpolate(-self.scroll, 0, alpha),
+                y: 0,
+            },
+        );
         self.backgrounds.iter().for_each(|background| {
-            background.draw(renderer);
+            background.draw(&scrolled);
         });
-        self.rhb.draw(renderer);
+        self.rhb.draw(
+            &TranslatedRenderer::new(
+                renderer,
+                Point {
+                    x: 0,
+                    y: interpolate(self.previous_rhb_y - self.rhb.pos_y(), 0, alpha),
+                },
+            ),
+            debug,
+        );
         self.obstacles.iter().for_each(|obstacle| {
-            obstacle.draw(renderer);
+            obstacle.draw(&scrolled, debug);
         });
-        let time = self.count / 60;
-        let minutes = time / 60;
-        let seconds = time % 60;
+        if debug.markers {
+            self.obstacles.iter().for_each(|obstacle| {
+                draw_vertical_line(&scrolled, obstacle.right(), (0, 255, 0));
+            });
+            draw_vertical_line(&scrolled, self.timeline, (255, 0, 255));
+        }
+        if debug.segment_boundaries {
+            self.segment_starts.iter().for_each(|&start| {
+                draw_vertical_line(&scrolled, start, (0, 255, 255));
+            });
+        }
         renderer
             .draw_text(
-                &format!("Time: {:>02}:{:>02}", minutes, seconds),
+                &format!("Time: {}", format_time(self.count)),
                 &Point { x: 20, y: 40 },
             )
             .expect("can not draw time");
     }
 
-    fn reset(scene: Self) -> Self {
+    /// `seed`でシーンを最初の状態に戻す
+    fn reset(scene: Self, seed: u64) -> Self {
         let starting_obstacles = stone_and_platform(
             scene.stone_element.clone(),
             scene.obstacle_sheet.clone(),
             CANVAS_SIZE,
         );
         let timeline = rightmost(&starting_obstacles);
+        let rhb = RedHatBoy::reset(scene.rhb);
         Scene {
-            rhb: RedHatBoy::reset(scene.rhb),
+            previous_rhb_y: rhb.pos_y(),
+            rhb,
             backgrounds: scene.backgrounds,
             obstacle_sheet: scene.obstacle_sheet.clone(),
+            segment_source: scene.segment_source,
+            segments: scene.segments,
+            policy: scene.policy,
+            previous_segment: None,
             obstacles: starting_obstacles,
             timeline,
             stone_element: scene.stone_element,
-            audio: scene.audio,
-            sound_collection: scene.sound_collection,
-            sound_nodes: scene.sound_nodes,
+            ui: scene.ui,
             count: 0,
+            distance: 0,
+            speed_curve: scene.speed_curve,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            generator: SegmentGenerator::new(seed),
+            course_seed: scene.course_seed,
+            mode: scene.mode,
+            daily_seed: scene.daily_seed,
+            scroll: 0,
+            segment_starts: vec![CANVAS_SIZE],
+            #[cfg(feature = "dev-tools")]
+            cheats: scene.cheats,
         }
     }
+
+    /// 開発用のチートを選び、RedHatBoyにも伝える
+    #[cfg(feature = "dev-tools")]
+    fn set_cheats(&mut self, cheats: dev_tools::Cheats) {
+        self.cheats = cheats;
+        self.apply_cheats();
+    }
+
+    #[cfg(feature = "dev-tools")]
+    fn apply_cheats(&mut self) {
+        self.rhb.invulnerable = self.cheats.god_mode;
+    }
 }
+/// 開発用の表示で、画面の上から下まで`x`の位置に線を引く
+fn draw_vertical_line(renderer: &dyn Renderer, x: i16, color: (u8, u8, u8)) {
+    renderer.draw_line(&Point { x, y: 0 }, &Point { x, y: CANVAS_SIZE }, color);
+}
+
+/// `from`から`to`までの`alpha`の割合の位置
+fn interpolate(from: i16, to: i16, alpha: f32) -> i16 {
+    from + (f32::from(to - from) * alpha).round() as i16
+}
+
+/// tick数を分と秒で表す
+fn format_time(count: i32) -> String {
+    let time = count / 60;
+    format!("{:>02}:{:>02}", time / 60, time % 60)
+}
+
 const TIMELINE_MINIMUM: i16 = 500;
 const OBSTACLE_BUFFER: i16 = 20;
+/// ぶつかった瞬間を見せるために、ゲームを止めるミリ秒
+const HIT_STOP_MILLISECONDS: f32 = 150.0;
 #[async_trait(?Send)]
 impl Game for WalkTheDog {
     async fn intialize(&self) -> Result<Box<dyn Game>> {
         match self.machine {
             None => {
+                // 音声設定
+                let audio = WebAudio::new()?;
+                let sounds = RedHatBoySounds {
+                    jump: audio.load_sound(JUMPING_SOUND_FILENAME).await?,
+                    background_music: audio.load_sound(BACKGROUND_MUSIC_FILENAME).await?,
+                };
+
                 let json = browser::fetch_json("rhb_trimmed.json").await?;
                 // キャラクターの設定
                 let rhb = RedHatBoy::new(
                     json.into_serde::<Sheet>()?,
                     engine::load_image("rhb_trimmed.png").await?,
+                    Rc::new(audio),
+                    sounds,
                 );
 
                 // 背景の設定
                 let background_element = engine::load_image("BG.png").await?;
-                let background_width = background_element.width() as i16;
-                let background1 = Image::new(background_element.clone(), Point { x: 0, y: 0 });
-                let background2 = Image::new(
-                    background_element,
-                    Point {
-                        x: background_width,
-                        y: 0,
-                    },
-                );
                 // 障害物画像の取得
                 let stone_element = engine::load_image("Stone.png").await?;
                 // 足場の設定
@@ -384,94 +755,260 @@ impl Game for WalkTheDog {
                     tiles.into_serde::<Sheet>()?,
                     engine::load_image("tiles.png").await?,
                 ));
-                let starting_obstacles =
-                    stone_and_platform(stone_element.clone(), sprite_sheet.clone(), CANVAS_SIZE);
-
-                // 音声設定
-                let audio = Rc::new(Audio::new()?).clone();
-                let jumping_sound = audio.load_sound(JUMPING_SOUND_FILENAME).await?;
-                let background_music = audio.load_sound(BACKGROUND_MUSIC_FILENAME).await?;
-                let mut sound_collection = HashMap::new();
-                sound_collection
-                    .insert(String::from(JUMPING_SOUND_FILENAME), Rc::new(jumping_sound));
-                sound_collection.insert(
-                    String::from(BACKGROUND_MUSIC_FILENAME),
-                    Rc::new(background_music),
-                );
-                let sound_nodes = HashMap::new();
+                // 障害物の組の設定
+                let segments = browser::fetch_json("segments.json")
+                    .await?
+                    .into_serde::<SegmentLibrary>()?;
+                segments.validate(&sprite_sheet)?;
+
+                // URLに?replayがあれば、最後に保存したプレイを再生する
+                let replays = Rc::new(LocalStorageReplays);
+                let replay = match browser::query_param("replay")? {
+                    Some(_) => replays.load()?,
+                    None => None,
+                };
+                if let Some(replay) = &replay {
+                    log!("Playing back a replay of {} ticks", replay.ticks());
+                }
+                // URLの?seed=で選ばれたシードがあれば、そのコースを繰り返し遊ぶ
+                let course_seed = browser::query_param("seed")?
+                    .map(|seed| {
+                        seed.parse::<u64>()
+                            .map_err(|err| anyhow!("Invalid seed {} {}", seed, err))
+                    })
+                    .transpose()?;
+                let seed = replay
+                    .as_ref()
+                    .map(Replay::seed)
+                    .or(course_seed)
+                    .unwrap_or_else(|| thread_rng().gen());
+                // URLの?segments=generatedで、決まった組の代わりに部品から作った組を走る
+                let segment_source = match replay.as_ref().map(Replay::segment_source) {
+                    Some(segment_source) => segment_source,
+                    None => browser::query_param("segments")?
+                        .map(|name| SegmentSource::parse(&name))
+                        .transpose()?
+                        .unwrap_or_default(),
+                };
 
-                // タイムラインの設定
-                let timeline = rightmost(&starting_obstacles);
-                let scene = Scene {
+                let mut scene = Scene::new(
                     rhb,
-                    backgrounds: [background1, background2],
-                    obstacle_sheet: sprite_sheet,
-                    obstacles: starting_obstacles,
-                    timeline,
+                    background_element,
                     stone_element,
-                    audio,
-                    sound_collection,
-                    sound_nodes,
-                    count: 0,
-                };
+                    sprite_sheet,
+                    Rc::new(segments),
+                    Rc::new(HtmlUi),
+                    seed,
+                );
+                scene.course_seed = course_seed;
+                scene.segment_source = segment_source;
+                scene.daily_seed = daily_seed(browser::today());
+                #[cfg(feature = "dev-tools")]
+                scene.set_cheats(dev_tools::Cheats::from_query(scene.segments.len())?);
+                let mut key_bindings = KeyBindings::load_or_default();
+                key_bindings.rebind_from_query()?;
                 Ok(Box::new(WalkTheDog {
-                    machine: Some(WalkTheDogStateMachine::Ready(WalkTheDogState {
-                        _state: Ready,
-                        scene,
-                    })),
+                    machine: Some(WalkTheDogStateMachine::Ready(
+                        WalkTheDogState::<Ready>::new(scene),
+                    )),
+                    key_bindings,
+                    replays,
+                    recording: Replay::new(seed, segment_source),
+                    playback: replay.map(ReplayPlayer::new),
+                    scores: Rc::new(LocalStorageScores),
+                    pause_held: false,
                 }))
             }
             Some(_) => Err(anyhow!("Error Game is already initialized!")),
         }
     }
-    fn update(&mut self, key_state: &KeyState) {
+    fn update(&mut self, key_state: &KeyState, time: &mut TimeControl) {
+        // 再生する記録が終わったら、キー入力に戻す
+        let action_state = match self.playback.as_mut().and_then(ReplayPlayer::next_input) {
+            Some(action_state) => action_state,
+            None => self.key_bindings.action_state(key_state),
+        };
         if let Some(machine) = self.machine.take() {
-            self.machine.replace(machine.update(key_state));
+            let was_ready = machine.is_ready();
+            let was_game_over = machine.is_game_over();
+            let was_knocked_out = machine.scene().rhb.is_knocked_out();
+            let mut machine = machine.update(&action_state);
+            if !was_knocked_out && machine.scene().rhb.is_knocked_out() {
+                time.hit_stop(HIT_STOP_MILLISECONDS);
+            }
+            if was_ready && !machine.is_ready() {
+                // 走り始めたtickから記録する。
+                // メニューのボタンで始めた場合も再生で走り始めるように、開始の操作を加えておく
+                self.recording = Replay::new(machine.scene().seed, machine.scene().segment_source);
+                let mut start = action_state;
+                start.set_just_pressed(Action::Start);
+                self.recording.record(start);
+                #[cfg(feature = "dev-tools")]
+                {
+                    machine = dev_tools::skip_ahead(machine);
+                }
+            } else if !was_ready && !was_game_over {
+                self.recording.record(action_state);
+            }
+            #[cfg(feature = "dev-tools")]
+            let cheated = machine.scene().cheats.is_active();
+            #[cfg(not(feature = "dev-tools"))]
+            let cheated = false;
+            if !was_game_over && machine.is_game_over() && self.playback.is_none() && !cheated {
+                self.finish_run(&mut machine);
+            }
+            if was_game_over && !machine.is_game_over() {
+                self.playback = None;
+            }
+            self.machine.replace(machine);
         }
         assert!(self.machine.is_some());
     }
-    fn draw(&self, renderer: &Renderer) {
+    /// 一時停止の操作で、ゲームの時間を止めたり動かしたりする。
+    /// 記録の再生中も、一時停止はキー入力で操作する
+    fn update_time(&mut self, key_state: &KeyState, time: &mut TimeControl) {
+        let action_state = self.key_bindings.action_state(key_state);
+        let pause =
+            action_state.is_pressed(Action::Pause) || action_state.is_just_pressed(Action::Pause);
+        if pause && !self.pause_held {
+            time.toggle_pause();
+        }
+        self.pause_held = pause;
+    }
+    fn draw(&self, renderer: &dyn Renderer, alpha: f32, debug: &DebugSettings) {
         if let Some(machine) = &self.machine {
-            machine.draw(renderer);
+            machine.draw(renderer, alpha, debug);
         }
     }
 }
 
 impl<T> WalkTheDogState<T> {
-    fn draw(&self, renderer: &Renderer) {
-        self.scene.draw(renderer);
+    fn draw(&self, renderer: &dyn Renderer, alpha: f32, debug: &DebugSettings) {
+        self.scene.draw(renderer, alpha, debug);
     }
 }
 
 impl WalkTheDog {
+    /// 終わったプレイの記録と最高記録を保存する
+    fn finish_run(&self, machine: &mut WalkTheDogStateMachine) {
+        if let Err(err) = self.replays.save(&self.recording) {
+            error!("Could not save replay {:#?}", err);
+        }
+        if let WalkTheDogStateMachine::GameOver(state) = machine {
+            match score::record_score(
+                self.scores.as_ref(),
+                &state.scene.score_key(),
+                state.scene.count,
+            ) {
+                Ok(best_score) => state._state.best_score = best_score,
+                Err(err) => {
+                    error!("Could not save best score {:#?}", err);
+                }
+            }
+        }
+    }
+
     pub fn new() -> Self {
-        WalkTheDog { machine: None }
+        WalkTheDog {
+            machine: None,
+            key_bindings: KeyBindings::default(),
+            replays: Rc::new(LocalStorageReplays),
+            recording: Replay::new(0, SegmentSource::Library),
+            playback: None,
+            scores: Rc::new(LocalStorageScores),
+            pause_held: false,
+        }
     }
 }
 
+/// RedHatBoyの動きに合わせて鳴らす音声
+pub struct RedHatBoySounds {
+    jump: Sound,
+    background_music: Sound,
+}
+
 pub struct RedHatBoy {
     state_machine: RedHatBoyStateMachine,
     sprite_sheet: Sheet,
-    image: HtmlImageElement,
+    image: ImageHandle,
+    audio: Rc<dyn Audio>,
+    sounds: RedHatBoySounds,
+    /// 前の[`RedHatBoy::update`]を始めた時の当たり判定。
+    /// 障害物はその後で動くので、障害物から見た位置に直してある
+    previous_box: Rect,
+    /// 前の[`RedHatBoy::update`]で、障害物から見て動いた量。
+    /// 障害物が逆向きに動くので、xは走る速さになる
+    movement: Point,
+    /// 前の[`RedHatBoy::update`]を始めた時の足の下端の高さ。
+    /// 当たり判定の下端は絵によって変わるので、上から来たかはこちらで決める
+    previous_bottom: i16,
+    /// 開発用のチートで、障害物にぶつかっても倒れない
+    #[cfg(feature = "dev-tools")]
+    invulnerable: bool,
 }
 
 impl RedHatBoy {
-    fn new(sheet: Sheet, image: HtmlImageElement) -> Self {
+    fn new(
+        sheet: Sheet,
+        image: ImageHandle,
+        audio: Rc<dyn Audio>,
+        sounds: RedHatBoySounds,
+    ) -> Self {
         RedHatBoy {
             state_machine: RedHatBoyStateMachine::Idle(RedHatBoyState::new()),
             sprite_sheet: sheet,
             image,
+            audio,
+            sounds,
+            previous_box: Rect::new_from_x_y(0, 0, 0, 0),
+            movement: Point { x: 0, y: 0 },
+            previous_bottom: 0,
+            #[cfg(feature = "dev-tools")]
+            invulnerable: false,
         }
     }
     fn reset(boy: Self) -> Self {
-        RedHatBoy::new(boy.sprite_sheet, boy.image)
+        RedHatBoy {
+            #[cfg(feature = "dev-tools")]
+            invulnerable: boy.invulnerable,
+            ..RedHatBoy::new(boy.sprite_sheet, boy.image, boy.audio, boy.sounds)
+        }
     }
-    fn draw(&self, renderer: &Renderer) {
+    fn draw(&self, renderer: &dyn Renderer, debug: &DebugSettings) {
         let cell = self.current_sprite().expect("Cell not found");
 
         renderer.draw_image(&self.image, &cell.rect(), &self.destination_box());
-        if cfg!(debug_assertions) {
-            renderer.draw_rect(&self.bounding_box(), (0, 0, 255));
+        let bounding_box = self.bounding_box();
+        if debug.hitboxes {
+            renderer.draw_rect(&bounding_box, (0, 0, 255));
+        }
+        if debug.velocities {
+            // 1tickの速度では短くて見えないので伸ばして描く
+            const VELOCITY_SCALE: i16 = 4;
+            let velocity = self.state_machine.context().velocity;
+            let center = Point {
+                x: bounding_box.x() + bounding_box.width / 2,
+                y: bounding_box.y() + bounding_box.height / 2,
+            };
+            renderer.draw_line(
+                &center,
+                &Point {
+                    x: center.x + velocity.x * VELOCITY_SCALE,
+                    y: center.y + velocity.y * VELOCITY_SCALE,
+                },
+                (255, 255, 0),
+            );
+        }
+        if debug.state_names {
+            if let Err(err) = renderer.draw_text(
+                self.state_machine.name(),
+                &Point {
+                    x: bounding_box.x(),
+                    y: bounding_box.y() - 10,
+                },
+            ) {
+                error!("Could not draw state name {:#?}", err);
+            }
         }
     }
     fn current_sprite(&self) -> Option<&Cell> {
@@ -489,43 +1026,91 @@ impl RedHatBoy {
             self.state_machine.context().position.y,
         )
     }
+    /// 絵の矩形から、左、上、右の余白を削った当たり判定。
+    /// スライディング中は帽子の上を削って低くし、伸ばした足まで含めて横に長くする
     fn bounding_box(&self) -> Rect {
-        const X_OFFSET: i16 = 18;
-        const Y_OFFSET: i16 = 14;
-        const WIDTH_OFFSET: i16 = 28;
+        let (x_offset, y_offset, width_offset) = match self.state_machine {
+            RedHatBoyStateMachine::Sliding(_) => (10, 40, 18),
+            _ => (18, 14, 28),
+        };
         let destination_box = self.destination_box();
         Rect::new_from_x_y(
-            destination_box.x() + X_OFFSET,
-            destination_box.y() + Y_OFFSET,
-            destination_box.width - WIDTH_OFFSET,
-            destination_box.height - Y_OFFSET,
+            destination_box.x() + x_offset,
+            destination_box.y() + y_offset,
+            destination_box.width - width_offset,
+            destination_box.height - y_offset,
+        )
+    }
+    /// 足元の高さ1の矩形。これと重なる足場に乗っている
+    fn feet(&self) -> Rect {
+        let bounding_box = self.bounding_box();
+        Rect::new_from_x_y(
+            bounding_box.x(),
+            self.state_machine.context().bottom(),
+            bounding_box.width,
+            1,
         )
     }
     fn pos_y(&self) -> i16 {
         self.state_machine.context().position.y
     }
+    /// 障害物にぶつかって倒れているところか、倒れた後
+    fn is_knocked_out(&self) -> bool {
+        matches!(
+            self.state_machine,
+            RedHatBoyStateMachine::Falling(_) | RedHatBoyStateMachine::KnockedOut(_)
+        )
+    }
     fn velocity_y(&self) -> i16 {
         self.state_machine.context().velocity.y
     }
+    /// 足場の下側に頭をぶつけ、`distance`だけ押し下げられて上向きの速さがなくなる
+    fn bump_head(&mut self, distance: i16) {
+        self.state_machine = self.state_machine.transition(Event::BumpHead(distance));
+    }
+    /// 足場に重なった分だけ、`translation`動かして押し出される
+    fn push_out(&mut self, translation: Point) {
+        self.state_machine = self.state_machine.transition(Event::PushOut(translation));
+    }
     /// RedHatBoy以外のものを逆方向に水平に動かすために必要なRedHatBoyのx速度
     fn walking_speed(&self) -> i16 {
         self.state_machine.context().velocity.x
     }
     fn update(&mut self) {
+        let before = *self.state_machine.context();
+        let previous_box = self.bounding_box();
+        self.previous_bottom = before.bottom();
         self.state_machine = self.state_machine.transition(Event::Update);
+        let after = self.state_machine.context();
+        self.movement = Point {
+            x: before.velocity.x + after.position.x - before.position.x,
+            y: after.position.y - before.position.y,
+        };
+        // 他のものは左へ動くので、前の当たり判定はその分だけ左にあったことにする
+        self.previous_box = previous_box.translate(-before.velocity.x, 0);
+    }
+    /// 前のtickの位置から今の位置まで動く間に、`other`とぶつかったか。
+    ///
+    /// 絵が変わると当たり判定の形も変わるので、動く間は前の当たり判定の形のまま動かす。
+    /// 形が変わっただけで重なった時は、動き終わりに重なり始めたものとし、面はわからない
+    fn sweep(&self, other: &Rect) -> Option<Sweep> {
+        self.previous_box.sweep(self.movement, other).or_else(|| {
+            self.bounding_box().intersects(other).then_some(Sweep {
+                time: 1.0,
+                normal: Point { x: 0, y: 0 },
+            })
+        })
+    }
+    fn run_right(&mut self) {
+        self.state_machine = self.state_machine.transition(Event::Run(
+            self.audio.as_ref(),
+            &self.sounds.background_music,
+        ));
     }
-    fn run_right(
-        &mut self,
-        audio: Rc<Audio>,
-        music: Rc<Sound>,
-        sound_nodes: &mut HashMap<String, Rc<AudioBufferSourceNode>>,
-    ) {
+    fn jump(&mut self) {
         self.state_machine = self
             .state_machine
-            .transition(Event::Run(audio, music, sound_nodes));
-    }
-    fn jump(&mut self, audio: Rc<Audio>, sound: Rc<Sound>) {
-        self.state_machine = self.state_machine.transition(Event::Jump(audio, sound));
+            .transition(Event::Jump(self.audio.as_ref(), &self.sounds.jump));
     }
     fn slide(&mut self) {
         self.state_machine = self.state_machine.transition(Event::Slide);
@@ -533,8 +1118,23 @@ impl RedHatBoy {
     fn land_on(&mut self, position_y: i16) {
         self.state_machine = self.state_machine.transition(Event::Land(position_y));
     }
-    fn knock_out(&mut self, sound_nodes: HashMap<String, Rc<AudioBufferSourceNode>>) {
-        self.state_machine = self.state_machine.transition(Event::KnockOut(sound_nodes));
+    /// どの足場にも乗っていない。床の上にいなければ落ち始める
+    fn lose_support(&mut self) {
+        self.state_machine = self.state_machine.transition(Event::LoseSupport);
+    }
+    /// 走っている間だけ、走る速さを変える
+    fn set_running_speed(&mut self, speed: i16) {
+        self.state_machine = self.state_machine.transition(Event::SetSpeed(speed));
+    }
+    fn knock_out(&mut self) {
+        #[cfg(feature = "dev-tools")]
+        if self.invulnerable {
+            return;
+        }
+        self.state_machine = self.state_machine.transition(Event::KnockOut(
+            self.audio.as_ref(),
+            &self.sounds.background_music,
+        ));
     }
 }
 
@@ -544,6 +1144,7 @@ enum RedHatBoyStateMachine {
     Running(RedHatBoyState<Running>),
     Jumping(RedHatBoyState<Jumping>),
     Sliding(RedHatBoyState<Sliding>),
+    Airborne(RedHatBoyState<Airborne>),
     Falling(RedHatBoyState<Falling>),
     KnockedOut(RedHatBoyState<KnockedOut>),
 }
@@ -572,6 +1173,12 @@ impl From<RedHatBoyState<Sliding>> for RedHatBoyStateMachine {
     }
 }
 
+impl From<RedHatBoyState<Airborne>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Airborne>) -> Self {
+        RedHatBoyStateMachine::Airborne(state)
+    }
+}
+
 impl From<RedHatBoyState<Falling>> for RedHatBoyStateMachine {
     fn from(state: RedHatBoyState<Falling>) -> Self {
         RedHatBoyStateMachine::Falling(state)
@@ -585,23 +1192,30 @@ impl From<RedHatBoyState<KnockedOut>> for RedHatBoyStateMachine {
 }
 
 pub enum Event<'a> {
-    Run(
-        Rc<Audio>,
-        Rc<Sound>,
-        &'a mut HashMap<String, Rc<AudioBufferSourceNode>>,
-    ),
-    Jump(Rc<Audio>, Rc<Sound>),
+    /// 走り出し、BGMを鳴らす
+    Run(&'a dyn Audio, &'a Sound),
+    /// ジャンプし、効果音を鳴らす
+    Jump(&'a dyn Audio, &'a Sound),
     Slide,
-    KnockOut(HashMap<String, Rc<AudioBufferSourceNode>>),
+    /// 倒れ、BGMを止める
+    KnockOut(&'a dyn Audio, &'a Sound),
     Land(i16),
+    /// 足場の端から足を踏み外す
+    LoseSupport,
+    /// 足場の下側に頭をぶつける
+    BumpHead(i16),
+    /// 足場に重なった分だけ押し出される
+    PushOut(Point),
+    /// 走る速さを変える
+    SetSpeed(i16),
     Update,
 }
 
 impl RedHatBoyStateMachine {
     fn transition(self, event: Event) -> Self {
         match (self, event) {
-            (RedHatBoyStateMachine::Idle(state), Event::Run(audio, music, sound_nodes)) => {
-                state.run(audio, music, sound_nodes).into()
+            (RedHatBoyStateMachine::Idle(state), Event::Run(audio, music)) => {
+                state.run(audio, music).into()
             }
             (RedHatBoyStateMachine::Running(state), Event::Jump(audio, sound)) => {
                 state.jump(audio, sound).into()
@@ -619,19 +1233,63 @@ impl RedHatBoyStateMachine {
             (RedHatBoyStateMachine::Sliding(state), Event::Land(position_y)) => {
                 state.land_on(position_y).into()
             }
-            (RedHatBoyStateMachine::Running(state), Event::KnockOut(sound_nodes)) => {
-                state.knock_out(sound_nodes).into()
+            (RedHatBoyStateMachine::Airborne(state), Event::Land(position_y)) => {
+                state.land_on(position_y).into()
+            }
+            (RedHatBoyStateMachine::Running(state), Event::LoseSupport)
+                if !state.context().is_on_floor() =>
+            {
+                state.lose_support().into()
+            }
+            (RedHatBoyStateMachine::Sliding(state), Event::LoseSupport)
+                if !state.context().is_on_floor() =>
+            {
+                state.lose_support().into()
+            }
+            (RedHatBoyStateMachine::Running(state), Event::KnockOut(audio, music)) => {
+                state.knock_out(audio, music).into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::KnockOut(audio, music)) => {
+                state.knock_out(audio, music).into()
+            }
+            (RedHatBoyStateMachine::Sliding(state), Event::KnockOut(audio, music)) => {
+                state.knock_out(audio, music).into()
+            }
+            (RedHatBoyStateMachine::Airborne(state), Event::KnockOut(audio, music)) => {
+                state.knock_out(audio, music).into()
             }
-            (RedHatBoyStateMachine::Jumping(state), Event::KnockOut(sound_nodes)) => {
-                state.knock_out(sound_nodes).into()
+            (RedHatBoyStateMachine::Running(state), Event::SetSpeed(speed)) => {
+                state.set_running_speed(speed).into()
             }
-            (RedHatBoyStateMachine::Sliding(state), Event::KnockOut(sound_nodes)) => {
-                state.knock_out(sound_nodes).into()
+            (RedHatBoyStateMachine::Jumping(state), Event::SetSpeed(speed)) => {
+                state.set_running_speed(speed).into()
+            }
+            (RedHatBoyStateMachine::Sliding(state), Event::SetSpeed(speed)) => {
+                state.set_running_speed(speed).into()
+            }
+            (RedHatBoyStateMachine::Airborne(state), Event::SetSpeed(speed)) => {
+                state.set_running_speed(speed).into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::BumpHead(distance)) => {
+                state.bump_head(distance).into()
+            }
+            (RedHatBoyStateMachine::Running(state), Event::PushOut(translation)) => {
+                state.push_out(translation).into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::PushOut(translation)) => {
+                state.push_out(translation).into()
+            }
+            (RedHatBoyStateMachine::Sliding(state), Event::PushOut(translation)) => {
+                state.push_out(translation).into()
+            }
+            (RedHatBoyStateMachine::Airborne(state), Event::PushOut(translation)) => {
+                state.push_out(translation).into()
             }
             (RedHatBoyStateMachine::Idle(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Running(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Jumping(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Sliding(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Airborne(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Falling(state), Event::Update) => state.update().into(),
             _ => self,
         }
@@ -639,6 +1297,18 @@ impl RedHatBoyStateMachine {
 }
 
 impl RedHatBoyStateMachine {
+    /// 開発用に表示する状態の名前
+    fn name(&self) -> &'static str {
+        match self {
+            RedHatBoyStateMachine::Idle(_) => "Idle",
+            RedHatBoyStateMachine::Running(_) => "Running",
+            RedHatBoyStateMachine::Jumping(_) => "Jumping",
+            RedHatBoyStateMachine::Sliding(_) => "Sliding",
+            RedHatBoyStateMachine::Airborne(_) => "Airborne",
+            RedHatBoyStateMachine::Falling(_) => "Falling",
+            RedHatBoyStateMachine::KnockedOut(_) => "KnockedOut",
+        }
+    }
     /**
      * あまり良い実装ではないがenumの仕組み上仕方ない
      */
@@ -648,6 +1318,7 @@ impl RedHatBoyStateMachine {
             RedHatBoyStateMachine::Running(state) => state.frame_name(),
             RedHatBoyStateMachine::Jumping(state) => state.frame_name(),
             RedHatBoyStateMachine::Sliding(state) => state.frame_name(),
+            RedHatBoyStateMachine::Airborne(state) => state.frame_name(),
             RedHatBoyStateMachine::Falling(state) => state.frame_name(),
             RedHatBoyStateMachine::KnockedOut(state) => state.frame_name(),
         }
@@ -661,6 +1332,7 @@ impl RedHatBoyStateMachine {
             RedHatBoyStateMachine::Running(state) => state.context(),
             RedHatBoyStateMachine::Jumping(state) => state.context(),
             RedHatBoyStateMachine::Sliding(state) => state.context(),
+            RedHatBoyStateMachine::Airborne(state) => state.context(),
             RedHatBoyStateMachine::Falling(state) => state.context(),
             RedHatBoyStateMachine::KnockedOut(state) => state.context(),
         }
@@ -668,20 +1340,18 @@ impl RedHatBoyStateMachine {
 }
 
 mod red_hat_boy_states {
-    use std::{collections::HashMap, rc::Rc};
-
-    use web_sys::AudioBufferSourceNode;
-
     use crate::{
         engine::{Audio, Point, Sound},
         sound::Looping,
     };
 
-    use super::{RedHatBoyStateMachine, BACKGROUND_MUSIC_NODENAME, CANVAS_SIZE};
+    use super::{RedHatBoyStateMachine, CANVAS_SIZE};
 
     const FLOOR: i16 = 479;
     const PLAYER_HEIGHT: i16 = CANVAS_SIZE - FLOOR;
     const STATING_POINT: i16 = -20;
+    /// 押し戻された後、走っている間に1tickで走り始めの位置へ戻る距離
+    const RETURN_SPEED: i16 = 1;
     const IDLE_FRAME_NAME: &str = "Idle";
     const RUN_FRAME_NAME: &str = "Run";
     const JUMP_FRAME_NAME: &str = "Jump";
@@ -692,20 +1362,18 @@ mod red_hat_boy_states {
     const JUMPING_FRAME: u8 = 35; // 12 * 3 - 1
     const SLIDING_FRAME: u8 = 14; // 5 * 3 - 1
     const FALLING_FRAME: u8 = 29; // 10 * 3 - 1
-    const RUNNING_SPEED: i16 = 3;
-    const JUMPING_SPEED: i16 = -25;
+    /// 足場から落ちる時は、ジャンプのアニメーションの下りの部分を使う
+    const AIRBORNE_START_FRAME: u8 = 18; // 7 * 3 - 3
+    /// 走り始めの速さ
+    pub(crate) const RUNNING_SPEED: i16 = 3;
+    pub(crate) const JUMPING_SPEED: i16 = -25;
     /// 重力加速度
     ///
     /// 1フレームにy速度がどれだけ加速するか
-    const GRAVITY: i16 = 1;
-    /// 落下スピードの終端速度
     ///
-    /// 落下スピードが高くなっていくと、
-    /// 1フレームでの移動がブロックの幅を超える。
-    /// するとブロックに着地せずにすり抜けてしまう
-    /// そうならないように、落下速度の最高速度を決める
-    /// 実際の落下でも空気抵抗により落下速度の加速は最終的な速度までで止まる
-    const TERMINAL_VELOCITY_Y: i16 = 20;
+    /// 落下が速くなって1フレームでの移動がブロックの厚さを超えても、
+    /// 当たり判定は[`crate::engine::Rect::sweep`]で動いた道筋を見るので、すり抜けない
+    pub(crate) const GRAVITY: i16 = 1;
 
     #[derive(Copy, Clone)]
     pub struct RedHatBoyState<S> {
@@ -725,6 +1393,11 @@ mod red_hat_boy_states {
     #[derive(Copy, Clone)]
     pub struct Sliding;
 
+    /// 足場の端から落ちている
+    #[derive(Copy, Clone)]
+    pub struct Airborne;
+
+    /// 障害物にぶつかって倒れている
     #[derive(Copy, Clone)]
     pub struct Falling;
 
@@ -736,6 +1409,7 @@ mod red_hat_boy_states {
             RedHatBoyState {
                 context: RedHatBoyContext {
                     frame: 0,
+                    frame_progress: 0,
                     position: Point {
                         x: STATING_POINT,
                         y: FLOOR,
@@ -755,14 +1429,8 @@ mod red_hat_boy_states {
             self
         }
 
-        pub fn run(
-            self,
-            audio: Rc<Audio>,
-            music: Rc<Sound>,
-            sound_nodes: &mut HashMap<String, Rc<AudioBufferSourceNode>>,
-        ) -> RedHatBoyState<Running> {
-            let audio_node = audio.play_sound(&music, Looping::Yes).unwrap();
-            sound_nodes.insert(String::from(BACKGROUND_MUSIC_NODENAME), Rc::new(audio_node));
+        pub fn run(self, audio: &dyn Audio, music: &Sound) -> RedHatBoyState<Running> {
+            audio.play_sound(music, Looping::Yes).unwrap();
             RedHatBoyState {
                 context: self.context.reset_frame().run_right(),
                 _state: Running {},
@@ -783,12 +1451,12 @@ mod red_hat_boy_states {
         }
 
         pub fn update(mut self) -> Self {
-            self.context = self.context.update(RUNNING_FRAME);
+            self.context = self.context.update_running(RUNNING_FRAME);
             self
         }
 
-        pub fn jump(self, audio: Rc<Audio>, sound: Rc<Sound>) -> RedHatBoyState<Jumping> {
-            audio.play_sound(&sound, Looping::No).unwrap();
+        pub fn jump(self, audio: &dyn Audio, sound: &Sound) -> RedHatBoyState<Jumping> {
+            audio.play_sound(sound, Looping::No).unwrap();
             RedHatBoyState {
                 context: self
                     .context
@@ -811,6 +1479,13 @@ mod red_hat_boy_states {
                 _state: Running {},
             }
         }
+
+        pub fn lose_support(self) -> RedHatBoyState<Airborne> {
+            RedHatBoyState {
+                context: self.context.drop_off(),
+                _state: Airborne {},
+            }
+        }
     }
 
     impl RedHatBoyState<Jumping> {
@@ -833,6 +1508,11 @@ mod red_hat_boy_states {
                 _state: Running {},
             }
         }
+
+        pub fn bump_head(mut self, distance: i16) -> Self {
+            self.context = self.context.move_down(distance);
+            self
+        }
     }
 
     pub enum JumpingEndstate {
@@ -876,6 +1556,13 @@ mod red_hat_boy_states {
                 _state: Sliding {},
             }
         }
+
+        pub fn lose_support(self) -> RedHatBoyState<Airborne> {
+            RedHatBoyState {
+                context: self.context.drop_off(),
+                _state: Airborne {},
+            }
+        }
     }
 
     pub enum SlidingEndState {
@@ -892,6 +1579,42 @@ mod red_hat_boy_states {
         }
     }
 
+    impl RedHatBoyState<Airborne> {
+        pub fn frame_name(&self) -> &str {
+            JUMP_FRAME_NAME
+        }
+
+        pub fn update(mut self) -> AirborneEndState {
+            self.context = self.context.update_without_looping(JUMPING_FRAME);
+            if self.context.is_on_floor() {
+                AirborneEndState::Complete(self.land_on(CANVAS_SIZE))
+            } else {
+                AirborneEndState::Airborne(self)
+            }
+        }
+
+        pub fn land_on(self, position_y: i16) -> RedHatBoyState<Running> {
+            RedHatBoyState {
+                context: self.context.reset_frame().set_on(position_y),
+                _state: Running {},
+            }
+        }
+    }
+
+    pub enum AirborneEndState {
+        Complete(RedHatBoyState<Running>),
+        Airborne(RedHatBoyState<Airborne>),
+    }
+
+    impl From<AirborneEndState> for RedHatBoyStateMachine {
+        fn from(end_state: AirborneEndState) -> Self {
+            match end_state {
+                AirborneEndState::Complete(running_state) => running_state.into(),
+                AirborneEndState::Airborne(airborne_state) => airborne_state.into(),
+            }
+        }
+    }
+
     impl RedHatBoyState<Falling> {
         pub fn frame_name(&self) -> &str {
             FALLING_FRAME_NAME
@@ -933,22 +1656,19 @@ mod red_hat_boy_states {
         pub fn context(&self) -> &RedHatBoyContext {
             &self.context
         }
-        pub fn knock_out(
-            &self,
-            mut sound_nodes: HashMap<String, Rc<AudioBufferSourceNode>>,
-        ) -> RedHatBoyState<Falling> {
-            let key = String::from(BACKGROUND_MUSIC_NODENAME);
-            if let Some(background_music_node) = sound_nodes.get(&key) {
-                background_music_node
-                    .stop()
-                    .expect("Can not stop background music!");
-                sound_nodes.remove(&key);
-            } else {
-                log!("cannot find background music node!");
-                sound_nodes.iter().for_each(|(k, _)| {
-                    log!("{}", k);
-                });
-            }
+        pub fn set_running_speed(mut self, speed: i16) -> Self {
+            self.context = self.context.set_horizontal_velocity(speed);
+            self
+        }
+        pub fn push_out(mut self, translation: Point) -> Self {
+            self.context.position.x += translation.x;
+            self.context.position.y += translation.y;
+            self
+        }
+        pub fn knock_out(&self, audio: &dyn Audio, music: &Sound) -> RedHatBoyState<Falling> {
+            audio
+                .stop_sound(music)
+                .expect("Can not stop background music!");
             RedHatBoyState {
                 context: self.context.reset_frame().stop(),
                 _state: Falling {},
@@ -959,6 +1679,8 @@ mod red_hat_boy_states {
     #[derive(Copy, Clone)]
     pub struct RedHatBoyContext {
         pub frame: u8,
+        /// 走る時にコマを進めるために貯めている速さ。[`RUNNING_SPEED`]貯まるとコマを1つ進める
+        pub frame_progress: i16,
         pub position: Point,
         pub velocity: Point,
     }
@@ -974,25 +1696,73 @@ mod red_hat_boy_states {
         /// RedHatBoyは同じ場所を走り続け、背景やその他のオブジェクトが[`self.velocity.x`]に従って逆方向に動くことで動きが実現される。
         ///
         /// * `frame_count` - [`self::frame`]の折り返し。この数字に達したら[`self.frame`]は0にリセットされる。
-        pub fn update(mut self, frame_count: u8) -> Self {
+        pub fn update(self, frame_count: u8) -> Self {
+            self.advance_frame(frame_count)
+                .move_vertically()
+                .return_to_start()
+        }
+        /// 走っている時の[`RedHatBoyContext::update`]。
+        ///
+        /// 走る速さが[`RUNNING_SPEED`]より速いほど、1tickで進めるコマを増やす。
+        pub fn update_running(mut self, frame_count: u8) -> Self {
+            self.frame_progress += self.velocity.x;
+            let frames = self.frame_progress / RUNNING_SPEED;
+            self.frame_progress %= RUNNING_SPEED;
+            (0..frames)
+                .fold(self, |context, _| context.advance_frame(frame_count))
+                .move_vertically()
+                .return_to_start()
+        }
+        /// [`RedHatBoyContext::update`]と同じだが、最後のコマまで進んだらそこで止める
+        pub fn update_without_looping(self, frame_count: u8) -> Self {
             if self.frame < frame_count {
-                self.frame += 1;
+                self.advance_frame(frame_count)
             } else {
-                self.frame = 0;
+                self
             }
-            self.position.y += self.velocity.y;
-            if self.position.y > FLOOR {
-                self.position.y = FLOOR;
+            .move_vertically()
+            .return_to_start()
+        }
+        /// 足の下端の高さ
+        pub fn bottom(&self) -> i16 {
+            self.position.y + PLAYER_HEIGHT
+        }
+        pub fn is_on_floor(&self) -> bool {
+            self.position.y >= FLOOR
+        }
+        fn advance_frame(mut self, frame_count: u8) -> Self {
+            if self.frame < frame_count {
+                self.frame += 1;
+            } else {
+                self.frame = 0;
+            }
+            self
+        }
+        fn move_vertically(mut self) -> Self {
+            self.position.y += self.velocity.y;
+            if self.position.y > FLOOR {
+                self.position.y = FLOOR;
                 self.velocity.y = 0;
             }
             self.velocity.y += GRAVITY;
-            if self.velocity.y > TERMINAL_VELOCITY_Y {
-                self.velocity.y = TERMINAL_VELOCITY_Y;
+            self
+        }
+        /// 走っている間は、押し戻された分を少しずつ取り戻して走り始めの位置へ戻る
+        fn return_to_start(mut self) -> Self {
+            if self.velocity.x > 0 && self.position.x < STATING_POINT {
+                self.position.x = (self.position.x + RETURN_SPEED).min(STATING_POINT);
             }
             self
         }
         fn reset_frame(mut self) -> Self {
             self.frame = 0;
+            self.frame_progress = 0;
+            self
+        }
+        /// 足場から落ち始める。落ちる速さは足場の上で溜まった分を引き継ぐ
+        fn drop_off(mut self) -> Self {
+            self.frame = AIRBORNE_START_FRAME;
+            self.frame_progress = 0;
             self
         }
         fn stop(mut self) -> Self {
@@ -1004,6 +1774,16 @@ mod red_hat_boy_states {
             self.velocity.x += RUNNING_SPEED;
             self
         }
+        fn set_horizontal_velocity(mut self, x: i16) -> Self {
+            self.velocity.x = x;
+            self
+        }
+        /// `distance`だけ下に動かし、上に向かう速さをなくす
+        fn move_down(mut self, distance: i16) -> Self {
+            self.position.y += distance;
+            self.velocity.y = self.velocity.y.max(0);
+            self
+        }
         fn set_vertical_velocity(mut self, y: i16) -> Self {
             self.velocity.y = y;
             self
@@ -1020,12 +1800,10 @@ mod red_hat_boy_states {
 }
 
 pub trait Obstacle<T> {
-    fn check_intersection(
-        &self,
-        rhb: &mut T,
-        sound_nodes: HashMap<String, Rc<AudioBufferSourceNode>>,
-    );
-    fn draw(&self, renderer: &Renderer);
+    fn check_intersection(&self, rhb: &mut T);
+    /// `rhb`が上に乗っているか
+    fn supports(&self, rhb: &T) -> bool;
+    fn draw(&self, renderer: &dyn Renderer, debug: &DebugSettings);
     fn move_horisontally(&mut self, distance: i16);
     fn right(&self) -> i16;
 }
@@ -1035,11 +1813,52 @@ struct Sprite {
     offset: Point,
 }
 
+impl Sprite {
+    /// `sheet`の`sprite_names`のタイルを、それぞれ`offsets`の位置に並べる。
+    /// シートにない名前は飛ばす
+    fn from_names(sheet: &SpriteSheet, sprite_names: &[&str], offsets: &[Point]) -> Vec<Sprite> {
+        sprite_names
+            .iter()
+            .filter_map(|sprite_name| sheet.cell(sprite_name).cloned())
+            .zip(offsets.iter())
+            .map(|(cell, offset)| Sprite {
+                cell,
+                offset: *offset,
+            })
+            .collect()
+    }
+
+    /// `sprites`を、`position`からのそれぞれの位置に描く
+    fn draw_all(sprites: &[Sprite], sheet: &SpriteSheet, renderer: &dyn Renderer, position: Point) {
+        sprites.iter().for_each(|sprite| {
+            sheet.draw(
+                renderer,
+                &sprite.cell.rect(),
+                &sprite
+                    .cell
+                    .rect_start_x_y(position.x + sprite.offset.x, position.y + sprite.offset.y),
+            );
+        });
+    }
+}
+
+/// 足場の横側にぶつかった時にどうなるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlatformSides {
+    /// 石と同じく倒れる
+    #[default]
+    KnockOut,
+    /// 倒れずに押し戻される。画面の左端より外に押し出されたら倒れる
+    PushBack,
+}
+
 pub struct Platform {
     sheet: Rc<SpriteSheet>,
     bounding_boxes: Vec<Rect>,
     sprites: Vec<Sprite>,
     position: Point,
+    sides: PlatformSides,
 }
 
 impl Platform {
@@ -1061,57 +1880,94 @@ impl Platform {
                 )
             })
             .collect();
-        let sprites = sprite_names
-            .iter()
-            .filter_map(|sprite_name| sheet.cell(sprite_name).cloned())
-            .zip(offsets.iter())
-            .map(|(cell, offset)| Sprite {
-                cell,
-                offset: *offset,
-            })
-            .collect();
+        let sprites = Sprite::from_names(&sheet, sprite_names, offsets);
         Platform {
             sheet,
             bounding_boxes,
             sprites,
             position,
+            sides: PlatformSides::default(),
         }
     }
+
+    /// 横側にぶつかった時の動きを`sides`にする
+    pub fn with_sides(mut self, sides: PlatformSides) -> Self {
+        self.sides = sides;
+        self
+    }
+
+    /// 画面上の当たり判定
+    pub fn bounding_boxes(&self) -> &[Rect] {
+        &self.bounding_boxes
+    }
 }
 
+/// 前のtickの足がこれだけ足場の上端より下でも、上から来たものとする。
+/// 走っている間は絵によって当たり判定が足場に届かず、数tickの間に少し沈むことがある
+const LANDING_TOLERANCE: i16 = 4;
+
 impl Obstacle<RedHatBoy> for Platform {
-    fn draw(&self, renderer: &Renderer) {
-        self.sprites.iter().for_each(|sprite| {
-            self.sheet.draw(
-                renderer,
-                &sprite.cell.rect(),
-                &sprite.cell.rect_start_x_y(
-                    self.position.x + sprite.offset.x,
-                    self.position.y + sprite.offset.y,
-                ),
-            );
-        });
-        if cfg!(debug_assertions) {
+    fn draw(&self, renderer: &dyn Renderer, debug: &DebugSettings) {
+        Sprite::draw_all(&self.sprites, &self.sheet, renderer, self.position);
+        if debug.hitboxes {
             self.bounding_boxes.iter().for_each(|bounding_box| {
                 renderer.draw_rect(bounding_box, (255, 255, 255));
             });
         }
     }
-    fn check_intersection(
-        &self,
-        rhb: &mut RedHatBoy,
-        _: HashMap<String, Rc<AudioBufferSourceNode>>,
-    ) {
-        if let Some(box_to_land_on) = self
-            .bounding_boxes
-            .iter()
-            .find(|&bounding_box| rhb.bounding_box().intersects(bounding_box))
-        {
-            if rhb.velocity_y() > 0 && rhb.pos_y() < self.position.y {
-                rhb.land_on(box_to_land_on.y());
+    /// 上から落ちてきたら乗せ、下から跳んできたら頭をぶつけさせ、横からぶつかったら
+    /// [`PlatformSides`]に従って倒すか押し戻す。
+    ///
+    /// 上に乗る時以外は、[`Rect::sweep`]でわかった面からぶつかったものとし、その面から
+    /// 押し出す。どの面からかわからない時は、[`Rect::overlap`]で一番短く押し出せる面を使う。
+    fn check_intersection(&self, rhb: &mut RedHatBoy) {
+        for bounding_box in &self.bounding_boxes {
+            let Some(sweep) = rhb.sweep(bounding_box) else {
+                continue;
+            };
+            // 前のtickで足が上端より上にあれば、上から来ている。絵の上端では決めない
+            if rhb.velocity_y() >= 0 && rhb.previous_bottom <= bounding_box.y() + LANDING_TOLERANCE
+            {
+                rhb.land_on(bounding_box.y());
+                continue;
+            }
+            let rhb_box = rhb.bounding_box();
+            let contact = match rhb_box.penetration(bounding_box, sweep.normal) {
+                Some(depth) => Some(Overlap {
+                    normal: sweep.normal,
+                    depth,
+                }),
+                None => rhb_box.overlap(bounding_box),
+            };
+            let Some(contact) = contact else {
+                continue;
+            };
+            if contact.normal.x == 0 {
+                if contact.normal.y > 0 && rhb.velocity_y() < 0 {
+                    rhb.bump_head(contact.depth);
+                } else {
+                    // 着地でも頭をぶつけたのでもないが、重なったままにはしない
+                    rhb.push_out(contact.translation());
+                }
+                continue;
+            }
+            match self.sides {
+                PlatformSides::KnockOut => rhb.knock_out(),
+                PlatformSides::PushBack => {
+                    rhb.push_out(contact.translation());
+                    if rhb.bounding_box().right() < 0 {
+                        rhb.knock_out();
+                    }
+                }
             }
         }
     }
+    fn supports(&self, rhb: &RedHatBoy) -> bool {
+        let feet = rhb.feet();
+        self.bounding_boxes
+            .iter()
+            .any(|bounding_box| feet.intersects(bounding_box))
+    }
     fn move_horisontally(&mut self, distance: i16) {
         self.position.x += distance;
         self.bounding_boxes.iter_mut().for_each(|bounding_box| {
@@ -1133,17 +1989,16 @@ pub struct Barrier {
 }
 
 impl Obstacle<RedHatBoy> for Barrier {
-    fn check_intersection(
-        &self,
-        rhb: &mut RedHatBoy,
-        sound_nodes: HashMap<String, Rc<AudioBufferSourceNode>>,
-    ) {
-        if rhb.bounding_box().intersects(self.collider.bounding_box()) {
-            rhb.knock_out(sound_nodes);
+    fn check_intersection(&self, rhb: &mut RedHatBoy) {
+        if rhb.sweep(self.collider.bounding_box()).is_some() {
+            rhb.knock_out();
         }
     }
-    fn draw(&self, renderer: &Renderer) {
-        self.collider.draw(renderer);
+    fn supports(&self, _rhb: &RedHatBoy) -> bool {
+        false
+    }
+    fn draw(&self, renderer: &dyn Renderer, debug: &DebugSettings) {
+        self.collider.draw(renderer, debug);
     }
     fn move_horisontally(&mut self, distance: i16) {
         self.collider.move_horisontally(distance);
@@ -1153,6 +2008,67 @@ impl Obstacle<RedHatBoy> for Barrier {
     }
 }
 
+/// 上から頭の高さまで垂れ下がっている障害物。ジャンプでは越えられず、
+/// スライディングで下をくぐるしかない。どこに触れても倒れるが、
+/// 下をくぐっている間は頭がつかえて立ち上がれず、抜けるまで滑り続ける
+pub struct OverheadBarrier {
+    sheet: Rc<SpriteSheet>,
+    sprites: Vec<Sprite>,
+    position: Point,
+    bounding_box: Rect,
+}
+
+impl OverheadBarrier {
+    /// タイルの位置と当たり判定は、`position`からの位置
+    pub fn new(
+        sheet: Rc<SpriteSheet>,
+        bounding_box: &Rect,
+        sprite_names: &[&str],
+        offsets: &[Point],
+        position: Point,
+    ) -> Self {
+        let sprites = Sprite::from_names(&sheet, sprite_names, offsets);
+        OverheadBarrier {
+            sheet,
+            sprites,
+            position,
+            bounding_box: bounding_box.translate(position.x, position.y),
+        }
+    }
+}
+
+impl Obstacle<RedHatBoy> for OverheadBarrier {
+    fn check_intersection(&self, rhb: &mut RedHatBoy) {
+        match rhb.sweep(&self.bounding_box) {
+            // 前のtickは下をくぐっていて、滑り終わって背が伸びただけ
+            Some(_)
+                if rhb.previous_box.y() >= self.bounding_box.bottom()
+                    && matches!(rhb.state_machine, RedHatBoyStateMachine::Running(_)) =>
+            {
+                rhb.slide();
+            }
+            Some(_) => rhb.knock_out(),
+            None => {}
+        }
+    }
+    fn supports(&self, _rhb: &RedHatBoy) -> bool {
+        false
+    }
+    fn draw(&self, renderer: &dyn Renderer, debug: &DebugSettings) {
+        Sprite::draw_all(&self.sprites, &self.sheet, renderer, self.position);
+        if debug.hitboxes {
+            renderer.draw_rect(&self.bounding_box, (255, 0, 0));
+        }
+    }
+    fn move_horisontally(&mut self, distance: i16) {
+        self.position.x += distance;
+        self.bounding_box.set_x(self.bounding_box.x() + distance);
+    }
+    fn right(&self) -> i16 {
+        self.bounding_box.right()
+    }
+}
+
 impl From<Image> for Barrier {
     fn from(image: Image) -> Self {
         Barrier {
@@ -1172,50 +2088,1378 @@ fn rightmost(obstacl_list: &[Box<dyn Obstacle<RedHatBoy>>]) -> i16 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::gesture::{Direction, Gesture};
+    use crate::engine::headless::{
+        AudioCommand, DrawCommand, HeadlessUi, RecordingAudio, RecordingRenderer,
+    };
+    use crate::replay::MemoryReplays;
+    use crate::score::MemoryScores;
+    use crate::segment::{
+        fixtures::{builder, stone, tiles_sheet},
+        selection::HARD_UNLOCK_DISTANCE,
+        Difficulty, SegmentBuilder,
+    };
+    use crate::sound::Looping;
     use futures::channel::mpsc::unbounded;
-    use std::collections::HashMap;
+    use red_hat_boy_states::RedHatBoyContext;
     use wasm_bindgen_test::wasm_bindgen_test;
 
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
-    /// 新しいゲームが始まった時にUIを消す。
-    ///
-    /// TODO 現在の設計の問題点
-    /// 新しいScene構造体を作るのが大変
-    /// gameモジュールは、web-sysやwasm-bindgenに強く依存しすぎている。
-    #[wasm_bindgen_test]
-    fn test_transition_from_game_over_to_new_game() {
-        // 準備
-        let (_, receiver) = unbounded();
-        let image = HtmlImageElement::new().unwrap();
+    /// デバッグ用の矩形と線を除いた描画命令
+    fn image_commands(renderer: &RecordingRenderer) -> Vec<DrawCommand> {
+        renderer
+            .commands()
+            .into_iter()
+            .filter(|command| {
+                !matches!(
+                    command,
+                    DrawCommand::DrawRect { .. } | DrawCommand::DrawLine { .. }
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stone_and_platform_draws_stone_and_three_platform_tiles() {
+        let renderer = RecordingRenderer::new();
+        let obstacles = stone_and_platform(stone(), tiles_sheet(), CANVAS_SIZE);
+
+        obstacles
+            .iter()
+            .for_each(|obstacle| obstacle.draw(&renderer, &DebugSettings::default()));
+
+        assert_eq!(
+            image_commands(&renderer),
+            vec![
+                DrawCommand::DrawEntireImage {
+                    image: "Stone.png".into(),
+                    position: Point { x: 700, y: 546 },
+                },
+                DrawCommand::DrawImage {
+                    image: "tiles.png".into(),
+                    frame: Rect::new_from_x_y(261, 391, 128, 93),
+                    destination: Rect::new_from_x_y(750, 420, 128, 93),
+                },
+                DrawCommand::DrawImage {
+                    image: "tiles.png".into(),
+                    frame: Rect::new_from_x_y(391, 391, 128, 93),
+                    destination: Rect::new_from_x_y(878, 420, 128, 93),
+                },
+                DrawCommand::DrawImage {
+                    image: "tiles.png".into(),
+                    frame: Rect::new_from_x_y(521, 391, 128, 93),
+                    destination: Rect::new_from_x_y(1006, 420, 128, 93),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn moved_obstacles_are_drawn_at_new_position() {
+        let renderer = RecordingRenderer::new();
+        let mut obstacles = stone_and_platform(stone(), tiles_sheet(), CANVAS_SIZE);
+
+        obstacles
+            .iter_mut()
+            .for_each(|obstacle| obstacle.move_horisontally(-100));
+        obstacles
+            .iter()
+            .for_each(|obstacle| obstacle.draw(&renderer, &DebugSettings::default()));
+
+        let commands = image_commands(&renderer);
+        assert_eq!(
+            commands[0],
+            DrawCommand::DrawEntireImage {
+                image: "Stone.png".into(),
+                position: Point { x: 600, y: 546 },
+            }
+        );
+        assert_eq!(
+            commands[1],
+            DrawCommand::DrawImage {
+                image: "tiles.png".into(),
+                frame: Rect::new_from_x_y(261, 391, 128, 93),
+                destination: Rect::new_from_x_y(650, 420, 128, 93),
+            }
+        );
+        assert_eq!(rightmost(&obstacles), 650 + 384);
+    }
+
+    fn test_scene(audio: Rc<dyn Audio>, ui: Rc<dyn Ui>) -> Scene {
         let rhb = RedHatBoy::new(
-            Sheet {
-                frames: HashMap::new(),
+            serde_json::from_str(include_str!("../static/rhb_trimmed.json")).unwrap(),
+            ImageHandle::headless("rhb_trimmed.png", 1024, 1024),
+            audio,
+            RedHatBoySounds {
+                jump: Sound::headless(JUMPING_SOUND_FILENAME),
+                background_music: Sound::headless(BACKGROUND_MUSIC_FILENAME),
             },
-            image.clone(),
         );
-        let sprite_sheet = SpriteSheet::new(
-            Sheet {
-                frames: HashMap::new(),
+        Scene::new(
+            rhb,
+            ImageHandle::headless("BG.png", 1000, 750),
+            stone(),
+            tiles_sheet(),
+            Rc::new(SegmentLibrary::from_json(include_str!("../static/segments.json")).unwrap()),
+            ui,
+            TEST_SEED,
+        )
+    }
+
+    const TEST_SEED: u64 = 42;
+
+    fn ready_game(audio: Rc<dyn Audio>, ui: Rc<dyn Ui>) -> WalkTheDog {
+        ready_game_with(test_scene(audio, ui))
+    }
+
+    fn ready_game_with(scene: Scene) -> WalkTheDog {
+        WalkTheDog {
+            machine: Some(WalkTheDogStateMachine::Ready(
+                WalkTheDogState::<Ready>::new(scene),
+            )),
+            key_bindings: KeyBindings::default(),
+            replays: Rc::new(MemoryReplays::default()),
+            recording: Replay::new(TEST_SEED, SegmentSource::Library),
+            playback: None,
+            scores: Rc::new(MemoryScores::default()),
+            pause_held: false,
+        }
+    }
+
+    /// GameLoopと同じく、更新の後にキーの変化を消す
+    fn tick(game: &mut WalkTheDog, key_state: &mut KeyState) {
+        game.update(key_state, &mut TimeControl::new());
+        key_state.tick();
+    }
+
+    fn update_until_game_over(game: &mut WalkTheDog, key_state: &mut KeyState) -> usize {
+        (1..=1000)
+            .find(|_| {
+                tick(game, key_state);
+                matches!(game.machine, Some(WalkTheDogStateMachine::GameOver(_)))
+            })
+            .expect("game never ended")
+    }
+
+    #[test]
+    fn ready_state_waits_for_arrow_right() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+
+        tick(&mut game, &mut key_state);
+        assert!(matches!(
+            game.machine,
+            Some(WalkTheDogStateMachine::Ready(_))
+        ));
+
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+        assert!(matches!(
+            game.machine,
+            Some(WalkTheDogStateMachine::Walking(_))
+        ));
+    }
+
+    #[test]
+    fn running_into_the_first_stone_ends_the_game() {
+        let audio = Rc::new(RecordingAudio::new());
+        let ui = Rc::new(HeadlessUi::new());
+        let mut game = ready_game(audio.clone(), ui.clone());
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+
+        update_until_game_over(&mut game, &mut key_state);
+
+        assert_eq!(
+            audio.commands(),
+            vec![
+                AudioCommand::Play {
+                    sound: BACKGROUND_MUSIC_FILENAME.into(),
+                    looping: Looping::Yes,
+                },
+                AudioCommand::Stop {
+                    sound: BACKGROUND_MUSIC_FILENAME.into(),
+                },
+            ]
+        );
+        assert_eq!(ui.visible_buttons(), vec![NEW_GAME_BUTTON_ID.to_string()]);
+        let Some(WalkTheDogStateMachine::GameOver(state)) = &game.machine else {
+            unreachable!();
+        };
+        assert!(matches!(
+            state.scene.rhb.state_machine,
+            RedHatBoyStateMachine::KnockedOut(_)
+        ));
+    }
+
+    #[test]
+    fn knock_out_requests_a_hit_stop_once() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        let mut time = TimeControl::new();
+
+        let mut hit_stops = 0;
+        while !matches!(game.machine, Some(WalkTheDogStateMachine::GameOver(_))) {
+            game.update(&key_state, &mut time);
+            key_state.tick();
+            if time.is_hit_stopped() {
+                hit_stops += 1;
+                time = TimeControl::new();
+            }
+        }
+
+        assert_eq!(hit_stops, 1);
+    }
+
+    #[test]
+    fn jumping_plays_the_jump_sound_and_leaves_the_floor() {
+        let audio = Rc::new(RecordingAudio::new());
+        let mut game = ready_game(audio.clone(), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+        key_state.set_released("ArrowRight");
+        key_state.set_pressed("ArrowUp");
+
+        tick(&mut game, &mut key_state);
+        tick(&mut game, &mut key_state);
+
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        assert!(matches!(
+            state.scene.rhb.state_machine,
+            RedHatBoyStateMachine::Jumping(_)
+        ));
+        assert!(state.scene.rhb.velocity_y() < 0);
+        assert!(audio.commands().contains(&AudioCommand::Play {
+            sound: JUMPING_SOUND_FILENAME.into(),
+            looping: Looping::No,
+        }));
+    }
+
+    #[test]
+    fn tap_starts_the_game_and_swipe_down_slides() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        Gesture::Tap.apply(&mut key_state);
+        tick(&mut game, &mut key_state);
+
+        Gesture::Swipe(Direction::Down).apply(&mut key_state);
+        tick(&mut game, &mut key_state);
+
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        assert!(matches!(
+            state.scene.rhb.state_machine,
+            RedHatBoyStateMachine::Sliding(_)
+        ));
+    }
+
+    #[test]
+    fn new_game_button_restarts_from_ready() {
+        let ui = Rc::new(HeadlessUi::new());
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), ui.clone());
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        update_until_game_over(&mut game, &mut key_state);
+        key_state.set_released("ArrowRight");
+
+        ui.click(NEW_GAME_BUTTON_ID).unwrap();
+        tick(&mut game, &mut key_state);
+
+        assert_eq!(
+            ui.visible_buttons(),
+            vec![ENDLESS_BUTTON_ID.to_string(), DAILY_BUTTON_ID.to_string()]
+        );
+        let Some(WalkTheDogStateMachine::Ready(state)) = &game.machine else {
+            panic!("game is not ready");
+        };
+        assert_eq!(state.scene.count, 0);
+        assert!(matches!(
+            state.scene.rhb.state_machine,
+            RedHatBoyStateMachine::Idle(_)
+        ));
+    }
+
+    #[test]
+    fn enter_key_restarts_from_ready() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        update_until_game_over(&mut game, &mut key_state);
+        key_state.set_released("ArrowRight");
+
+        key_state.set_pressed("Enter");
+        tick(&mut game, &mut key_state);
+
+        assert!(matches!(
+            game.machine,
+            Some(WalkTheDogStateMachine::Ready(_))
+        ));
+    }
+
+    #[test]
+    fn walking_scene_draws_background_boy_obstacles_and_time() {
+        let renderer = RecordingRenderer::new();
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+
+        game.draw(&renderer, 1.0, &DebugSettings::default());
+
+        let commands = image_commands(&renderer);
+        assert_eq!(
+            commands[0],
+            DrawCommand::Clear(Rect::new_from_x_y(0, 0, CANVAS_SIZE, CANVAS_SIZE))
+        );
+        assert_eq!(
+            commands[1..3],
+            [
+                DrawCommand::DrawEntireImage {
+                    image: "BG.png".into(),
+                    position: Point { x: 0, y: 0 },
+                },
+                DrawCommand::DrawEntireImage {
+                    image: "BG.png".into(),
+                    position: Point { x: 1000, y: 0 },
+                },
+            ]
+        );
+        assert!(matches!(
+            &commands[3],
+            DrawCommand::DrawImage { image, .. } if image == "rhb_trimmed.png"
+        ));
+        assert_eq!(
+            commands.last(),
+            Some(&DrawCommand::DrawText {
+                text: "Time: 00:00".into(),
+                location: Point { x: 20, y: 40 },
+            })
+        );
+    }
+
+    #[test]
+    fn debug_overlays_are_hidden_by_default() {
+        let renderer = RecordingRenderer::new();
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+
+        game.draw(&renderer, 1.0, &DebugSettings::default());
+
+        assert!(renderer.commands().iter().all(|command| !matches!(
+            command,
+            DrawCommand::DrawRect { .. } | DrawCommand::DrawLine { .. }
+        )));
+    }
+
+    #[test]
+    fn enabled_debug_overlays_are_drawn() {
+        let renderer = RecordingRenderer::new();
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+        let debug = DebugSettings {
+            hitboxes: true,
+            velocities: true,
+            state_names: true,
+            markers: true,
+            segment_boundaries: true,
+        };
+
+        game.draw(&renderer, 1.0, &debug);
+
+        let commands = renderer.commands();
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        let scene = &state.scene;
+        assert!(commands.contains(&DrawCommand::DrawRect {
+            rect: scene.rhb.bounding_box(),
+            color: (0, 0, 255),
+        }));
+        assert!(commands.contains(&DrawCommand::DrawText {
+            text: "Running".into(),
+            location: Point {
+                x: scene.rhb.bounding_box().x(),
+                y: scene.rhb.bounding_box().y() - 10,
             },
-            image.clone(),
+        }));
+        let lines: Vec<(i16, (u8, u8, u8))> = commands
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::DrawLine { from, to, color } if from.x == to.x => {
+                    Some((from.x, *color))
+                }
+                _ => None,
+            })
+            .collect();
+        assert!(lines.contains(&(scene.timeline, (255, 0, 255))));
+        assert!(lines.contains(&(scene.obstacles[0].right(), (0, 255, 0))));
+        assert!(lines.contains(&(scene.segment_starts[0], (0, 255, 255))));
+        // 走っているので、速度の線は右へ伸びる
+        assert!(commands.iter().any(|command| matches!(
+            command,
+            DrawCommand::DrawLine { from, to, color: (255, 255, 0) } if to.x > from.x
+        )));
+    }
+
+    #[test]
+    fn holding_jump_does_not_jump_again_after_landing() {
+        let audio = Rc::new(RecordingAudio::new());
+        let mut game = ready_game(audio.clone(), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+        key_state.set_pressed("ArrowUp");
+
+        (0..100).for_each(|_| tick(&mut game, &mut key_state));
+
+        let jumps = audio
+            .commands()
+            .into_iter()
+            .filter(|command| {
+                command
+                    == &AudioCommand::Play {
+                        sound: JUMPING_SOUND_FILENAME.into(),
+                        looping: Looping::No,
+                    }
+            })
+            .count();
+        assert_eq!(jumps, 1);
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        assert!(matches!(
+            state.scene.rhb.state_machine,
+            RedHatBoyStateMachine::Running(_)
+        ));
+    }
+
+    #[test]
+    fn confirm_held_since_walking_does_not_restart() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        key_state.set_pressed("Enter");
+
+        update_until_game_over(&mut game, &mut key_state);
+        tick(&mut game, &mut key_state);
+
+        assert!(matches!(
+            game.machine,
+            Some(WalkTheDogStateMachine::GameOver(_))
+        ));
+    }
+
+    fn segment_sequence(scene: &mut Scene, length: usize) -> Vec<usize> {
+        (0..length).map(|_| scene.choose_segment()).collect()
+    }
+
+    #[test]
+    fn seed_decides_the_exact_segment_sequence() {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+
+        assert_eq!(
+            segment_sequence(&mut scene, 10),
+            vec![0, 3, 0, 3, 2, 1, 2, 1, 3, 0]
         );
-        let audio = Audio::new().unwrap();
-        let scene = Scene {
-            rhb,
-            backgrounds: [
-                Image::new(image.clone(), Point { x: 0, y: 0 }),
-                Image::new(image.clone(), Point { x: 0, y: 0 }),
-            ],
-            obstacles: vec![],
-            obstacle_sheet: Rc::new(sprite_sheet),
-            audio: Rc::new(audio),
-            sound_collection: HashMap::new(),
-            sound_nodes: HashMap::new(),
-            stone_element: image.clone(),
-            timeline: 0,
-            count: 0,
+    }
+
+    #[test]
+    fn reset_with_the_same_seed_repeats_the_course() {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let first = segment_sequence(&mut scene, 20);
+
+        let mut scene = Scene::reset(scene, TEST_SEED);
+        assert_eq!(scene.seed, TEST_SEED);
+        assert_eq!(segment_sequence(&mut scene, 20), first);
+
+        let mut scene = Scene::reset(scene, TEST_SEED + 1);
+        assert_ne!(segment_sequence(&mut scene, 20), first);
+    }
+
+    #[test]
+    fn generated_obstacles_follow_the_seed() {
+        let draw_segments = |seed| {
+            let scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+            let mut scene = Scene::reset(scene, seed);
+            (0..5).for_each(|_| scene.generate_next_segment());
+            let renderer = RecordingRenderer::new();
+            scene.draw(&renderer, 1.0, &DebugSettings::default());
+            image_commands(&renderer)
+        };
+
+        assert_eq!(draw_segments(7), draw_segments(7));
+        assert_ne!(draw_segments(7), draw_segments(8));
+    }
+
+    #[test]
+    fn generated_source_places_segments_from_a_generator_with_the_scene_seed() {
+        let draw_generated = |seed| {
+            let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+            scene.segment_source = SegmentSource::Generated;
+            let mut scene = Scene::reset(scene, seed);
+            (0..3).for_each(|_| scene.generate_next_segment());
+            let renderer = RecordingRenderer::new();
+            scene.draw(&renderer, 1.0, &DebugSettings::default());
+            image_commands(&renderer)
+        };
+        let mut expected = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut generator = SegmentGenerator::new(TEST_SEED);
+        (0..3).for_each(|_| {
+            expected.obstacles.append(&mut generator.next_segment(
+                expected.stone_element.clone(),
+                expected.obstacle_sheet.clone(),
+                expected.timeline + OBSTACLE_BUFFER,
+            ));
+            expected.timeline = rightmost(&expected.obstacles);
+        });
+        let renderer = RecordingRenderer::new();
+        expected.draw(&renderer, 1.0, &DebugSettings::default());
+
+        assert_eq!(draw_generated(TEST_SEED), image_commands(&renderer));
+        assert_ne!(draw_generated(TEST_SEED + 1), image_commands(&renderer));
+    }
+
+    /// 直前の組の次の番号を選ぶ
+    struct CyclingPolicy;
+
+    impl SegmentPolicy for CyclingPolicy {
+        fn choose(
+            &self,
+            segments: &SegmentLibrary,
+            context: &SelectionContext,
+            _rng: &mut dyn rand::RngCore,
+        ) -> usize {
+            context.previous.map_or(0, |previous| previous + 1) % segments.len()
+        }
+    }
+
+    #[test]
+    fn segment_policy_can_be_replaced() {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        scene.policy = Box::new(CyclingPolicy);
+
+        assert_eq!(
+            segment_sequence(&mut scene, 10),
+            vec![0, 1, 2, 3, 4, 5, 6, 7, 0, 1]
+        );
+
+        let mut scene = Scene::reset(scene, TEST_SEED);
+        assert_eq!(segment_sequence(&mut scene, 2), vec![0, 1]);
+    }
+
+    #[test]
+    fn only_easy_segments_come_first_and_hard_ones_are_followed_by_a_breather() {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let difficulties = |scene: &mut Scene| {
+            let segments = scene.segments.clone();
+            segment_sequence(scene, 100)
+                .into_iter()
+                .map(|index| segments.difficulty(index))
+                .collect::<Vec<Difficulty>>()
         };
+
+        assert!(difficulties(&mut scene)
+            .iter()
+            .all(|&difficulty| difficulty == Difficulty::Easy));
+
+        scene.distance = HARD_UNLOCK_DISTANCE;
+        let later = difficulties(&mut scene);
+        assert!(later.contains(&Difficulty::Hard));
+        later
+            .windows(2)
+            .filter(|pair| pair[0] == Difficulty::Hard)
+            .for_each(|pair| assert_eq!(pair[1], Difficulty::Easy));
+    }
+
+    #[test]
+    fn walking_adds_up_the_distance() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+
+        (0..11).for_each(|_| tick(&mut game, &mut key_state));
+
+        assert_eq!(walking_scene(&game).distance, 30);
+    }
+
+    fn walking_scene_mut(game: &mut WalkTheDog) -> &mut Scene {
+        let Some(WalkTheDogStateMachine::Walking(state)) = &mut game.machine else {
+            panic!("game is not walking");
+        };
+        &mut state.scene
+    }
+
+    #[test]
+    fn the_run_speeds_up_with_distance() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+        tick(&mut game, &mut key_state);
+        assert_eq!(walking_scene(&game).scroll, -3);
+
+        walking_scene_mut(&mut game).distance = 10800;
+        tick(&mut game, &mut key_state);
+
+        assert_eq!(walking_scene(&game).scroll, -4);
+        assert_eq!(walking_scene(&game).rhb.walking_speed(), 4);
+    }
+
+    #[test]
+    fn obstacle_spacing_grows_with_the_speed() {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        assert_eq!(scene.obstacle_buffer(), OBSTACLE_BUFFER);
+        assert_eq!(scene.timeline_minimum(), TIMELINE_MINIMUM);
+
+        scene.distance = 43200;
+        let timeline = scene.timeline;
+        scene.generate_next_segment();
+
+        assert_eq!(scene.obstacle_buffer(), OBSTACLE_BUFFER * 2);
+        assert_eq!(scene.timeline_minimum(), TIMELINE_MINIMUM * 2);
+        assert_eq!(
+            scene.segment_starts.last(),
+            Some(&(timeline + OBSTACLE_BUFFER * 2))
+        );
+    }
+
+    #[test]
+    fn run_animation_follows_the_running_speed() {
+        let run_frames = |speed: i16| {
+            let mut rhb =
+                test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+            rhb.run_right();
+            rhb.set_running_speed(speed);
+            (0..3).for_each(|_| rhb.update());
+            rhb.state_machine.context().frame
+        };
+
+        assert_eq!(run_frames(3), 3);
+        assert_eq!(run_frames(4), 4);
+        assert_eq!(run_frames(6), 6);
+    }
+
+    #[test]
+    fn running_speed_only_changes_while_running() {
+        let mut rhb = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+
+        rhb.set_running_speed(5);
+        assert_eq!(rhb.walking_speed(), 0);
+
+        rhb.run_right();
+        rhb.knock_out();
+        rhb.set_running_speed(5);
+        assert_eq!(rhb.walking_speed(), 0);
+    }
+
+    /// 障害物を`platforms`だけにして、走り出したRedHatBoyを`start`で動かしてから`ticks`だけ走らせる。
+    /// ゲームオーバーになるまでの、tickごとの状態の名前と[`RedHatBoyContext`]を返す
+    fn run_among_platforms(
+        platforms: SegmentBuilder,
+        start: impl FnOnce(&mut RedHatBoy),
+        ticks: usize,
+    ) -> Vec<(&'static str, RedHatBoyContext)> {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+        let scene = walking_scene_mut(&mut game);
+        scene.obstacles = platforms.build();
+        scene.timeline = i16::MAX;
+        start(&mut scene.rhb);
+
+        (0..ticks)
+            .map_while(|_| {
+                tick(&mut game, &mut key_state);
+                let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+                    return None;
+                };
+                let state_machine = state.scene.rhb.state_machine;
+                Some((state_machine.name(), *state_machine.context()))
+            })
+            .collect()
+    }
+
+    /// 高さ93の当たり判定が一つだけの足場を、左上を`x`, `y`にして置く
+    fn block(builder: SegmentBuilder, x: i16, y: i16, sides: PlatformSides) -> SegmentBuilder {
+        builder.platform(
+            x,
+            y,
+            &["13.png"],
+            &[Point { x: 0, y: 0 }],
+            &[&Rect::new_from_x_y(0, 0, 128, 93)],
+            sides,
+        )
+    }
+
+    #[test]
+    fn walking_off_a_platform_falls_to_the_floor() {
+        let states = run_among_platforms(
+            builder(0).floating_platform(-100, 420),
+            |rhb| rhb.land_on(420),
+            150,
+        );
+
+        let airborne = states
+            .iter()
+            .position(|&(name, _)| name == "Airborne")
+            .expect("never left the platform");
+        assert!(airborne > 60, "left the platform at tick {}", airborne);
+        assert!(states[..airborne]
+            .iter()
+            .all(|(name, context)| *name == "Running" && (420..440).contains(&context.bottom())));
+        let (name, context) = states.last().unwrap();
+        assert_eq!((*name, context.bottom()), ("Running", CANVAS_SIZE));
+    }
+
+    #[test]
+    fn walking_off_a_platform_lands_on_the_one_below() {
+        let states = run_among_platforms(
+            builder(0)
+                .floating_platform(-100, 300)
+                .floating_platform(284, 450),
+            |rhb| rhb.land_on(300),
+            150,
+        );
+
+        assert!(states.iter().any(|&(name, _)| name == "Airborne"));
+        let (name, context) = states.last().unwrap();
+        assert_eq!(*name, "Running");
+        assert!((450..470).contains(&context.bottom()));
+    }
+
+    #[test]
+    fn running_into_the_side_of_a_low_platform_knocks_out() {
+        let states = run_among_platforms(
+            block(builder(0), 150, 460, PlatformSides::KnockOut),
+            |_| {},
+            60,
+        );
+
+        assert!(states.iter().any(|&(name, _)| name == "Falling"));
+    }
+
+    #[test]
+    fn running_into_a_platform_lower_than_the_head_is_not_a_landing() {
+        // 足場の上端は、RedHatBoyの絵の上端より下で足より上にある
+        let knocked_out = run_among_platforms(
+            block(builder(0), 150, 520, PlatformSides::KnockOut),
+            |_| {},
+            60,
+        );
+        let pushed_back = run_among_platforms(
+            block(builder(0), 150, 520, PlatformSides::PushBack),
+            |_| {},
+            60,
+        );
+
+        assert!(knocked_out.iter().any(|&(name, _)| name == "Falling"));
+        assert!(pushed_back
+            .windows(2)
+            .any(|pair| pair[1].1.position.x < pair[0].1.position.x));
+        [knocked_out, pushed_back].iter().for_each(|states| {
+            assert!(states
+                .iter()
+                .all(|(_, context)| context.bottom() == CANVAS_SIZE));
+        });
+    }
+
+    #[test]
+    fn pushing_platforms_push_back_until_off_the_screen() {
+        let states = run_among_platforms(
+            block(builder(0), 150, 460, PlatformSides::PushBack),
+            |_| {},
+            120,
+        );
+
+        let pushed = states
+            .iter()
+            .position(|(_, context)| context.position.x < states[0].1.position.x)
+            .expect("never pushed back");
+        assert!(states[pushed..pushed + 10]
+            .iter()
+            .all(|&(name, _)| name == "Running"));
+        assert!(states[pushed..pushed + 10]
+            .windows(2)
+            .all(|pair| pair[1].1.position.x < pair[0].1.position.x));
+        assert!(states.iter().any(|&(name, _)| name == "Falling"));
+    }
+
+    #[test]
+    fn pushed_back_position_is_regained_while_running() {
+        let mut rhb = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+        rhb.run_right();
+        let start = rhb.state_machine.context().position.x;
+
+        rhb.push_out(Point { x: -10, y: 0 });
+        (0..5).for_each(|_| rhb.update());
+        assert_eq!(rhb.state_machine.context().position.x, start - 5);
+        (0..20).for_each(|_| rhb.update());
+        assert_eq!(rhb.state_machine.context().position.x, start);
+    }
+
+    #[test]
+    fn falling_into_the_underside_is_pushed_out_below() {
+        let mut rhb = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+        rhb.run_right();
+        rhb.jump();
+        while rhb.velocity_y() < 0 {
+            rhb.update();
+        }
+        rhb.update();
+        // 頭が下側に3だけめり込んでいる足場
+        let head = rhb.bounding_box();
+        let platform = block(
+            builder(0),
+            head.x(),
+            head.y() + 3 - 93,
+            PlatformSides::KnockOut,
+        )
+        .build()
+        .remove(0);
+
+        platform.check_intersection(&mut rhb);
+
+        assert_eq!(rhb.bounding_box().y(), head.y() + 3);
+        assert!(!rhb.is_knocked_out());
+    }
+
+    #[test]
+    fn feet_deep_inside_a_platform_top_are_pushed_out_above() {
+        let mut rhb = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+        rhb.run_right();
+        rhb.update();
+        // 足が上側に10だけめり込んでいて、上から乗ったとは言えない足場
+        let feet = rhb.bounding_box();
+        let platform = block(
+            builder(0),
+            feet.x() - 20,
+            feet.bottom() - 10,
+            PlatformSides::KnockOut,
+        )
+        .build()
+        .remove(0);
+
+        platform.check_intersection(&mut rhb);
+
+        assert_eq!(rhb.bounding_box().bottom(), feet.bottom() - 10);
+        assert!(!rhb.is_knocked_out());
+    }
+
+    #[test]
+    fn jumping_into_the_underside_bumps_the_head() {
+        let states = run_among_platforms(
+            block(builder(0), 20, 300, PlatformSides::KnockOut),
+            |rhb| rhb.jump(),
+            40,
+        );
+
+        let bumped = states
+            .iter()
+            .position(|(_, context)| context.velocity.y >= 0)
+            .expect("never stopped rising");
+        assert!(bumped < 10, "still rising at tick {}", bumped);
+        assert!(states.iter().all(|(_, context)| context.position.y > 300));
+        assert!(states
+            .iter()
+            .all(|&(name, _)| name == "Jumping" || name == "Running"));
+        let (name, context) = states.last().unwrap();
+        assert_eq!((*name, context.bottom()), ("Running", CANVAS_SIZE));
+    }
+
+    #[test]
+    fn landing_on_a_platform_from_its_side_is_not_a_hit() {
+        let states = run_among_platforms(
+            block(builder(0), 150, 460, PlatformSides::KnockOut),
+            |rhb| rhb.jump(),
+            60,
+        );
+
+        assert!(states.iter().all(|&(name, _)| name != "Falling"));
+        assert!(states
+            .iter()
+            .any(|(name, context)| *name == "Running" && context.bottom() == 460));
+    }
+
+    #[test]
+    fn a_long_fall_lands_on_a_platform_thinner_than_one_tick_of_falling() {
+        // 足場は、落ちる途中の2つのtickの当たり判定の間に挟まる高さに置く
+        let thin = builder(0).platform(
+            200,
+            390,
+            &["13.png"],
+            &[Point { x: 0, y: 0 }],
+            &[&Rect::new_from_x_y(0, 0, 400, 5)],
+            PlatformSides::KnockOut,
+        );
+        let states = run_among_platforms(thin, |rhb| rhb.land_on(-7000), 200);
+
+        let landed = states
+            .iter()
+            .position(|&(name, _)| name == "Running")
+            .expect("never landed");
+        eprintln!(
+            "{:?}",
+            states[landed - 3..=landed]
+                .iter()
+                .map(|(n, c)| (*n, c.position.x, c.position.y, c.velocity.y))
+                .collect::<Vec<_>>()
+        );
+        // 1tickで、RedHatBoyの背丈の121よりも長く落ちている
+        assert!(states[landed - 1].1.velocity.y > 121);
+        assert_eq!(states[landed].1.bottom(), 390);
+    }
+
+    #[test]
+    fn sliding_makes_the_hitbox_short_and_wide() {
+        let mut rhb = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+        rhb.run_right();
+        let running = rhb.bounding_box();
+
+        rhb.slide();
+        let sliding = rhb.bounding_box();
+
+        assert!(sliding.height < running.height);
+        assert!(sliding.width > running.width);
+        assert!(sliding.y() > running.y());
+    }
+
+    #[test]
+    fn only_sliding_passes_under_an_overhead_barrier() {
+        let overhead = || builder(0).overhead_barrier(110);
+        let falls = |states: Vec<(&str, RedHatBoyContext)>| {
+            states.iter().any(|&(name, _)| name == "Falling")
+        };
+
+        assert!(falls(run_among_platforms(overhead(), |_| {}, 30)));
+        assert!(falls(run_among_platforms(overhead(), |rhb| rhb.jump(), 30)));
+        let sliding = run_among_platforms(overhead(), |rhb| rhb.slide(), 120);
+        assert!(!falls(sliding.clone()));
+        assert_eq!(sliding.last().map(|&(name, _)| name), Some("Running"));
+    }
+
+    #[test]
+    fn falling_from_a_platform_shows_the_fall_and_cannot_jump() {
+        let mut rhb = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+        rhb.run_right();
+        rhb.land_on(420);
+
+        rhb.lose_support();
+        rhb.jump();
+        rhb.slide();
+        rhb.update();
+
+        assert_eq!(rhb.state_machine.name(), "Airborne");
+        assert_eq!(rhb.state_machine.frame_name(), "Jump");
+        assert!(rhb.velocity_y() > 0);
+    }
+
+    #[test]
+    fn losing_support_on_the_floor_keeps_running() {
+        let mut rhb = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new())).rhb;
+        rhb.run_right();
+        rhb.update();
+
+        rhb.lose_support();
+
+        assert_eq!(rhb.state_machine.name(), "Running");
+    }
+
+    #[test]
+    fn game_over_screen_shows_the_seed() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        update_until_game_over(&mut game, &mut key_state);
+
+        let renderer = RecordingRenderer::new();
+        game.draw(&renderer, 1.0, &DebugSettings::default());
+
+        assert!(renderer.commands().contains(&DrawCommand::DrawText {
+            text: format!("Seed: {}", TEST_SEED),
+            location: Point { x: 20, y: 80 },
+        }));
+    }
+
+    #[test]
+    fn chosen_course_seed_is_kept_for_the_next_game() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        if let Some(WalkTheDogStateMachine::Ready(state)) = &mut game.machine {
+            state.scene.course_seed = Some(TEST_SEED);
+        }
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        update_until_game_over(&mut game, &mut key_state);
+        key_state.set_released("ArrowRight");
+
+        key_state.set_pressed("Enter");
+        tick(&mut game, &mut key_state);
+
+        let Some(WalkTheDogStateMachine::Ready(state)) = &game.machine else {
+            panic!("game is not ready");
+        };
+        assert_eq!(state.scene.seed, TEST_SEED);
+        assert_eq!(game.recording.seed(), TEST_SEED);
+    }
+
+    #[test]
+    fn daily_seed_is_the_date_as_digits() {
+        assert_eq!(daily_seed((2026, 10, 17)), 20261017);
+        assert_ne!(daily_seed((2026, 10, 17)), daily_seed((2026, 10, 18)));
+    }
+
+    /// 日付を決めたデイリーランのあるゲーム
+    fn game_with_daily_seed(ui: Rc<dyn Ui>, seed: u64) -> WalkTheDog {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), ui);
+        scene.daily_seed = seed;
+        ready_game_with(scene)
+    }
+
+    #[test]
+    fn ready_state_shows_the_mode_menu() {
+        let ui = Rc::new(HeadlessUi::new());
+        let game = ready_game(Rc::new(RecordingAudio::new()), ui.clone());
+        let renderer = RecordingRenderer::new();
+
+        game.draw(&renderer, 1.0, &DebugSettings::default());
+
+        assert_eq!(
+            ui.visible_buttons(),
+            vec![ENDLESS_BUTTON_ID.to_string(), DAILY_BUTTON_ID.to_string()]
+        );
+        assert!(renderer.commands().contains(&DrawCommand::DrawText {
+            text: "▶ Endless".into(),
+            location: Point { x: 20, y: 80 },
+        }));
+    }
+
+    #[test]
+    fn daily_button_starts_the_course_of_the_day() {
+        let ui = Rc::new(HeadlessUi::new());
+        let mut game = game_with_daily_seed(ui.clone(), 20261017);
+        let mut key_state = KeyState::new();
+
+        ui.click(DAILY_BUTTON_ID).unwrap();
+        tick(&mut game, &mut key_state);
+
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        assert_eq!(state.scene.mode, GameMode::Daily);
+        assert_eq!(state.scene.seed, 20261017);
+        assert!(ui.visible_buttons().is_empty());
+        assert_eq!(game.recording.seed(), 20261017);
+    }
+
+    #[test]
+    fn mode_can_be_chosen_with_keys() {
+        let mut game = game_with_daily_seed(Rc::new(HeadlessUi::new()), 20261017);
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed("ArrowDown");
+        tick(&mut game, &mut key_state);
+        key_state.set_released("ArrowDown");
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        assert_eq!(state.scene.mode, GameMode::Daily);
+        assert_eq!(state.scene.seed, 20261017);
+    }
+
+    #[test]
+    fn daily_and_endless_best_scores_are_separate() {
+        let ui = Rc::new(HeadlessUi::new());
+        let scores = Rc::new(MemoryScores::default());
+        let mut game = game_with_daily_seed(ui.clone(), 20261017);
+        game.scores = scores.clone();
+        let mut key_state = KeyState::new();
+
+        ui.click(DAILY_BUTTON_ID).unwrap();
+        let ticks = update_until_game_over(&mut game, &mut key_state) as i32;
+
+        assert_eq!(
+            scores.best("best-score-daily-20261017").unwrap(),
+            Some(ticks - 1)
+        );
+        assert_eq!(scores.best("best-score-endless").unwrap(), None);
+        let renderer = RecordingRenderer::new();
+        game.draw(&renderer, 1.0, &DebugSettings::default());
+        assert!(renderer.commands().contains(&DrawCommand::DrawText {
+            text: format!("Daily Run Best: {}", format_time(ticks - 1)),
+            location: Point { x: 20, y: 110 },
+        }));
+    }
+
+    #[test]
+    fn new_game_after_a_daily_run_offers_the_daily_course_again() {
+        let ui = Rc::new(HeadlessUi::new());
+        let mut game = game_with_daily_seed(ui.clone(), 20261017);
+        let mut key_state = KeyState::new();
+        ui.click(DAILY_BUTTON_ID).unwrap();
+        update_until_game_over(&mut game, &mut key_state);
+
+        ui.click(NEW_GAME_BUTTON_ID).unwrap();
+        tick(&mut game, &mut key_state);
+
+        let Some(WalkTheDogStateMachine::Ready(state)) = &game.machine else {
+            panic!("game is not ready");
+        };
+        assert_eq!(state.scene.mode, GameMode::Daily);
+        assert_eq!(state.scene.seed, 20261017);
+    }
+
+    /// 描画命令から描いた位置だけを取り出す。アニメーションのフレームは補間しないので除く
+    fn drawn_positions(renderer: &RecordingRenderer) -> Vec<Point> {
+        renderer
+            .commands()
+            .iter()
+            .filter_map(|command| match command {
+                DrawCommand::DrawImage { destination, .. } => Some(destination.position),
+                DrawCommand::DrawEntireImage { position, .. } => Some(*position),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walking_scene_is_drawn_between_the_last_two_updates() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        tick(&mut game, &mut key_state);
+        tick(&mut game, &mut key_state);
+        let before = RecordingRenderer::new();
+        game.draw(&before, 1.0, &DebugSettings::default());
+        key_state.set_pressed("ArrowUp");
+        tick(&mut game, &mut key_state);
+        tick(&mut game, &mut key_state);
+        let previous = RecordingRenderer::new();
+        game.draw(&previous, 1.0, &DebugSettings::default());
+        tick(&mut game, &mut key_state);
+
+        let start = RecordingRenderer::new();
+        game.draw(&start, 0.0, &DebugSettings::default());
+        let middle = RecordingRenderer::new();
+        game.draw(&middle, 0.5, &DebugSettings::default());
+        let end = RecordingRenderer::new();
+        game.draw(&end, 1.0, &DebugSettings::default());
+
+        let scenery = |renderer: &RecordingRenderer| {
+            let mut positions = drawn_positions(renderer);
+            positions.remove(2);
+            positions
+        };
+        assert_eq!(scenery(&start), scenery(&previous));
+        assert_ne!(scenery(&end), scenery(&previous));
+        let [start_stone, middle_stone, end_stone] =
+            [&start, &middle, &end].map(|renderer| scenery(renderer)[2]);
+        assert!(start_stone.x > middle_stone.x && middle_stone.x > end_stone.x);
+
+        // 同じ更新の後なのでアニメーションのフレームは同じで、高さだけが補間される
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        let rise = state.scene.previous_rhb_y - state.scene.rhb.pos_y();
+        assert!(rise > 0);
+        let [start_boy, end_boy] = [&start, &end].map(|renderer| drawn_positions(renderer)[2]);
+        assert_eq!(start_boy.y - end_boy.y, rise);
+        assert_ne!(drawn_positions(&before), drawn_positions(&end));
+    }
+
+    /// 決まった間隔でジャンプしながら、ゲームオーバーになるまで走る
+    fn play_scripted_run(game: &mut WalkTheDog) -> usize {
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+        (1..=2000)
+            .find(|tick_count| {
+                if tick_count % 40 == 0 {
+                    key_state.set_pressed("ArrowUp");
+                } else {
+                    key_state.set_released("ArrowUp");
+                }
+                tick(game, &mut key_state);
+                matches!(game.machine, Some(WalkTheDogStateMachine::GameOver(_)))
+            })
+            .expect("game never ended")
+    }
+
+    #[cfg(feature = "dev-tools")]
+    fn cheating_game(cheats: dev_tools::Cheats) -> WalkTheDog {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        scene.set_cheats(cheats);
+        ready_game_with(scene)
+    }
+
+    fn walking_scene(game: &WalkTheDog) -> &Scene {
+        let Some(WalkTheDogStateMachine::Walking(state)) = &game.machine else {
+            panic!("game is not walking");
+        };
+        &state.scene
+    }
+
+    #[cfg(feature = "dev-tools")]
+    #[test]
+    fn god_mode_runs_through_stones_and_saves_nothing() {
+        let replays = Rc::new(MemoryReplays::default());
+        let mut game = cheating_game(dev_tools::Cheats {
+            god_mode: true,
+            ..Default::default()
+        });
+        game.replays = replays.clone();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+
+        (0..1000).for_each(|_| tick(&mut game, &mut key_state));
+
+        assert_eq!(walking_scene(&game).count, 999);
+        assert_eq!(replays.load().unwrap(), None);
+    }
+
+    #[cfg(feature = "dev-tools")]
+    #[test]
+    fn forced_segment_is_always_generated() {
+        let mut scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        scene.set_cheats(dev_tools::Cheats {
+            segment: Some(4),
+            ..Default::default()
+        });
+        let mut expected = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+
+        (0..3).for_each(|_| {
+            scene.generate_next_segment();
+            expected.obstacles.append(
+                &mut expected
+                    .segments
+                    .instantiate(
+                        4,
+                        expected.stone_element.clone(),
+                        expected.obstacle_sheet.clone(),
+                        expected.timeline + OBSTACLE_BUFFER,
+                    )
+                    .unwrap(),
+            );
+            expected.timeline = rightmost(&expected.obstacles);
+        });
+
+        assert_eq!(scene.timeline, expected.timeline);
+        assert_eq!(scene.obstacles.len(), expected.obstacles.len());
+    }
+
+    #[cfg(feature = "dev-tools")]
+    #[test]
+    fn running_speed_override_scrolls_the_scene_faster() {
+        let mut game = cheating_game(dev_tools::Cheats {
+            running_speed: Some(9),
+            ..Default::default()
+        });
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+
+        tick(&mut game, &mut key_state);
+        tick(&mut game, &mut key_state);
+
+        assert_eq!(walking_scene(&game).scroll, -9);
+    }
+
+    #[cfg(feature = "dev-tools")]
+    #[test]
+    fn skip_jumps_ahead_when_the_run_starts() {
+        let mut game = cheating_game(dev_tools::Cheats {
+            skip_seconds: 30,
+            ..Default::default()
+        });
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowRight");
+
+        tick(&mut game, &mut key_state);
+
+        let scene = walking_scene(&game);
+        assert_eq!(scene.count, 30 * 60);
+        assert!(!scene.rhb.invulnerable);
+    }
+
+    #[test]
+    fn game_over_saves_the_run_as_a_replay() {
+        let replays = Rc::new(MemoryReplays::default());
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        game.replays = replays.clone();
+
+        let ticks = play_scripted_run(&mut game);
+
+        let replay = replays.load().unwrap().expect("replay was not saved");
+        assert_eq!(replay.seed(), TEST_SEED);
+        assert_eq!(replay.ticks(), ticks);
+    }
+
+    #[test]
+    fn replaying_a_run_reproduces_it_frame_for_frame() {
+        let replays = Rc::new(MemoryReplays::default());
+        let mut recorded = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        recorded.replays = replays.clone();
+        play_scripted_run(&mut recorded);
+        let replay = Replay::from_bytes(&replays.load().unwrap().unwrap().to_bytes()).unwrap();
+        let ticks = replay.ticks();
+
+        let audio = Rc::new(RecordingAudio::new());
+        let mut replayed = ready_game(audio.clone(), Rc::new(HeadlessUi::new()));
+        replayed.playback = Some(ReplayPlayer::new(replay));
+        let mut key_state = KeyState::new();
+        (0..ticks).for_each(|_| tick(&mut replayed, &mut key_state));
+
+        let recorded_renderer = RecordingRenderer::new();
+        recorded.draw(&recorded_renderer, 1.0, &DebugSettings::default());
+        let replayed_renderer = RecordingRenderer::new();
+        replayed.draw(&replayed_renderer, 1.0, &DebugSettings::default());
+        assert_eq!(replayed_renderer.commands(), recorded_renderer.commands());
+        assert!(matches!(
+            replayed.machine,
+            Some(WalkTheDogStateMachine::GameOver(_))
+        ));
+        assert!(audio
+            .commands()
+            .iter()
+            .any(|command| matches!(command, AudioCommand::Play { sound, .. } if sound == JUMPING_SOUND_FILENAME)));
+    }
+
+    #[test]
+    fn pause_action_toggles_the_time_once_per_press() {
+        let mut game = ready_game(Rc::new(RecordingAudio::new()), Rc::new(HeadlessUi::new()));
+        let mut time = TimeControl::new();
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed("Escape");
+        game.update_time(&key_state, &mut time);
+        key_state.tick();
+        game.update_time(&key_state, &mut time);
+        assert!(time.is_paused());
+
+        key_state.set_released("Escape");
+        key_state.tick();
+        game.update_time(&key_state, &mut time);
+        key_state.set_pressed("KeyP");
+        game.update_time(&key_state, &mut time);
+        assert!(!time.is_paused());
+    }
+
+    /// 新しいゲームが始まった時にUIを消す。
+    #[wasm_bindgen_test]
+    fn test_transition_from_game_over_to_new_game() {
+        // 準備
+        let (_, receiver) = unbounded();
+        let scene = test_scene(Rc::new(RecordingAudio::new()), Rc::new(HtmlUi));
         let document = browser::document().unwrap();
         let body = document.body().unwrap();
         body.insert_adjacent_html("afterbegin", "<canvas id=\"canvas\" tabindex=\"0\" height=\"600\" width=\"600\">Your browser does not support the canvas.</canvas>")
@@ -1228,6 +3472,7 @@ mod tests {
         let state = WalkTheDogState {
             _state: GameOver {
                 new_game_event: receiver,
+                best_score: 0,
             },
             scene,
         };
@@ -1235,8 +3480,8 @@ mod tests {
         // 実行
         state.new_game();
 
-        // 確認
+        // 確認（遊び方を選ぶボタンだけが残る）
         let ui = browser::find_html_elemebt_by_id("ui").unwrap();
-        assert_eq!(ui.child_element_count(), 0);
+        assert_eq!(ui.child_element_count(), 2);
     }
 }
@@ -0,0 +1,141 @@
+//! 長いプレイを確かめるための開発用のチート
+//!
+//! `dev-tools`フィーチャーを有効にした時だけコンパイルされ、URLのクエリで選ぶ。
+//! - `?god`: 石にぶつかっても倒れない
+//! - `?segment=N`: 障害物の組を番号`N`のものに固定する。部品から組を作る時は使われない
+//! - `?speed=N`: 走る速さを`N`にする
+//! - `?skip=N`: 走り始めたら`N`秒先まで一気に進める
+//!
+//! チートを使ったプレイは、記録も最高記録も保存しない。
+
+use anyhow::{anyhow, Result};
+
+use super::WalkTheDogStateMachine;
+use crate::{browser, input::ActionState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cheats {
+    pub god_mode: bool,
+    /// 次から置く障害物の組の番号
+    pub segment: Option<usize>,
+    /// [`super::speed::SpeedCurve`]で上がっていく速さの代わりに使う、走る速さ
+    pub running_speed: Option<i16>,
+    pub skip_seconds: u32,
+}
+
+impl Cheats {
+    /// `segment_count`はゲームにある障害物の組の数
+    pub fn from_query(segment_count: usize) -> Result<Self> {
+        Cheats::parse(browser::query_param, segment_count)
+    }
+
+    /// `query`で取り出したクエリの値からチートを選ぶ
+    fn parse(query: impl Fn(&str) -> Result<Option<String>>, segment_count: usize) -> Result<Self> {
+        let segment = parse_number::<usize>(&query, "segment")?;
+        if let Some(segment) = segment.filter(|&segment| segment >= segment_count) {
+            return Err(anyhow!(
+                "Segment {} does not exist (expected less than {})",
+                segment,
+                segment_count
+            ));
+        }
+        let running_speed = parse_number::<i16>(&query, "speed")?;
+        if let Some(speed) = running_speed.filter(|&speed| speed <= 0) {
+            return Err(anyhow!("Running speed {} must be positive", speed));
+        }
+        Ok(Cheats {
+            god_mode: query("god")?.is_some(),
+            segment,
+            running_speed,
+            skip_seconds: parse_number(&query, "skip")?.unwrap_or(0),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self != Cheats::default()
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(
+    query: impl Fn(&str) -> Result<Option<String>>,
+    name: &str,
+) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    query(name)?
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|err| anyhow!("Invalid {} {} {}", name, value, err))
+        })
+        .transpose()
+}
+
+/// 走り始めたばかりのゲームを、入力なしで[`Cheats::skip_seconds`]秒分だけ進める。
+/// 進めている間は倒れない。
+pub(super) fn skip_ahead(machine: WalkTheDogStateMachine) -> WalkTheDogStateMachine {
+    let WalkTheDogStateMachine::Walking(mut state) = machine else {
+        return machine;
+    };
+    let ticks = state.scene.cheats.skip_seconds * 60;
+    state.scene.rhb.invulnerable = true;
+    let mut machine = (0..ticks).fold(WalkTheDogStateMachine::Walking(state), |machine, _| {
+        machine.update(&ActionState::default())
+    });
+    if let WalkTheDogStateMachine::Walking(state) = &mut machine {
+        state.scene.apply_cheats();
+    }
+    machine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(pairs: &[(&str, &str)]) -> Result<Cheats> {
+        let query: HashMap<String, String> = pairs
+            .iter()
+            .map(|&(name, value)| (name.into(), value.into()))
+            .collect();
+        Cheats::parse(|name| Ok(query.get(name).cloned()), 6)
+    }
+
+    #[test]
+    fn no_flags_mean_no_cheats() {
+        let cheats = parse(&[]).unwrap();
+
+        assert_eq!(cheats, Cheats::default());
+        assert!(!cheats.is_active());
+    }
+
+    #[test]
+    fn every_flag_is_read() {
+        let cheats = parse(&[
+            ("god", ""),
+            ("segment", "3"),
+            ("speed", "8"),
+            ("skip", "30"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            cheats,
+            Cheats {
+                god_mode: true,
+                segment: Some(3),
+                running_speed: Some(8),
+                skip_seconds: 30,
+            }
+        );
+        assert!(cheats.is_active());
+    }
+
+    #[test]
+    fn invalid_flags_are_rejected() {
+        assert!(parse(&[("segment", "99")]).is_err());
+        assert!(parse(&[("speed", "0")]).is_err());
+        assert!(parse(&[("skip", "soon")]).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! 障害物の組を抜けられるかを、ブラウザなしで調べる
+//!
+//! 床を走っているRedHatBoyが組に向かっていく間、tickごとに「何もしない」「ジャンプ」
+//! 「スライディング」のどれかを入力した時の状態をすべてたどる。
+//! 動きはゲームと同じ[`RedHatBoy::update`]と[`Obstacle::check_intersection`]で計算するので、
+//! 走る速さや重力を変えると結果も変わる。
+//!
+//! 入力の猶予まで求める[`check`]はすべての状態をたどる。抜けられるかだけを知りたい時は、
+//! 抜けられる入力の並びを一つ見つけたらやめる[`is_clearable`]の方が速い。
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Obstacle, RedHatBoy, RedHatBoyStateMachine, CANVAS_SIZE, GRAVITY, JUMPING_SPEED};
+
+/// 入力が必要な場面で、これより猶予が短い組は難しすぎる
+pub const MINIMUM_TIMING_WINDOW: u32 = 3;
+
+/// 床からジャンプして床に戻るまでのtick数。どの入力の効果もこれより長くは続かない
+const LONGEST_INPUT_TICKS: i16 = 2 * -JUMPING_SPEED / GRAVITY + 1;
+/// 走り出す前のRedHatBoyの絵の右端は、どのコマでもこれより左にある
+const RED_HAT_BOY_RIGHT: i16 = 130;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    Wait,
+    Jump,
+    Slide,
+}
+
+const INPUTS: [Input; 3] = [Input::Wait, Input::Jump, Input::Slide];
+
+impl Input {
+    /// `rhb`に入力する。入力しても状態が変わらなければ`false`で、何もしなかった時と同じ
+    fn apply(self, rhb: &mut RedHatBoy) -> bool {
+        let before = state_key(&rhb.state_machine);
+        match self {
+            Input::Wait => {}
+            Input::Jump => rhb.jump(),
+            Input::Slide => rhb.slide(),
+        }
+        self == Input::Wait || state_key(&rhb.state_machine) != before
+    }
+}
+
+/// 組を抜けられるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Solvability {
+    /// どう入力しても倒れる
+    Impossible,
+    /// 何も入力しなくても抜けられる
+    Free,
+    /// 入力が必要な場面のうち、一番短い入力の猶予のtick数
+    TimingWindow(u32),
+}
+
+impl Solvability {
+    pub fn is_clearable(&self) -> bool {
+        *self != Solvability::Impossible
+    }
+}
+
+/// 入力した後の`rhb`を1tick進め、倒れなければ次のtickの状態を返す。
+/// [`WalkTheDogState<Walking>::update`](super::WalkTheDogState)と同じ順番で動かす
+fn advance(
+    rhb: &mut RedHatBoy,
+    obstacles: &[&dyn Obstacle<RedHatBoy>],
+) -> Option<RedHatBoyStateMachine> {
+    rhb.update();
+    obstacles
+        .iter()
+        .for_each(|obstacle| obstacle.check_intersection(rhb));
+    if !obstacles.iter().any(|obstacle| obstacle.supports(rhb)) {
+        rhb.lose_support();
+    }
+    (!rhb.is_knocked_out()).then_some(rhb.state_machine)
+}
+
+/// 同じ状態をまとめるための値。状態の名前、アニメーションのコマとその進み具合、位置、速度
+type StateKey = (&'static str, u8, i16, i16, i16, i16, i16);
+
+fn state_key(state: &RedHatBoyStateMachine) -> StateKey {
+    let context = state.context();
+    (
+        state.name(),
+        context.frame,
+        context.frame_progress,
+        context.position.x,
+        context.position.y,
+        context.velocity.x,
+        context.velocity.y,
+    )
+}
+
+/// あるtickの状態から、入力ごとに次のtickのどの状態になるか。倒れる入力は`None`
+type Transitions = [Option<usize>; INPUTS.len()];
+
+/// tickごとにたどり着ける状態と、その移り変わり
+struct ReachableStates {
+    /// `transitions[tick][state]`
+    transitions: Vec<Vec<Transitions>>,
+    /// `clearable[tick][state]`は、その状態から倒れずに組を抜けられるか
+    clearable: Vec<Vec<bool>>,
+}
+
+/// `speed`で走る時に、組の左端を置く位置。
+///
+/// 画面の右端に置いた時と同じ所を通って、走り出してすぐに入力しても組に届く前に
+/// 入力の効果が終わる所まで近づける。組に届くまでの何も起きないtickを調べずに済む
+pub fn segment_offset(speed: i16) -> i16 {
+    let skipped_ticks = (CANVAS_SIZE - RED_HAT_BOY_RIGHT) / speed - LONGEST_INPUT_TICKS;
+    CANVAS_SIZE - skipped_ticks.max(0) * speed
+}
+
+/// 走り出す前の`rhb`が`speed`で走った時に、`obstacles`を抜けられるかを調べる。
+/// 障害物はゲームと同じく、画面の左端を過ぎたら消える。
+pub fn check(
+    rhb: &mut RedHatBoy,
+    obstacles: Vec<Box<dyn Obstacle<RedHatBoy>>>,
+    speed: i16,
+) -> Solvability {
+    rhb.run_right();
+    rhb.set_running_speed(speed);
+    let states = ReachableStates::explore(rhb, obstacles);
+    if !states.clearable[0][0] {
+        return Solvability::Impossible;
+    }
+    match states.timing_window(0, 0, &mut HashMap::new()) {
+        Some(window) => Solvability::TimingWindow(window),
+        None => Solvability::Free,
+    }
+}
+
+/// [`check`]が[`Solvability::Impossible`]にしない組か。
+///
+/// 何もしない入力から順に深さ優先でたどり、組を抜けた所でやめる。
+/// 一度たどった状態には戻らないので、抜けられない組でも[`check`]より遅くはならない
+pub fn is_clearable(
+    rhb: &mut RedHatBoy,
+    mut obstacles: Vec<Box<dyn Obstacle<RedHatBoy>>>,
+    speed: i16,
+) -> bool {
+    rhb.run_right();
+    rhb.set_running_speed(speed);
+    let horizontal_velocity = i32::from(-rhb.walking_speed());
+    // 障害物はtickの初めに右端が画面の左端を過ぎていたら消える
+    let rights: Vec<i32> = obstacles
+        .iter()
+        .map(|obstacle| i32::from(obstacle.right()))
+        .collect();
+    let is_present = |right: i32, tick: usize| right + tick as i32 * horizontal_velocity > 0;
+    let ticks = (0..)
+        .find(|&tick| !rights.iter().any(|&right| is_present(right, tick)))
+        .map_or(0, |tick| tick + 1);
+
+    // 障害物は`moved`tick目の分まで動かしてある
+    let mut moved = 0;
+    let mut visited = HashSet::new();
+    let mut stack = vec![(0, rhb.state_machine)];
+    while let Some((tick, state)) = stack.pop() {
+        if tick == ticks {
+            return true;
+        }
+        let distance = (tick as i32 + 1 - moved) * horizontal_velocity;
+        obstacles.iter_mut().for_each(|obstacle| {
+            obstacle.move_horisontally(i16::try_from(distance).expect("obstacles stay in range"))
+        });
+        moved = tick as i32 + 1;
+        let present: Vec<&dyn Obstacle<RedHatBoy>> = obstacles
+            .iter()
+            .zip(&rights)
+            .filter(|&(_, &right)| is_present(right, tick))
+            .map(|(obstacle, _)| obstacle.as_ref())
+            .collect();
+        // 何もしない入力を先にたどるように、逆順に積む
+        INPUTS.iter().rev().for_each(|&input| {
+            rhb.state_machine = state;
+            if !input.apply(rhb) {
+                return;
+            }
+            if let Some(next) = advance(rhb, &present) {
+                if visited.insert((tick + 1, state_key(&next))) {
+                    stack.push((tick + 1, next));
+                }
+            }
+        });
+    }
+    false
+}
+
+impl ReachableStates {
+    fn explore(rhb: &mut RedHatBoy, mut obstacles: Vec<Box<dyn Obstacle<RedHatBoy>>>) -> Self {
+        let horizontal_velocity = -rhb.walking_speed();
+        let mut states = vec![rhb.state_machine];
+        let mut transitions: Vec<Vec<Transitions>> = vec![];
+        while !obstacles.is_empty() && !states.is_empty() {
+            obstacles.retain(|obstacle| obstacle.right() > 0);
+            obstacles
+                .iter_mut()
+                .for_each(|obstacle| obstacle.move_horisontally(horizontal_velocity));
+            let present: Vec<&dyn Obstacle<RedHatBoy>> =
+                obstacles.iter().map(|obstacle| obstacle.as_ref()).collect();
+            let mut next_states = vec![];
+            let mut indices = HashMap::new();
+            transitions.push(
+                states
+                    .iter()
+                    .map(|&state| {
+                        let mut waited = None;
+                        INPUTS.map(|input| {
+                            rhb.state_machine = state;
+                            if !input.apply(rhb) {
+                                return waited.expect("waiting is tried first");
+                            }
+                            let next = advance(rhb, &present).map(|next| {
+                                *indices.entry(state_key(&next)).or_insert_with(|| {
+                                    next_states.push(next);
+                                    next_states.len() - 1
+                                })
+                            });
+                            waited.get_or_insert(next);
+                            next
+                        })
+                    })
+                    .collect(),
+            );
+            states = next_states;
+        }
+
+        let mut clearable = vec![vec![true; states.len()]];
+        transitions.iter().rev().for_each(|tick| {
+            let next = clearable.last().expect("the last tick is always known");
+            let current = tick
+                .iter()
+                .map(|inputs| inputs.iter().flatten().any(|&state| next[state]))
+                .collect();
+            clearable.push(current);
+        });
+        clearable.reverse();
+        ReachableStates {
+            transitions,
+            clearable,
+        }
+    }
+
+    fn next(&self, tick: usize, state: usize, input: Input) -> Option<usize> {
+        let index = INPUTS.iter().position(|&candidate| candidate == input)?;
+        self.transitions[tick][state][index].filter(|&next| self.clearable[tick + 1][next])
+    }
+
+    /// 何もせずに待ち続けた時に、入力しないと倒れてしまう最初のtick。
+    /// 最後まで待てる時は組を抜けるtick
+    fn deadline(&self, mut tick: usize, mut state: usize) -> usize {
+        while tick < self.transitions.len() {
+            match self.next(tick, state, Input::Wait) {
+                Some(next) => state = next,
+                None => return tick,
+            }
+            tick += 1;
+        }
+        tick
+    }
+
+    /// `tick`の`state`から組を抜けるまでに入力が必要な場面のうち、一番短い入力の猶予のtick数。
+    /// 入力しなくても抜けられる時は`None`。
+    ///
+    /// 猶予は、入力しないと倒れてしまう最後のtickから遡って、同じ入力をもっと早くしても
+    /// 次に入力が必要になるまでの間が縮まない、続いたtickの数。早すぎるジャンプは
+    /// 障害物の手前で着地してしまうので数えない。猶予の中のどのtickで入力するかで
+    /// その後の猶予が変わる時は、一番楽になるtickを選ぶ。
+    fn timing_window(
+        &self,
+        tick: usize,
+        state: usize,
+        memo: &mut HashMap<(usize, usize), Option<u32>>,
+    ) -> Option<u32> {
+        if let Some(&window) = memo.get(&(tick, state)) {
+            return window;
+        }
+        let mut path = vec![state];
+        let mut last = tick;
+        while let Some(next) = (last < self.transitions.len())
+            .then(|| self.next(last, path[last - tick], Input::Wait))
+            .flatten()
+        {
+            path.push(next);
+            last += 1;
+        }
+        let window = (last < self.transitions.len()).then(|| {
+            let (input, next) = [Input::Jump, Input::Slide]
+                .into_iter()
+                .find_map(|input| Some((input, self.next(last, path[last - tick], input)?)))
+                .expect("a clearable state has a clearable input");
+            let deadline = self.deadline(last + 1, next);
+            let choices: Vec<(usize, usize)> = (tick..=last)
+                .rev()
+                .map_while(|earlier| {
+                    let next = self.next(earlier, path[earlier - tick], input)?;
+                    (self.deadline(earlier + 1, next) >= deadline).then_some((earlier, next))
+                })
+                .collect();
+            let window = choices.len() as u32;
+            let mut rest = Some(0);
+            for (earlier, next) in choices {
+                match self.timing_window(earlier + 1, next, memo) {
+                    Some(window) => rest = rest.map(|rest: u32| rest.max(window)),
+                    None => rest = None,
+                }
+            }
+            rest.map_or(window, |rest| rest.min(window))
+        });
+        memo.insert((tick, state), window);
+        window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        engine::{headless::RecordingAudio, ImageHandle, Sound},
+        game::{
+            red_hat_boy_states::RUNNING_SPEED, speed::SpeedCurve, RedHatBoySounds, CANVAS_SIZE,
+        },
+        segment::{
+            fixtures::{builder, stone, tiles_sheet},
+            generator::{self, SegmentGenerator},
+            SegmentBuilder, SegmentLibrary,
+        },
+    };
+
+    fn red_hat_boy() -> RedHatBoy {
+        RedHatBoy::new(
+            serde_json::from_str(include_str!("../../static/rhb_trimmed.json")).unwrap(),
+            ImageHandle::headless("rhb_trimmed.png", 1024, 1024),
+            Rc::new(RecordingAudio::new()),
+            RedHatBoySounds {
+                jump: Sound::headless("jump.mp3"),
+                background_music: Sound::headless("music.mp3"),
+            },
+        )
+    }
+
+    #[test]
+    fn every_bundled_segment_is_clearable_with_time_to_react_at_every_speed() {
+        let segments =
+            SegmentLibrary::from_json(include_str!("../../static/segments.json")).unwrap();
+
+        SpeedCurve::default().speeds().for_each(|speed| {
+            (0..segments.len()).for_each(|index| {
+                let obstacles = segments
+                    .instantiate(index, stone(), tiles_sheet(), segment_offset(speed))
+                    .unwrap();
+                let solvability = check(&mut red_hat_boy(), obstacles, speed);
+
+                assert!(
+                    match solvability {
+                        Solvability::Impossible => false,
+                        Solvability::Free => true,
+                        Solvability::TimingWindow(window) => window >= MINIMUM_TIMING_WINDOW,
+                    },
+                    "{} is {:?} at speed {}",
+                    segments.name(index),
+                    solvability,
+                    speed
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn generated_segments_are_clearable_at_the_slowest_and_fastest_speed() {
+        let speeds: Vec<i16> = SpeedCurve::default().speeds().collect();
+
+        (0..32).for_each(|seed| {
+            let mut generator = SegmentGenerator::new(seed);
+            (0..8).for_each(|_| {
+                let layout = generator.next_layout(stone().width());
+                [speeds[0], speeds[speeds.len() - 1]]
+                    .into_iter()
+                    .for_each(|speed| {
+                        let obstacles =
+                            generator::build(&layout, builder(segment_offset(speed))).build();
+
+                        assert!(
+                            is_clearable(&mut red_hat_boy(), obstacles, speed),
+                            "{:?} from seed {} is not clearable at speed {}",
+                            layout,
+                            seed,
+                            speed
+                        );
+                    });
+            });
+        });
+    }
+
+    #[test]
+    fn open_ground_needs_no_input() {
+        let obstacles = builder(CANVAS_SIZE).floating_platform(100, 100).build();
+
+        assert_eq!(
+            check(&mut red_hat_boy(), obstacles, RUNNING_SPEED),
+            Solvability::Free
+        );
+    }
+
+    #[test]
+    fn a_stone_has_to_be_jumped_in_time() {
+        let obstacles = builder(CANVAS_SIZE).stone_on_ground(100).build();
+
+        let solvability = check(&mut red_hat_boy(), obstacles, RUNNING_SPEED);
+
+        let Solvability::TimingWindow(window) = solvability else {
+            panic!("unexpected {:?}", solvability);
+        };
+        assert!(
+            (MINIMUM_TIMING_WINDOW..60).contains(&window),
+            "window {}",
+            window
+        );
+    }
+
+    #[test]
+    fn an_overhead_barrier_has_to_be_slid_under() {
+        // 走るアニメーションで当たり判定が大きくなっても、滑らずにくぐれることはない
+        (0..RUNNING_SPEED).for_each(|offset| {
+            let obstacles = builder(CANVAS_SIZE + offset).overhead_barrier(100).build();
+
+            let solvability = check(&mut red_hat_boy(), obstacles, RUNNING_SPEED);
+
+            assert!(
+                matches!(solvability, Solvability::TimingWindow(window) if window >= MINIMUM_TIMING_WINDOW),
+                "{:?} at offset {}",
+                solvability,
+                offset
+            );
+        });
+    }
+
+    fn wall_of_stones() -> SegmentBuilder {
+        (0..6).fold(builder(CANVAS_SIZE), |builder, stone| {
+            builder.stone_on_ground(stone * 90)
+        })
+    }
+
+    #[test]
+    fn a_wall_of_stones_is_impossible() {
+        assert_eq!(
+            check(&mut red_hat_boy(), wall_of_stones().build(), RUNNING_SPEED),
+            Solvability::Impossible
+        );
+        assert!(!is_clearable(
+            &mut red_hat_boy(),
+            wall_of_stones().build(),
+            RUNNING_SPEED
+        ));
+    }
+
+    #[test]
+    fn a_platform_over_the_stones_makes_them_clearable() {
+        let over_the_stones = || wall_of_stones().floating_platform(60, 420).build();
+
+        assert!(check(&mut red_hat_boy(), over_the_stones(), RUNNING_SPEED).is_clearable());
+        assert!(is_clearable(
+            &mut red_hat_boy(),
+            over_the_stones(),
+            RUNNING_SPEED
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+//! 走る速さの上がり方
+//!
+//! シーンは走った距離から[`SpeedCurve`]で走る速さを決め、RedHatBoyに伝える。
+//! 障害物の組の間隔も同じ割合で広げて、組の間の時間が速さで変わらないようにする。
+
+use anyhow::{anyhow, Result};
+
+use super::red_hat_boy_states::RUNNING_SPEED;
+
+/// [`SpeedCurve::default`]の段階。走る速さ3で1分ごとに速くなる
+const DEFAULT_STEPS: [SpeedStep; 4] = [
+    SpeedStep {
+        distance: 0,
+        speed: RUNNING_SPEED,
+    },
+    SpeedStep {
+        distance: 10800,
+        speed: 4,
+    },
+    SpeedStep {
+        distance: 25200,
+        speed: 5,
+    },
+    SpeedStep {
+        distance: 43200,
+        speed: 6,
+    },
+];
+
+/// `distance`を走ったら、走る速さを`speed`にする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedStep {
+    pub distance: i32,
+    pub speed: i16,
+}
+
+/// 走った距離ごとの走る速さ。段階的に速くなる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpeedCurve {
+    /// 距離の順に並んでいて、最初の段階は距離0
+    steps: Vec<SpeedStep>,
+}
+
+impl Default for SpeedCurve {
+    fn default() -> Self {
+        SpeedCurve {
+            steps: DEFAULT_STEPS.to_vec(),
+        }
+    }
+}
+
+impl SpeedCurve {
+    /// `steps`は距離0から始まり、距離も速さも増えていくこと
+    #[allow(dead_code)]
+    pub fn new(steps: Vec<SpeedStep>) -> Result<Self> {
+        match steps.first() {
+            Some(first) if first.distance == 0 && first.speed > 0 => {}
+            _ => {
+                return Err(anyhow!(
+                    "Speed curve must start at distance 0 with a positive speed"
+                ))
+            }
+        }
+        if let Some(pair) = steps
+            .windows(2)
+            .find(|pair| pair[0].distance >= pair[1].distance || pair[0].speed > pair[1].speed)
+        {
+            return Err(anyhow!(
+                "Speed step {:?} must come after {:?}",
+                pair[1],
+                pair[0]
+            ));
+        }
+        Ok(SpeedCurve { steps })
+    }
+
+    /// `distance`を走った時の走る速さ
+    pub fn speed_at(&self, distance: i32) -> i16 {
+        self.steps
+            .iter()
+            .take_while(|step| step.distance <= distance)
+            .last()
+            .map_or(self.steps[0].speed, |step| step.speed)
+    }
+
+    /// 途中で変わる走る速さを、遅い順にすべて
+    #[allow(dead_code)]
+    pub fn speeds(&self) -> impl Iterator<Item = i16> + '_ {
+        self.steps.iter().map(|step| step.speed)
+    }
+
+    /// 走り始めの速さで`length`だった長さを、`distance`を走った時の速さに合わせて伸ばす
+    pub fn scale(&self, length: i16, distance: i32) -> i16 {
+        let scaled =
+            i32::from(length) * i32::from(self.speed_at(distance)) / i32::from(self.steps[0].speed);
+        scaled as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_goes_up_in_steps() {
+        let curve = SpeedCurve::default();
+
+        assert_eq!(curve.speed_at(0), RUNNING_SPEED);
+        assert_eq!(curve.speed_at(10799), RUNNING_SPEED);
+        assert_eq!(curve.speed_at(10800), 4);
+        assert_eq!(curve.speed_at(1_000_000), 6);
+        assert_eq!(curve.speeds().collect::<Vec<i16>>(), vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn lengths_grow_with_the_speed() {
+        let curve = SpeedCurve::new(vec![
+            SpeedStep {
+                distance: 0,
+                speed: 3,
+            },
+            SpeedStep {
+                distance: 100,
+                speed: 6,
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(curve.scale(20, 0), 20);
+        assert_eq!(curve.scale(20, 100), 40);
+    }
+
+    #[test]
+    fn invalid_curves_are_rejected() {
+        let step = |distance, speed| SpeedStep { distance, speed };
+
+        assert!(SpeedCurve::new(vec![]).is_err());
+        assert!(SpeedCurve::new(vec![step(10, 3)]).is_err());
+        assert!(SpeedCurve::new(vec![step(0, 0)]).is_err());
+        assert!(SpeedCurve::new(vec![step(0, 3), step(0, 4)]).is_err());
+        assert!(SpeedCurve::new(vec![step(0, 4), step(100, 3)]).is_err());
+    }
+}
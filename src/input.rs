@@ -0,0 +1,489 @@
+//! キー入力をゲームの操作に変換する。
+//!
+//! ゲームは物理的なキーコードではなく[`Action`]で入力を読む。
+//! どのキーがどの操作になるかは[`KeyBindings`]で決まり、実行中に変更したり、保存したりできる。
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{browser, engine::KeyState};
+
+/// ゲームの操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Jump,
+    Slide,
+    /// 走り始める
+    Start,
+    /// ゲームオーバー画面で次のゲームに進む
+    Confirm,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; 5] = [
+        Action::Jump,
+        Action::Slide,
+        Action::Start,
+        Action::Confirm,
+        Action::Pause,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// `Jump`のような操作の名前から操作を探す
+    pub fn from_name(name: &str) -> Option<Self> {
+        Action::ALL
+            .into_iter()
+            .find(|action| format!("{:?}", action) == name)
+    }
+}
+
+/// あるtickで入力されている操作の集合
+///
+/// [`KeyState`]と同じく、押されているかに加えてこのtickでの変化を持つ。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActionState {
+    pressed: u8,
+    just_pressed: u8,
+    just_released: u8,
+}
+
+impl ActionState {
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.pressed & action.bit() != 0
+    }
+
+    /// このtickの間に押されたか
+    pub fn is_just_pressed(&self, action: Action) -> bool {
+        self.just_pressed & action.bit() != 0
+    }
+
+    /// このtickの間に離されたか
+    #[allow(dead_code)]
+    pub fn is_just_released(&self, action: Action) -> bool {
+        self.just_released & action.bit() != 0
+    }
+
+    pub fn set_pressed(&mut self, action: Action) {
+        self.pressed |= action.bit();
+    }
+
+    pub fn set_just_pressed(&mut self, action: Action) {
+        self.just_pressed |= action.bit();
+    }
+
+    pub fn set_just_released(&mut self, action: Action) {
+        self.just_released |= action.bit();
+    }
+
+    /// 記録用の3バイトの表現。押されている、押された、離された操作のビットの順に並ぶ
+    pub fn to_bytes(self) -> [u8; 3] {
+        [self.pressed, self.just_pressed, self.just_released]
+    }
+
+    pub fn from_bytes([pressed, just_pressed, just_released]: [u8; 3]) -> Self {
+        ActionState {
+            pressed,
+            just_pressed,
+            just_released,
+        }
+    }
+}
+
+const KEY_BINDINGS_STORAGE_KEY: &str = "key-bindings";
+
+/// 操作ごとに割り当てられたキーコードの表
+///
+/// 一つの操作に複数のキーを割り当てられる。
+/// キーコードは[`web_sys::KeyboardEvent::code`]の値か、
+/// [`crate::engine::gamepad`]が作るゲームパッドや[`crate::engine::gesture`]が作るタッチの
+/// 仮想的なキーコード。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    bindings: BTreeMap<Action, Vec<String>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut key_bindings = KeyBindings {
+            bindings: BTreeMap::new(),
+        };
+        // ゲームパッドのボタンの番号は標準の配置のもの
+        // 0: 下のボタン(A), 8: セレクト, 9: スタート, 12: 十字キー上, 13: 十字キー下, 15: 十字キー右
+        // スタートは走り始めるのに使うので、一時停止はセレクトにする
+        // 軸1は左スティックの上下
+        // タッチはタップでジャンプ、下へのスワイプでスライディング、タップで開始
+        key_bindings.rebind(
+            Action::Jump,
+            &[
+                "ArrowUp",
+                "GamepadButton0",
+                "GamepadButton12",
+                "GamepadAxis1-",
+                "TouchTap",
+                "TouchSwipeUp",
+            ],
+        );
+        key_bindings.rebind(
+            Action::Slide,
+            &[
+                "ArrowDown",
+                "GamepadButton13",
+                "GamepadAxis1+",
+                "TouchSwipeDown",
+            ],
+        );
+        key_bindings.rebind(
+            Action::Start,
+            &[
+                "ArrowRight",
+                "GamepadButton0",
+                "GamepadButton9",
+                "GamepadButton15",
+                "TouchTap",
+            ],
+        );
+        key_bindings.rebind(
+            Action::Confirm,
+            &["Enter", "GamepadButton0", "GamepadButton9", "TouchTap"],
+        );
+        key_bindings.rebind(Action::Pause, &["KeyP", "Escape", "GamepadButton8"]);
+        key_bindings
+    }
+}
+
+impl KeyBindings {
+    /// 保存された割り当てを読み込む。
+    /// 保存されていないか壊れていれば既定の割り当てを使う。
+    pub fn load_or_default() -> Self {
+        match browser::load_item(KEY_BINDINGS_STORAGE_KEY)
+            .and_then(|json| json.map(|json| KeyBindings::from_json(&json)).transpose())
+        {
+            Ok(Some(key_bindings)) => key_bindings,
+            Ok(None) => KeyBindings::default(),
+            Err(err) => {
+                error!("Could not load key bindings {:#?}", err);
+                KeyBindings::default()
+            }
+        }
+    }
+
+    /// 割り当てを保存し、次回の起動時に読み込まれるようにする。
+    pub fn save(&self) -> Result<()> {
+        browser::save_item(KEY_BINDINGS_STORAGE_KEY, &self.to_json()?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|err| anyhow!("Could not serialize key bindings {}", err))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|err| anyhow!("Could not parse key bindings {}", err))
+    }
+
+    /// `action`に割り当てられたキーコード
+    pub fn keys(&self, action: Action) -> &[String] {
+        self.bindings
+            .get(&action)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// `action`の割り当てを`codes`で置き換える
+    pub fn rebind(&mut self, action: Action, codes: &[&str]) {
+        self.bindings
+            .insert(action, codes.iter().map(|&code| code.into()).collect());
+    }
+
+    /// `action`に`code`を追加で割り当てる
+    pub fn bind(&mut self, action: Action, code: &str) {
+        let codes = self.bindings.entry(action).or_default();
+        if !codes.iter().any(|bound| bound == code) {
+            codes.push(code.into());
+        }
+    }
+
+    /// `action`から`code`の割り当てを外す
+    pub fn unbind(&mut self, action: Action, code: &str) {
+        if let Some(codes) = self.bindings.get_mut(&action) {
+            codes.retain(|bound| bound != code);
+        }
+    }
+
+    /// URLのクエリで割り当てを変更し、変更があれば保存する。
+    ///
+    /// `?bind=Jump:KeyZ,Slide:KeyS`でキーを追加し、`?unbind=Jump:ArrowUp`でキーを外す。
+    /// 外してから追加するので、同じキーを別の操作に付け替えられる。
+    pub fn rebind_from_query(&mut self) -> Result<()> {
+        if self.apply_query(browser::query_param)? {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// `query`で取り出したクエリの値で割り当てを変更する。変更があれば`true`を返す
+    fn apply_query(&mut self, query: impl Fn(&str) -> Result<Option<String>>) -> Result<bool> {
+        let (unbind, bind) = (query("unbind")?, query("bind")?);
+        let unbind = parse_pairs(unbind.as_deref().unwrap_or_default())?;
+        let bind = parse_pairs(bind.as_deref().unwrap_or_default())?;
+        let before = self.clone();
+        unbind
+            .into_iter()
+            .for_each(|(action, code)| self.unbind(action, code));
+        bind.into_iter()
+            .for_each(|(action, code)| self.bind(action, code));
+        Ok(*self != before)
+    }
+
+    /// 押されているキーを操作に変換する
+    ///
+    /// 一つの操作に割り当てられたキーのうち、どれか一つでも押されていれば操作は押されている。
+    /// 別のキーで既に押されている操作に新しいキーを重ねても、押されたことにはならない。
+    pub fn action_state(&self, key_state: &KeyState) -> ActionState {
+        let mut action_state = ActionState::default();
+        Action::ALL.iter().for_each(|&action| {
+            let keys = self.keys(action);
+            let pressed = keys.iter().any(|code| key_state.is_pressed(code));
+            let pressed_before = keys.iter().any(|code| {
+                !key_state.is_just_pressed(code)
+                    && (key_state.is_pressed(code) || key_state.is_just_released(code))
+            });
+            if pressed {
+                action_state.set_pressed(action);
+            }
+            if !pressed_before && keys.iter().any(|code| key_state.is_just_pressed(code)) {
+                action_state.set_just_pressed(action);
+            }
+            if !pressed && keys.iter().any(|code| key_state.is_just_released(code)) {
+                action_state.set_just_released(action);
+            }
+        });
+        action_state
+    }
+}
+
+/// `Jump:KeyZ,Slide:KeyS`のような、操作とキーコードの組の並びを読む
+fn parse_pairs(value: &str) -> Result<Vec<(Action, &str)>> {
+    value
+        .split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, code) = pair
+                .split_once(':')
+                .filter(|(_, code)| !code.is_empty())
+                .ok_or_else(|| anyhow!("Invalid key binding {}", pair))?;
+            let action =
+                Action::from_name(name).ok_or_else(|| anyhow!("Unknown action {}", name))?;
+            Ok((action, code))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_match_the_original_keys() {
+        let key_bindings = KeyBindings::default();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowUp");
+        key_state.set_pressed("Enter");
+
+        let action_state = key_bindings.action_state(&key_state);
+
+        assert!(action_state.is_pressed(Action::Jump));
+        assert!(action_state.is_pressed(Action::Confirm));
+        assert!(!action_state.is_pressed(Action::Slide));
+        assert!(!action_state.is_pressed(Action::Start));
+    }
+
+    #[test]
+    fn any_of_several_keys_triggers_the_action() {
+        let key_bindings = KeyBindings::default();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("Escape");
+
+        assert!(key_bindings
+            .action_state(&key_state)
+            .is_pressed(Action::Pause));
+    }
+
+    #[test]
+    fn rebinding_replaces_the_old_keys() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.rebind(Action::Jump, &["KeyZ", "Space"]);
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowUp");
+
+        assert!(!key_bindings
+            .action_state(&key_state)
+            .is_pressed(Action::Jump));
+
+        key_state.set_pressed("Space");
+        assert!(key_bindings
+            .action_state(&key_state)
+            .is_pressed(Action::Jump));
+    }
+
+    #[test]
+    fn bind_and_unbind_single_keys() {
+        let mut key_bindings = KeyBindings::default();
+
+        key_bindings.rebind(Action::Slide, &["ArrowDown"]);
+
+        key_bindings.bind(Action::Slide, "KeyS");
+        key_bindings.bind(Action::Slide, "KeyS");
+        assert_eq!(key_bindings.keys(Action::Slide), ["ArrowDown", "KeyS"]);
+
+        key_bindings.unbind(Action::Slide, "ArrowDown");
+        assert_eq!(key_bindings.keys(Action::Slide), ["KeyS"]);
+    }
+
+    #[test]
+    fn bindings_survive_a_json_round_trip() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.rebind(Action::Jump, &["KeyW"]);
+
+        let json = key_bindings.to_json().unwrap();
+
+        assert_eq!(KeyBindings::from_json(&json).unwrap(), key_bindings);
+        assert!(json.contains("\"Jump\":[\"KeyW\"]"));
+    }
+
+    #[test]
+    fn action_is_just_pressed_only_on_the_first_tick() {
+        let key_bindings = KeyBindings::default();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("ArrowUp");
+
+        let first = key_bindings.action_state(&key_state);
+        key_state.tick();
+        let second = key_bindings.action_state(&key_state);
+
+        assert!(first.is_just_pressed(Action::Jump));
+        assert!(second.is_pressed(Action::Jump));
+        assert!(!second.is_just_pressed(Action::Jump));
+    }
+
+    #[test]
+    fn second_key_for_a_held_action_is_not_a_new_press() {
+        let key_bindings = KeyBindings::default();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("KeyP");
+        key_state.tick();
+
+        key_state.set_pressed("Escape");
+        key_state.set_released("KeyP");
+        let action_state = key_bindings.action_state(&key_state);
+
+        assert!(action_state.is_pressed(Action::Pause));
+        assert!(!action_state.is_just_pressed(Action::Pause));
+        assert!(!action_state.is_just_released(Action::Pause));
+    }
+
+    #[test]
+    fn tap_between_ticks_is_pressed_and_released() {
+        let key_bindings = KeyBindings::default();
+        let mut key_state = KeyState::new();
+
+        key_state.set_pressed("Enter");
+        key_state.set_released("Enter");
+        let action_state = key_bindings.action_state(&key_state);
+
+        assert!(!action_state.is_pressed(Action::Confirm));
+        assert!(action_state.is_just_pressed(Action::Confirm));
+        assert!(action_state.is_just_released(Action::Confirm));
+    }
+
+    #[test]
+    fn gamepad_buttons_are_bound_by_default() {
+        let key_bindings = KeyBindings::default();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("GamepadAxis1-");
+        key_state.set_pressed("GamepadButton13");
+
+        let action_state = key_bindings.action_state(&key_state);
+
+        assert!(action_state.is_just_pressed(Action::Jump));
+        assert!(action_state.is_just_pressed(Action::Slide));
+    }
+
+    #[test]
+    fn touch_gestures_are_bound_by_default() {
+        let key_bindings = KeyBindings::default();
+        let mut key_state = KeyState::new();
+        key_state.set_pressed("TouchTap");
+        key_state.set_released("TouchTap");
+        key_state.set_pressed("TouchSwipeDown");
+        key_state.set_released("TouchSwipeDown");
+
+        let action_state = key_bindings.action_state(&key_state);
+
+        assert!(action_state.is_just_pressed(Action::Jump));
+        assert!(action_state.is_just_pressed(Action::Start));
+        assert!(action_state.is_just_pressed(Action::Confirm));
+        assert!(action_state.is_just_pressed(Action::Slide));
+    }
+
+    #[test]
+    fn broken_json_is_an_error() {
+        assert!(KeyBindings::from_json("{\"Jump\": 1}").is_err());
+    }
+
+    fn query<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Result<Option<String>> + 'a {
+        move |name| {
+            Ok(pairs
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string()))
+        }
+    }
+
+    #[test]
+    fn query_moves_a_key_to_another_action() {
+        let mut key_bindings = KeyBindings::default();
+
+        let changed = key_bindings
+            .apply_query(query(&[
+                ("unbind", "Jump:ArrowUp"),
+                ("bind", "Slide:ArrowUp,Jump:KeyZ"),
+            ]))
+            .unwrap();
+
+        assert!(changed);
+        assert!(!key_bindings.keys(Action::Jump).contains(&"ArrowUp".into()));
+        assert!(key_bindings.keys(Action::Jump).contains(&"KeyZ".into()));
+        assert!(key_bindings.keys(Action::Slide).contains(&"ArrowUp".into()));
+    }
+
+    #[test]
+    fn query_without_changes_does_not_need_saving() {
+        let mut key_bindings = KeyBindings::default();
+
+        assert!(!key_bindings.apply_query(query(&[])).unwrap());
+        assert!(!key_bindings
+            .apply_query(query(&[("bind", "Jump:ArrowUp")]))
+            .unwrap());
+        assert_eq!(key_bindings, KeyBindings::default());
+    }
+
+    #[test]
+    fn unknown_action_in_query_is_an_error() {
+        let mut key_bindings = KeyBindings::default();
+
+        assert!(key_bindings
+            .apply_query(query(&[("bind", "Fly:KeyF")]))
+            .is_err());
+        assert!(key_bindings
+            .apply_query(query(&[("bind", "Jump")]))
+            .is_err());
+        assert_eq!(key_bindings, KeyBindings::default());
+    }
+}
@@ -2,6 +2,9 @@
 mod browser;
 mod engine;
 mod game;
+mod input;
+mod replay;
+mod score;
 mod segment;
 mod sound;
 use anyhow::Result;
@@ -0,0 +1,322 @@
+//! 一回のプレイの記録と再生
+//!
+//! 障害物の選択に使う乱数のシードと組の作り方、tickごとにゲームに与えた[`ActionState`]を記録する。
+//! 同じシードで始めて同じ入力を順番に与えれば、同じプレイがtick単位で再現される。
+//!
+//! ファイルの形式は次の通り。数値はすべてリトルエンディアン。
+//! - マジックナンバー`WTDR`（4バイト）
+//! - 形式のバージョン（1バイト）
+//! - シード（u64）
+//! - 障害物の組の作り方（[`SegmentSource::to_byte`]の1バイト）
+//! - 記録したtick数（u32）
+//! - 同じ入力が続くtick数（u16）と、その入力（[`ActionState::to_bytes`]の3バイト）の組の並び
+
+use std::fmt::Write;
+
+use anyhow::{anyhow, Result};
+
+use crate::{browser, input::ActionState, segment::SegmentSource};
+
+/// ファイルの形式のバージョン。形式を変えたら上げる
+pub const REPLAY_VERSION: u8 = 2;
+const MAGIC: &[u8; 4] = b"WTDR";
+/// 同じ入力が続くtick数と、その入力の組のバイト数
+const RUN_SIZE: usize = 5;
+const REPLAY_STORAGE_KEY: &str = "last-replay";
+
+/// 一回のプレイの記録
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    seed: u64,
+    segment_source: SegmentSource,
+    inputs: Vec<ActionState>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, segment_source: SegmentSource) -> Self {
+        Replay {
+            seed,
+            segment_source,
+            inputs: vec![],
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn segment_source(&self) -> SegmentSource {
+        self.segment_source
+    }
+
+    /// 記録したtick数
+    pub fn ticks(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// 1tick分の入力を記録する
+    pub fn record(&mut self, action_state: ActionState) {
+        self.inputs.push(action_state);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(REPLAY_VERSION);
+        bytes.extend(self.seed.to_le_bytes());
+        bytes.push(self.segment_source.to_byte());
+        bytes.extend((self.inputs.len() as u32).to_le_bytes());
+        let mut runs: Vec<(u16, ActionState)> = vec![];
+        self.inputs
+            .iter()
+            .for_each(|&action_state| match runs.last_mut() {
+                Some((length, last)) if *last == action_state && *length < u16::MAX => {
+                    *length += 1;
+                }
+                _ => runs.push((1, action_state)),
+            });
+        runs.iter().for_each(|(length, action_state)| {
+            bytes.extend(length.to_le_bytes());
+            bytes.extend(action_state.to_bytes());
+        });
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        if take(&mut bytes, MAGIC.len())? != MAGIC {
+            return Err(anyhow!("Not a replay file"));
+        }
+        let version = take(&mut bytes, 1)?[0];
+        if version != REPLAY_VERSION {
+            return Err(anyhow!(
+                "Unsupported replay version {} (expected {})",
+                version,
+                REPLAY_VERSION
+            ));
+        }
+        let seed = u64::from_le_bytes(take(&mut bytes, 8)?.try_into()?);
+        let segment_source = SegmentSource::from_byte(take(&mut bytes, 1)?[0])?;
+        let ticks = u32::from_le_bytes(take(&mut bytes, 4)?.try_into()?) as usize;
+        // 壊れたヘッダで大きな領域を確保しないように、残りのバイトで書ける数までにする
+        let encodable_ticks = bytes.len() / RUN_SIZE * usize::from(u16::MAX);
+        if ticks > encodable_ticks {
+            return Err(anyhow!(
+                "Replay has {} ticks but can hold at most {}",
+                ticks,
+                encodable_ticks
+            ));
+        }
+        let mut inputs = Vec::with_capacity(ticks);
+        while !bytes.is_empty() {
+            let length = u16::from_le_bytes(take(&mut bytes, 2)?.try_into()?);
+            // 書き込む時に長さ0の並びは作らないので、あれば壊れている
+            if length == 0 {
+                return Err(anyhow!("Replay file has an empty run of inputs"));
+            }
+            let action_state = ActionState::from_bytes(take(&mut bytes, 3)?.try_into()?);
+            inputs.extend(std::iter::repeat(action_state).take(length as usize));
+            if inputs.len() > ticks {
+                return Err(anyhow!("Replay has more inputs than its {} ticks", ticks));
+            }
+        }
+        if inputs.len() != ticks {
+            return Err(anyhow!(
+                "Replay has {} ticks but {} were recorded",
+                ticks,
+                inputs.len()
+            ));
+        }
+        Ok(Replay {
+            seed,
+            segment_source,
+            inputs,
+        })
+    }
+
+    /// localStorageなど文字列しか保存できない場所のための16進数の表現
+    pub fn to_hex(&self) -> String {
+        self.to_bytes().iter().fold(String::new(), |mut hex, byte| {
+            // Stringへの書き込みは失敗しない
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(anyhow!("Replay is not a hex string"));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|err| anyhow!("Replay is not a hex string {}", err))?;
+        Replay::from_bytes(&bytes)
+    }
+}
+
+/// `bytes`の先頭から`length`バイトを取り出す
+fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+    if bytes.len() < length {
+        return Err(anyhow!("Replay file is truncated"));
+    }
+    let (head, tail) = bytes.split_at(length);
+    *bytes = tail;
+    Ok(head)
+}
+
+/// 記録された入力を1tickずつ取り出す
+pub struct ReplayPlayer {
+    replay: Replay,
+    tick: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayer { replay, tick: 0 }
+    }
+
+    /// 次のtickの入力。記録が終わっていればNoneを返す。
+    pub fn next_input(&mut self) -> Option<ActionState> {
+        let input = self.replay.inputs.get(self.tick).copied()?;
+        self.tick += 1;
+        Some(input)
+    }
+}
+
+/// 記録の保存先
+pub trait ReplayStore {
+    /// 最後のプレイとして保存する
+    fn save(&self, replay: &Replay) -> Result<()>;
+    /// 最後に保存したプレイ
+    fn load(&self) -> Result<Option<Replay>>;
+}
+
+/// 最後のプレイをlocalStorageに保存する[`ReplayStore`]
+pub struct LocalStorageReplays;
+
+impl ReplayStore for LocalStorageReplays {
+    fn save(&self, replay: &Replay) -> Result<()> {
+        browser::save_item(REPLAY_STORAGE_KEY, &replay.to_hex())
+    }
+
+    fn load(&self) -> Result<Option<Replay>> {
+        browser::load_item(REPLAY_STORAGE_KEY)?
+            .map(|hex| Replay::from_hex(&hex))
+            .transpose()
+    }
+}
+
+/// 保存した記録をメモリに置いておくだけの[`ReplayStore`]
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryReplays {
+    replay: std::cell::RefCell<Option<Replay>>,
+}
+
+#[cfg(test)]
+impl ReplayStore for MemoryReplays {
+    fn save(&self, replay: &Replay) -> Result<()> {
+        self.replay.replace(Some(replay.clone()));
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Option<Replay>> {
+        Ok(self.replay.borrow().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Action;
+
+    fn pressed(action: Action) -> ActionState {
+        let mut action_state = ActionState::default();
+        action_state.set_pressed(action);
+        action_state
+    }
+
+    fn sample_replay() -> Replay {
+        let mut replay = Replay::new(0x0123_4567_89ab_cdef, SegmentSource::Generated);
+        (0..100).for_each(|_| replay.record(ActionState::default()));
+        let mut jump = pressed(Action::Jump);
+        jump.set_just_pressed(Action::Jump);
+        replay.record(jump);
+        (0..20).for_each(|_| replay.record(pressed(Action::Jump)));
+        replay
+    }
+
+    #[test]
+    fn replay_survives_a_round_trip() {
+        let replay = sample_replay();
+
+        assert_eq!(Replay::from_bytes(&replay.to_bytes()).unwrap(), replay);
+        assert_eq!(Replay::from_hex(&replay.to_hex()).unwrap(), replay);
+    }
+
+    #[test]
+    fn repeated_inputs_are_stored_once() {
+        let bytes = sample_replay().to_bytes();
+
+        // ヘッダ18バイトと、3つの連続した入力がそれぞれ5バイト
+        assert_eq!(bytes.len(), 18 + 3 * 5);
+    }
+
+    #[test]
+    fn long_runs_are_split() {
+        let mut replay = Replay::new(1, SegmentSource::Library);
+        (0..70000).for_each(|_| replay.record(ActionState::default()));
+
+        let bytes = replay.to_bytes();
+
+        assert_eq!(bytes.len(), 18 + 2 * 5);
+        assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = sample_replay().to_bytes();
+        bytes[4] = REPLAY_VERSION + 1;
+
+        assert!(Replay::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = sample_replay().to_bytes();
+
+        assert!(Replay::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Replay::from_bytes(&bytes[..bytes.len() - 5]).is_err());
+        assert!(Replay::from_bytes(b"WTD").is_err());
+        assert!(Replay::from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn tick_counts_the_file_cannot_hold_are_rejected() {
+        let mut bytes = sample_replay().to_bytes();
+        bytes[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(Replay::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn empty_runs_are_rejected() {
+        let mut bytes = sample_replay().to_bytes();
+        // 最初の並びの前に、長さ0の並びを差し込む
+        bytes.splice(18..18, [0, 0, 0, 0, 0]);
+
+        assert!(Replay::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn player_returns_inputs_in_order_then_stops() {
+        let mut player = ReplayPlayer::new(sample_replay());
+
+        assert_eq!(player.next_input(), Some(ActionState::default()));
+        let inputs: Vec<ActionState> = std::iter::from_fn(|| player.next_input()).collect();
+
+        assert_eq!(inputs.len(), 120);
+        assert!(inputs[99].is_just_pressed(Action::Jump));
+        assert_eq!(player.next_input(), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! 最高記録
+//!
+//! 記録は走り続けたtick数で、遊び方ごとに別のキーで保存する。
+
+use anyhow::{anyhow, Result};
+
+use crate::browser;
+
+/// 最高記録の保存先
+pub trait ScoreStore {
+    fn best(&self, key: &str) -> Result<Option<i32>>;
+    fn save_best(&self, key: &str, score: i32) -> Result<()>;
+}
+
+/// `score`が最高記録を超えていれば保存し、更新後の最高記録を返す。
+pub fn record_score(store: &dyn ScoreStore, key: &str, score: i32) -> Result<i32> {
+    match store.best(key)? {
+        Some(best) if best >= score => Ok(best),
+        _ => {
+            store.save_best(key, score)?;
+            Ok(score)
+        }
+    }
+}
+
+/// 最高記録をlocalStorageに保存する[`ScoreStore`]
+pub struct LocalStorageScores;
+
+impl ScoreStore for LocalStorageScores {
+    fn best(&self, key: &str) -> Result<Option<i32>> {
+        browser::load_item(key)?
+            .map(|score| {
+                score
+                    .parse()
+                    .map_err(|err| anyhow!("Invalid score {} for {} {}", score, key, err))
+            })
+            .transpose()
+    }
+
+    fn save_best(&self, key: &str, score: i32) -> Result<()> {
+        browser::save_item(key, &score.to_string())
+    }
+}
+
+/// 最高記録をメモリに置いておくだけの[`ScoreStore`]
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryScores {
+    scores: std::cell::RefCell<std::collections::HashMap<String, i32>>,
+}
+
+#[cfg(test)]
+impl ScoreStore for MemoryScores {
+    fn best(&self, key: &str) -> Result<Option<i32>> {
+        Ok(self.scores.borrow().get(key).copied())
+    }
+
+    fn save_best(&self, key: &str, score: i32) -> Result<()> {
+        self.scores.borrow_mut().insert(key.into(), score);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_higher_score_replaces_the_best() {
+        let store = MemoryScores::default();
+
+        assert_eq!(record_score(&store, "best", 300).unwrap(), 300);
+        assert_eq!(record_score(&store, "best", 200).unwrap(), 300);
+        assert_eq!(record_score(&store, "best", 400).unwrap(), 400);
+        assert_eq!(store.best("best").unwrap(), Some(400));
+    }
+
+    #[test]
+    fn keys_are_recorded_separately() {
+        let store = MemoryScores::default();
+
+        record_score(&store, "endless", 500).unwrap();
+        record_score(&store, "daily", 100).unwrap();
+
+        assert_eq!(store.best("endless").unwrap(), Some(500));
+        assert_eq!(store.best("daily").unwrap(), Some(100));
+    }
+}
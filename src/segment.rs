@@ -1,68 +1,83 @@
-use std::rc::Rc;
+//! 障害物の組
+//!
+//! ゲームが使う組は`static/segments.json`に書かれていて、起動時に[`SegmentLibrary`]として読み込む。
+//! 走り始めに置く最初の組だけは、[`stone_and_platform`]で[`SegmentBuilder`]を使って作る。
+//! どの組を次に置くかは[`selection`]で決める。
+//! [`generator`]は、決まった組の代わりに部品を組み合わせて新しい組を作る。
+//! どちらを使うかは[`SegmentSource`]で選ぶ。
 
-use web_sys::HtmlImageElement;
+#[cfg(test)]
+pub mod fixtures;
+pub mod generator;
+pub mod selection;
+
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
 
 use crate::{
-    engine::{Image, Point, Rect, SpriteSheet},
-    game::{Barrier, Obstacle, Platform, RedHatBoy},
+    engine::{Image, ImageHandle, Point, Rect, SpriteSheet},
+    game::{Barrier, Obstacle, OverheadBarrier, Platform, PlatformSides, RedHatBoy},
 };
 
-const STONE_ON_GROUND: i16 = 546;
+/// 障害物を置ける一番下の高さ。画面の下端で、地面の高さ
+const SEGMENT_FLOOR: i16 = 600;
+/// 足場の上の石は、浮いて見えないように足場に少しめり込ませる
+const STONE_SINK_INTO_PLATFORM: i16 = 1;
+
+/// 次に置く障害物の組をどこから持ってくるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SegmentSource {
+    /// [`SegmentLibrary`]の決まった組から選ぶ
+    #[default]
+    Library,
+    /// [`generator::SegmentGenerator`]で部品を組み合わせて作る
+    Generated,
+}
+
+impl SegmentSource {
+    /// `?segments=`に書く名前から選ぶ
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "library" => Ok(SegmentSource::Library),
+            "generated" => Ok(SegmentSource::Generated),
+            _ => Err(anyhow!(
+                "Unknown segment source {} (expected library or generated)",
+                name
+            )),
+        }
+    }
+
+    /// 記録用の1バイトの表現
+    pub fn to_byte(self) -> u8 {
+        match self {
+            SegmentSource::Library => 0,
+            SegmentSource::Generated => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(SegmentSource::Library),
+            1 => Ok(SegmentSource::Generated),
+            _ => Err(anyhow!("Unknown segment source {}", byte)),
+        }
+    }
+}
 
 pub fn stone_and_platform(
-    stone_element: HtmlImageElement,
-    sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
-    const INITIAL_STONE_OFFSET: i16 = 100;
-    const LOW_PLATFORM: i16 = 420;
-    const FIRST_PLATFORM: i16 = 150;
-    // 障害物の設定
-    let stone_image = Image::new(
-        stone_element,
-        Point {
-            x: offset_x + INITIAL_STONE_OFFSET,
-            y: STONE_ON_GROUND,
-        },
-    );
-    let stone: Barrier = stone_image.into();
-    // 足場の設定
-    let platform = create_floating_platform(
-        sprite_sheet,
-        Point {
-            x: offset_x + FIRST_PLATFORM,
-            y: LOW_PLATFORM,
-        },
-    );
-    vec![Box::new(stone), Box::new(platform)]
-}
-
-pub fn stone_and_platform2(
-    stone_element: HtmlImageElement,
+    stone_element: ImageHandle,
     sprite_sheet: Rc<SpriteSheet>,
     offset_x: i16,
 ) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
-    const INITIAL_STONE_OFFSET: i16 = 150;
-    const LOW_PLATFORM: i16 = 420;
-    const FIRST_PLATFORM: i16 = 150;
-    // 障害物の設定
-    let stone_image = Image::new(
-        stone_element,
-        Point {
-            x: offset_x + INITIAL_STONE_OFFSET,
-            y: STONE_ON_GROUND,
-        },
-    );
-    let stone: Barrier = stone_image.into();
-    // 足場の設定
-    let platform = create_floating_platform(
-        sprite_sheet,
-        Point {
-            x: offset_x + FIRST_PLATFORM,
-            y: LOW_PLATFORM,
-        },
-    );
-    vec![Box::new(stone), Box::new(platform)]
+    SegmentBuilder::new(stone_element, sprite_sheet, offset_x)
+        .stone_on_ground(100)
+        .floating_platform(150, 420)
+        .build()
 }
 
 const FLOATING_PLATFORM_SPRITE_NAMES: [&str; 3] = ["13.png", "14.png", "15.png"];
@@ -71,181 +86,670 @@ const FLOATING_PLATFORM_SPRITE_OFFSETS: [Point; 3] = [
     Point { x: 128, y: 0 },
     Point { x: 256, y: 0 },
 ];
+const FLOATING_PLATFORM_WIDTH: i16 = 384;
 const FLOATING_PLATFORM_BONDING_BOXES: [&Rect; 3] = [
     &Rect::new_from_x_y(0, 0, 60, 54),
-    &Rect::new_from_x_y(60, 0, 384 - (60 * 2), 93),
-    &Rect::new_from_x_y(384 - 60, 0, 60, 54),
+    &Rect::new_from_x_y(60, 0, FLOATING_PLATFORM_WIDTH - (60 * 2), 93),
+    &Rect::new_from_x_y(FLOATING_PLATFORM_WIDTH - 60, 0, 60, 54),
 ];
 
-fn create_floating_platform(sprite_sheet: Rc<SpriteSheet>, position: Point) -> Platform {
-    Platform::new(
-        sprite_sheet,
-        &FLOATING_PLATFORM_BONDING_BOXES,
-        &FLOATING_PLATFORM_SPRITE_NAMES,
-        &FLOATING_PLATFORM_SPRITE_OFFSETS,
-        position,
-    )
-}
+/// 垂れ下がる障害物の下端。走っている時の頭より低く、スライディング中の頭より高い
+const OVERHEAD_BARRIER_BOTTOM: i16 = 525;
+/// 土のタイルを縦に積み、一番下だけ底の見えるタイルにする
+const OVERHEAD_BARRIER_SPRITE_NAMES: [&str; 5] = ["5.png", "5.png", "5.png", "5.png", "9.png"];
+const OVERHEAD_BARRIER_SPRITE_OFFSETS: [Point; 5] = [
+    Point { x: 0, y: 0 },
+    Point { x: 0, y: 128 },
+    Point { x: 0, y: 256 },
+    Point { x: 0, y: 384 },
+    Point { x: 0, y: 512 },
+];
+const OVERHEAD_BARRIER_BOUNDING_BOX: Rect = Rect::new_from_x_y(0, 0, 128, 640);
 
-pub fn stone_and_platform3(
-    stone_element: HtmlImageElement,
-    sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
-    const INITIAL_STONE_OFFSET: i16 = 200;
-    const STONE_ON_PLATFORM: i16 = 420 - 93;
-    const LOW_PLATFORM: i16 = 380;
-    const FIRST_PLATFORM: i16 = 150;
-    // 障害物の設定
-    let stone_image = Image::new(
-        stone_element,
-        Point {
-            x: offset_x + INITIAL_STONE_OFFSET,
-            y: STONE_ON_PLATFORM,
-        },
-    );
-    let stone: Barrier = stone_image.into();
-    // 足場の設定
-    let platform = create_floating_platform(
-        sprite_sheet,
-        Point {
-            x: offset_x + FIRST_PLATFORM,
-            y: LOW_PLATFORM,
-        },
-    );
-    vec![Box::new(stone), Box::new(platform)]
-}
-
-pub fn stone_and_platform4(
-    stone_element: HtmlImageElement,
-    sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
-    const INITIAL_STONE_OFFSET: i16 = 300;
-    const STONE_ON_PLATFORM: i16 = 420 - 93;
-    const LOW_PLATFORM: i16 = 380;
-    const FIRST_PLATFORM: i16 = 150;
-    // 障害物の設定
-    let stone_image = Image::new(
-        stone_element,
-        Point {
-            x: offset_x + INITIAL_STONE_OFFSET,
-            y: STONE_ON_PLATFORM,
-        },
-    );
-    let stone: Barrier = stone_image.into();
-    // 足場の設定
-    let platform = create_floating_platform(
-        sprite_sheet,
-        Point {
-            x: offset_x + FIRST_PLATFORM,
-            y: LOW_PLATFORM,
-        },
-    );
-    vec![Box::new(stone), Box::new(platform)]
-}
-
-pub fn platform_and_platform(
-    stone_element: HtmlImageElement,
-    sprite_sheet: Rc<SpriteSheet>,
-    offset_x: i16,
-) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
-    const STONE_OFFSET1: i16 = 150;
-    const STONE_OFFSET2: i16 = 200;
-    const STONE_ON_PLATFORM: i16 = 340 - 93;
-    const LOW_PLATFORM1: i16 = 300;
-    const FIRST_PLATFORM1: i16 = 50;
-    const LOW_PLATFORM2: i16 = 100;
-    const FIRST_PLATFORM2: i16 = 300;
-    // 障害物の設定
-    let stone_image1 = Image::new(
-        stone_element.clone(),
-        Point {
-            x: offset_x + STONE_OFFSET1,
-            y: STONE_ON_GROUND,
-        },
-    );
-    let stone1: Barrier = stone_image1.into();
-    let stone_image2 = Image::new(
-        stone_element,
-        Point {
-            x: offset_x + STONE_OFFSET2,
-            y: STONE_ON_PLATFORM,
-        },
-    );
-    let stone2: Barrier = stone_image2.into();
-    // 足場の設定
-    let platform1 = create_floating_platform(
-        sprite_sheet.clone(),
-        Point {
-            x: offset_x + FIRST_PLATFORM1,
-            y: LOW_PLATFORM1,
-        },
-    );
-    let platform2 = create_floating_platform(
-        sprite_sheet,
-        Point {
-            x: offset_x + FIRST_PLATFORM2,
-            y: LOW_PLATFORM2,
-        },
-    );
-    vec![
-        Box::new(stone1),
-        Box::new(stone2),
-        Box::new(platform1),
-        Box::new(platform2),
-    ]
-}
-
-pub fn platform_and_platform_and2(
-    stone_element: HtmlImageElement,
+/// 障害物の組を少しずつ組み立てる
+///
+/// x座標は組の左端からの距離で、y座標は画面の上端からの高さ。
+/// 石の高さは、地面や足場の当たり判定と石の画像の大きさから決める。
+/// 作った障害物は、石、足場、垂れ下がる障害物の順に並ぶ。
+pub struct SegmentBuilder {
+    stone_element: ImageHandle,
     sprite_sheet: Rc<SpriteSheet>,
     offset_x: i16,
-) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
-    const STONE_OFFSET1: i16 = 300;
-    const STONE_OFFSET2: i16 = 500;
-    const STONE_ON_PLATFORM1: i16 = 340 - 93;
-    const STONE_ON_PLATFORM2: i16 = 140 - 93;
-    const LOW_PLATFORM1: i16 = 300;
-    const FIRST_PLATFORM1: i16 = 150;
-    const LOW_PLATFORM2: i16 = 100;
-    const FIRST_PLATFORM2: i16 = 400;
-    // 障害物の設定
-    let stone_image1 = Image::new(
-        stone_element.clone(),
-        Point {
-            x: offset_x + STONE_OFFSET1,
-            y: STONE_ON_PLATFORM1,
-        },
-    );
-    let stone1: Barrier = stone_image1.into();
-    let stone_image2 = Image::new(
-        stone_element,
-        Point {
-            x: offset_x + STONE_OFFSET2,
-            y: STONE_ON_PLATFORM2,
-        },
-    );
-    let stone2: Barrier = stone_image2.into();
-    // 足場の設定
-    let platform1 = create_floating_platform(
-        sprite_sheet.clone(),
-        Point {
-            x: offset_x + FIRST_PLATFORM1,
-            y: LOW_PLATFORM1,
-        },
-    );
-    let platform2 = create_floating_platform(
-        sprite_sheet,
-        Point {
-            x: offset_x + FIRST_PLATFORM2,
-            y: LOW_PLATFORM2,
-        },
-    );
-    vec![
-        Box::new(stone1),
-        Box::new(stone2),
-        Box::new(platform1),
-        Box::new(platform2),
-    ]
+    stones: Vec<Barrier>,
+    platforms: Vec<Platform>,
+    overhead_barriers: Vec<OverheadBarrier>,
+}
+
+impl SegmentBuilder {
+    pub fn new(stone_element: ImageHandle, sprite_sheet: Rc<SpriteSheet>, offset_x: i16) -> Self {
+        SegmentBuilder {
+            stone_element,
+            sprite_sheet,
+            offset_x,
+            stones: vec![],
+            platforms: vec![],
+            overhead_barriers: vec![],
+        }
+    }
+
+    /// 石の画像の左上を`x`, `y`に置く
+    pub fn stone(mut self, x: i16, y: i16) -> Self {
+        let stone = Image::new(
+            self.stone_element.clone(),
+            Point {
+                x: self.offset_x + x,
+                y,
+            },
+        );
+        self.stones.push(stone.into());
+        self
+    }
+
+    /// 地面の上に石を置く
+    pub fn stone_on_ground(self, x: i16) -> Self {
+        let y = SEGMENT_FLOOR - self.stone_element.height();
+        self.stone(x, y)
+    }
+
+    /// `platform`番目に加えた足場の上に石を置く。
+    /// 高さは、石の下にある足場の当たり判定の上端から決める。
+    ///
+    /// # Panics
+    /// `platform`番目の足場がまだ加えられていなければpanicする。
+    pub fn stone_on_platform(self, platform: usize, x: i16) -> Self {
+        let bounding_boxes = self
+            .platforms
+            .get(platform)
+            .unwrap_or_else(|| panic!("Platform {} has not been added", platform))
+            .bounding_boxes();
+        let left = self.offset_x + x;
+        let right = left + self.stone_element.width();
+        let overlapping_top = bounding_boxes
+            .iter()
+            .filter(|bounding_box| bounding_box.x() < right && bounding_box.right() > left)
+            .map(Rect::y)
+            .min();
+        let top = overlapping_top
+            .or_else(|| bounding_boxes.iter().map(Rect::y).min())
+            .unwrap_or(SEGMENT_FLOOR);
+        let y = top + STONE_SINK_INTO_PLATFORM - self.stone_element.height();
+        self.stone(x, y)
+    }
+
+    /// 3枚のタイルでできた空中の足場を、左上を`x`, `y`にして置く
+    pub fn floating_platform(self, x: i16, y: i16) -> Self {
+        self.platform(
+            x,
+            y,
+            &FLOATING_PLATFORM_SPRITE_NAMES,
+            &FLOATING_PLATFORM_SPRITE_OFFSETS,
+            &FLOATING_PLATFORM_BONDING_BOXES,
+            PlatformSides::default(),
+        )
+    }
+
+    /// タイルの名前と位置、当たり判定を指定した足場を、左上を`x`, `y`にして置く。
+    /// タイルの位置と当たり判定は足場の左上からの位置。
+    /// `sides`は横側にぶつかった時の動き。
+    pub fn platform(
+        mut self,
+        x: i16,
+        y: i16,
+        sprite_names: &[&str],
+        offsets: &[Point],
+        bounding_boxes: &[&Rect],
+        sides: PlatformSides,
+    ) -> Self {
+        let platform = Platform::new(
+            self.sprite_sheet.clone(),
+            bounding_boxes,
+            sprite_names,
+            offsets,
+            Point {
+                x: self.offset_x + x,
+                y,
+            },
+        )
+        .with_sides(sides);
+        self.platforms.push(platform);
+        self
+    }
+
+    /// 左端を`x`にして、画面の上から頭の高さまで垂れ下がる障害物を置く。
+    /// スライディングでしか抜けられない
+    pub fn overhead_barrier(mut self, x: i16) -> Self {
+        let overhead_barrier = OverheadBarrier::new(
+            self.sprite_sheet.clone(),
+            &OVERHEAD_BARRIER_BOUNDING_BOX,
+            &OVERHEAD_BARRIER_SPRITE_NAMES,
+            &OVERHEAD_BARRIER_SPRITE_OFFSETS,
+            Point {
+                x: self.offset_x + x,
+                y: OVERHEAD_BARRIER_BOTTOM - OVERHEAD_BARRIER_BOUNDING_BOX.height,
+            },
+        );
+        self.overhead_barriers.push(overhead_barrier);
+        self
+    }
+
+    pub fn build(self) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
+        let stones = self
+            .stones
+            .into_iter()
+            .map(|stone| Box::new(stone) as Box<dyn Obstacle<RedHatBoy>>);
+        let platforms = self
+            .platforms
+            .into_iter()
+            .map(|platform| Box::new(platform) as Box<dyn Obstacle<RedHatBoy>>);
+        let overhead_barriers = self
+            .overhead_barriers
+            .into_iter()
+            .map(|overhead_barrier| Box::new(overhead_barrier) as Box<dyn Obstacle<RedHatBoy>>);
+        stones.chain(platforms).chain(overhead_barriers).collect()
+    }
+}
+
+/// 障害物の組の定義の一覧。`segments.json`の中身
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SegmentLibrary {
+    /// 名前をつけた足場の形。組の中で[`PlatformPlacement::kind`]として使う
+    platforms: HashMap<String, PlatformDefinition>,
+    segments: Vec<SegmentDefinition>,
+}
+
+/// 一つの障害物の組。座標は組の左端からの位置
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SegmentDefinition {
+    name: String,
+    #[serde(default)]
+    difficulty: Difficulty,
+    #[serde(default)]
+    barriers: Vec<BarrierDefinition>,
+    #[serde(default)]
+    platforms: Vec<PlatformPlacement>,
+    #[serde(default)]
+    overhead_barriers: Vec<OverheadBarrierDefinition>,
+}
+
+/// 組の難しさ。書かれていなければ[`Difficulty::Normal`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    /// 何もしなくても抜けられるくらいの組。難しい組の後の息抜きにも使う
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+/// 石。画像の左上の位置に置く
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct BarrierDefinition {
+    x: i16,
+    y: i16,
+}
+
+/// 垂れ下がる障害物。高さは決まっているので、左端の位置だけを書く
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct OverheadBarrierDefinition {
+    x: i16,
+}
+
+/// 足場の形を置く位置
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PlatformPlacement {
+    kind: String,
+    x: i16,
+    y: i16,
+}
+
+/// 足場の形。タイルの絵と当たり判定は、どちらも足場の左上からの位置
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PlatformDefinition {
+    sprites: Vec<SpriteDefinition>,
+    bounding_boxes: Vec<RectDefinition>,
+    /// 横側にぶつかった時の動き。書かれていなければ倒れる
+    #[serde(default)]
+    sides: PlatformSides,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SpriteDefinition {
+    /// `tiles.json`のタイルの名前
+    name: String,
+    offset: Point,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct RectDefinition {
+    x: i16,
+    y: i16,
+    width: i16,
+    height: i16,
+}
+
+impl From<RectDefinition> for Rect {
+    fn from(rect: RectDefinition) -> Self {
+        Rect::new_from_x_y(rect.x, rect.y, rect.width, rect.height)
+    }
+}
+
+impl SegmentLibrary {
+    #[cfg(test)]
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|err| anyhow!("Could not parse segments {}", err))
+    }
+
+    /// 組の数
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// `index`番目の組の名前
+    #[cfg(test)]
+    pub fn name(&self, index: usize) -> &str {
+        &self.segments[index].name
+    }
+
+    pub fn difficulty(&self, index: usize) -> Difficulty {
+        self.segments[index].difficulty
+    }
+
+    /// ゲームで使えるかを確かめる。
+    /// 使えなければ、どの組のどこが悪いかをエラーで返す。
+    pub fn validate(&self, sprite_sheet: &SpriteSheet) -> Result<()> {
+        if self.segments.is_empty() {
+            return Err(anyhow!("No segments are defined"));
+        }
+        self.platforms.iter().try_for_each(|(kind, platform)| {
+            platform
+                .validate(sprite_sheet)
+                .map_err(|err| anyhow!("Platform {} is invalid: {}", kind, err))
+        })?;
+        let mut names = HashSet::new();
+        self.segments.iter().try_for_each(|segment| {
+            if !names.insert(&segment.name) {
+                return Err(anyhow!("Segment {} is defined twice", segment.name));
+            }
+            segment
+                .validate(self)
+                .map_err(|err| anyhow!("Segment {} is invalid: {}", segment.name, err))
+        })?;
+        if !self
+            .segments
+            .iter()
+            .any(|segment| segment.difficulty == Difficulty::Easy)
+        {
+            return Err(anyhow!(
+                "No easy segments are defined to start with and rest after hard ones"
+            ));
+        }
+        Ok(())
+    }
+
+    /// `index`番目の組の障害物を、左端を`offset_x`にして作る。
+    /// そんな組がなければエラーを返す。
+    pub fn instantiate(
+        &self,
+        index: usize,
+        stone_element: ImageHandle,
+        sprite_sheet: Rc<SpriteSheet>,
+        offset_x: i16,
+    ) -> Result<Vec<Box<dyn Obstacle<RedHatBoy>>>> {
+        let segment = self.segments.get(index).ok_or_else(|| {
+            anyhow!(
+                "Segment {} does not exist (expected less than {})",
+                index,
+                self.segments.len()
+            )
+        })?;
+        let builder = SegmentBuilder::new(stone_element, sprite_sheet, offset_x);
+        let builder = segment.barriers.iter().fold(builder, |builder, barrier| {
+            builder.stone(barrier.x, barrier.y)
+        });
+        let builder = segment
+            .overhead_barriers
+            .iter()
+            .fold(builder, |builder, overhead_barrier| {
+                builder.overhead_barrier(overhead_barrier.x)
+            });
+        Ok(segment
+            .platforms
+            .iter()
+            .filter_map(|placement| Some((placement, self.platforms.get(&placement.kind)?)))
+            .fold(builder, |builder, (placement, platform)| {
+                let bounding_boxes: Vec<Rect> = platform
+                    .bounding_boxes
+                    .iter()
+                    .map(|&bounding_box| bounding_box.into())
+                    .collect();
+                let sprite_names: Vec<&str> = platform
+                    .sprites
+                    .iter()
+                    .map(|sprite| sprite.name.as_str())
+                    .collect();
+                let offsets: Vec<Point> = platform
+                    .sprites
+                    .iter()
+                    .map(|sprite| sprite.offset)
+                    .collect();
+                builder.platform(
+                    placement.x,
+                    placement.y,
+                    &sprite_names,
+                    &offsets,
+                    &bounding_boxes.iter().collect::<Vec<&Rect>>(),
+                    platform.sides,
+                )
+            })
+            .build())
+    }
+}
+
+impl SegmentDefinition {
+    fn validate(&self, library: &SegmentLibrary) -> Result<()> {
+        if self.barriers.is_empty()
+            && self.platforms.is_empty()
+            && self.overhead_barriers.is_empty()
+        {
+            return Err(anyhow!("it has no obstacles"));
+        }
+        let mut positions = self
+            .barriers
+            .iter()
+            .map(|barrier| (barrier.x, barrier.y))
+            .chain(
+                self.platforms
+                    .iter()
+                    .map(|platform| (platform.x, platform.y)),
+            )
+            // 垂れ下がる障害物は画面の上端から下がっている
+            .chain(
+                self.overhead_barriers
+                    .iter()
+                    .map(|overhead_barrier| (overhead_barrier.x, 0)),
+            );
+        positions.try_for_each(|(x, y)| {
+            if x < 0 {
+                Err(anyhow!("obstacle at x {} is left of the segment", x))
+            } else if !(0..=SEGMENT_FLOOR).contains(&y) {
+                Err(anyhow!("obstacle at y {} is off the screen", y))
+            } else {
+                Ok(())
+            }
+        })?;
+        self.platforms.iter().try_for_each(|placement| {
+            if library.platforms.contains_key(&placement.kind) {
+                Ok(())
+            } else {
+                Err(anyhow!("platform kind {} is not defined", placement.kind))
+            }
+        })
+    }
+}
+
+impl PlatformDefinition {
+    fn validate(&self, sprite_sheet: &SpriteSheet) -> Result<()> {
+        if self.bounding_boxes.is_empty() {
+            return Err(anyhow!("it has no bounding boxes"));
+        }
+        if let Some(rect) = self
+            .bounding_boxes
+            .iter()
+            .find(|rect| rect.width <= 0 || rect.height <= 0)
+        {
+            return Err(anyhow!("bounding box {:?} has no area", rect));
+        }
+        self.sprites.iter().try_for_each(|sprite| {
+            sprite_sheet
+                .cell(&sprite.name)
+                .map(|_| ())
+                .ok_or_else(|| anyhow!("sprite {} is not in the tile sheet", sprite.name))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{builder, stone, tiles_sheet};
+    use super::*;
+    use crate::engine::debug::DebugSettings;
+    use crate::engine::headless::{DrawCommand, RecordingRenderer};
+
+    fn library() -> SegmentLibrary {
+        SegmentLibrary::from_json(include_str!("../static/segments.json")).unwrap()
+    }
+
+    fn draw(obstacles: &[Box<dyn Obstacle<RedHatBoy>>]) -> RecordingRenderer {
+        let renderer = RecordingRenderer::new();
+        let debug = DebugSettings {
+            hitboxes: true,
+            ..Default::default()
+        };
+        obstacles
+            .iter()
+            .for_each(|obstacle| obstacle.draw(&renderer, &debug));
+        renderer
+    }
+
+    /// 障害物の当たり判定の左上の位置を、並んでいる順に返す
+    fn hitbox_positions(obstacles: &[Box<dyn Obstacle<RedHatBoy>>]) -> Vec<Point> {
+        draw(obstacles)
+            .commands()
+            .into_iter()
+            .filter_map(|command| match command {
+                DrawCommand::DrawRect { rect, .. } => Some(rect.position),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stones_on_the_ground_stand_on_the_floor() {
+        let obstacles = builder(500).stone_on_ground(100).build();
+
+        assert_eq!(hitbox_positions(&obstacles), vec![Point { x: 600, y: 546 }]);
+    }
+
+    #[test]
+    fn stones_on_a_platform_stand_on_its_top() {
+        let obstacles = builder(0)
+            .floating_platform(150, 380)
+            .stone_on_platform(0, 200)
+            .build();
+
+        // 元の配置の STONE_ON_PLATFORM = 420 - 93 と同じ高さ
+        assert_eq!(hitbox_positions(&obstacles)[0], Point { x: 200, y: 327 });
+    }
+
+    #[test]
+    fn stone_height_follows_the_bounding_box_under_it() {
+        let step = [
+            &Rect::new_from_x_y(0, 50, 100, 10),
+            &Rect::new_from_x_y(100, 0, 100, 60),
+        ];
+        let obstacles = builder(0)
+            .platform(
+                0,
+                300,
+                &["13.png"],
+                &[Point { x: 0, y: 0 }],
+                &step,
+                PlatformSides::default(),
+            )
+            .stone_on_platform(0, 0)
+            .stone_on_platform(0, 110)
+            .build();
+
+        let positions = hitbox_positions(&obstacles);
+        assert_eq!(positions[0].y, 350 + 1 - 54);
+        assert_eq!(positions[1].y, 300 + 1 - 54);
+    }
+
+    #[test]
+    fn stones_come_before_platforms() {
+        let obstacles = builder(0)
+            .floating_platform(0, 300)
+            .stone_on_ground(500)
+            .build();
+
+        assert_eq!(obstacles.len(), 2);
+        assert_eq!(obstacles[0].right(), 590);
+        assert_eq!(obstacles[1].right(), 384);
+    }
+
+    #[test]
+    fn overhead_barriers_come_last_and_hang_down_to_head_height() {
+        let obstacles = builder(100)
+            .overhead_barrier(0)
+            .floating_platform(200, 300)
+            .build();
+
+        let positions = hitbox_positions(&obstacles);
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[3], Point { x: 100, y: -115 });
+        assert_eq!(obstacles[1].right(), 228);
+    }
+
+    #[test]
+    #[should_panic(expected = "Platform 1 has not been added")]
+    fn stone_on_a_missing_platform_panics() {
+        builder(0).floating_platform(0, 300).stone_on_platform(1, 0);
+    }
+
+    #[test]
+    fn bundled_segments_are_valid() {
+        library().validate(&tiles_sheet()).unwrap();
+    }
+
+    #[test]
+    fn segment_sources_are_parsed_by_name_and_byte() {
+        assert_eq!(
+            SegmentSource::parse("generated").unwrap(),
+            SegmentSource::Generated
+        );
+        assert!(SegmentSource::parse("random").is_err());
+        [SegmentSource::Library, SegmentSource::Generated]
+            .iter()
+            .for_each(|&source| {
+                assert_eq!(SegmentSource::from_byte(source.to_byte()).unwrap(), source);
+            });
+        assert!(SegmentSource::from_byte(2).is_err());
+    }
+
+    #[test]
+    fn instantiating_a_missing_segment_is_an_error() {
+        let library = library();
+        let stone = stone();
+
+        assert!(library
+            .instantiate(library.len(), stone, tiles_sheet(), 700)
+            .is_err());
+    }
+
+    #[test]
+    fn unknown_sprites_and_platform_kinds_are_rejected() {
+        let unknown_sprite = SegmentLibrary::from_json(
+            r#"{
+                "platforms": {
+                    "broken": {
+                        "sprites": [{ "name": "999.png", "offset": { "x": 0, "y": 0 } }],
+                        "bounding_boxes": [{ "x": 0, "y": 0, "width": 10, "height": 10 }]
+                    }
+                },
+                "segments": [{ "name": "a", "platforms": [{ "kind": "broken", "x": 0, "y": 0 }] }]
+            }"#,
+        )
+        .unwrap();
+        let unknown_kind = SegmentLibrary::from_json(
+            r#"{
+                "platforms": {},
+                "segments": [{ "name": "a", "platforms": [{ "kind": "missing", "x": 0, "y": 0 }] }]
+            }"#,
+        )
+        .unwrap();
+
+        let err = unknown_sprite.validate(&tiles_sheet()).unwrap_err();
+        assert!(err.to_string().contains("999.png"));
+        let err = unknown_kind.validate(&tiles_sheet()).unwrap_err();
+        assert!(err.to_string().contains("missing"));
+    }
+
+    #[test]
+    fn empty_duplicate_and_misplaced_segments_are_rejected() {
+        let validate = |segments: &str| {
+            SegmentLibrary::from_json(&format!(
+                r#"{{ "platforms": {{}}, "segments": [{}] }}"#,
+                segments
+            ))
+            .unwrap()
+            .validate(&tiles_sheet())
+        };
+
+        assert!(validate("").is_err());
+        assert!(validate(r#"{ "name": "a" }"#).is_err());
+        assert!(validate(
+            r#"{ "name": "a", "barriers": [{ "x": 0, "y": 546 }] },
+               { "name": "a", "barriers": [{ "x": 0, "y": 546 }] }"#
+        )
+        .is_err());
+        assert!(validate(
+            r#"{ "name": "a", "difficulty": "easy", "barriers": [{ "x": -5, "y": 546 }] }"#
+        )
+        .is_err());
+        assert!(validate(
+            r#"{ "name": "a", "difficulty": "easy", "barriers": [{ "x": 0, "y": 546 }] }"#
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn a_library_without_easy_segments_is_rejected() {
+        let library = SegmentLibrary::from_json(
+            r#"{
+                "platforms": {},
+                "segments": [
+                    { "name": "a", "barriers": [{ "x": 0, "y": 546 }] },
+                    { "name": "b", "difficulty": "hard", "barriers": [{ "x": 0, "y": 546 }] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(library.difficulty(0), Difficulty::Normal);
+        let err = library.validate(&tiles_sheet()).unwrap_err();
+        assert!(err.to_string().contains("easy"));
+    }
+
+    #[test]
+    fn platform_sides_default_to_knocking_out() {
+        let library = |sides: &str| {
+            SegmentLibrary::from_json(&format!(
+                r#"{{
+                    "platforms": {{
+                        "block": {{
+                            "sprites": [],
+                            "bounding_boxes": [{{ "x": 0, "y": 0, "width": 10, "height": 10 }}]
+                            {}
+                        }}
+                    }},
+                    "segments": []
+                }}"#,
+                sides
+            ))
+        };
+        let sides = |library: SegmentLibrary| library.platforms["block"].sides;
+
+        assert_eq!(sides(library("").unwrap()), PlatformSides::KnockOut);
+        assert_eq!(
+            sides(library(r#", "sides": "push_back""#).unwrap()),
+            PlatformSides::PushBack
+        );
+        assert!(library(r#", "sides": "bounce""#).is_err());
+    }
+
+    #[test]
+    fn malformed_json_is_an_error() {
+        assert!(SegmentLibrary::from_json(r#"{ "segments": 1 }"#).is_err());
+    }
 }
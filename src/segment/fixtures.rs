@@ -0,0 +1,27 @@
+//! テストで障害物の組を作るための素材
+//!
+//! 画像は読み込まず、ゲームと同じ大きさの[`ImageHandle::headless`]を使う。
+
+use std::rc::Rc;
+
+use crate::engine::{ImageHandle, SpriteSheet};
+
+use super::SegmentBuilder;
+
+/// `static/tiles.json`のタイルのシート
+pub fn tiles_sheet() -> Rc<SpriteSheet> {
+    Rc::new(SpriteSheet::new(
+        serde_json::from_str(include_str!("../../static/tiles.json")).unwrap(),
+        ImageHandle::headless("tiles.png", 1024, 512),
+    ))
+}
+
+/// `Stone.png`と同じ大きさの石
+pub fn stone() -> ImageHandle {
+    ImageHandle::headless("Stone.png", 90, 54)
+}
+
+/// 左端を`offset_x`にした、石とタイルのシートを使う組
+pub fn builder(offset_x: i16) -> SegmentBuilder {
+    SegmentBuilder::new(stone(), tiles_sheet(), offset_x)
+}
@@ -0,0 +1,193 @@
+//! 部品を組み合わせて障害物の組を作る
+//!
+//! 組は次の文法で作る。隙間の広さや足場の高さは、ジャンプで届く距離と高さから決める。
+//!
+//! ```text
+//! 組   := (隙間 部品)+
+//! 部品 := 地面の石 | 足場の段差 | 石の乗った足場
+//! ```
+//!
+//! 作った組は[`SegmentBuilder`]で組み立てるので、ほかの組と同じようにシーンに置ける。
+//! 同じシードの[`SegmentGenerator`]は、同じ組を同じ順番で作る。
+
+use std::rc::Rc;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use super::{SegmentBuilder, FLOATING_PLATFORM_WIDTH, SEGMENT_FLOOR};
+use crate::{
+    engine::{ImageHandle, SpriteSheet},
+    game::{Obstacle, RedHatBoy, GRAVITY, JUMPING_SPEED, RUNNING_SPEED},
+};
+
+/// ジャンプしてから元の高さに戻るまでのtick数
+const JUMP_TICKS: i16 = 2 * -JUMPING_SPEED / GRAVITY + 1;
+/// ジャンプで足が上がる高さ。実際にはこれより少し高く上がる
+const JUMP_HEIGHT: i16 = JUMPING_SPEED * JUMPING_SPEED / (2 * GRAVITY);
+/// 走り始めの速さでジャンプした時に進む距離
+const JUMP_DISTANCE: i16 = JUMP_TICKS * RUNNING_SPEED;
+/// 跳び乗る足場の上端と、ジャンプで一番高く上がった足の間に残す余裕
+const JUMP_CLEARANCE: i16 = 40;
+
+/// 部品の前の隙間の広さ。着地してからもう一度跳ぶまでの余裕を、速く走った時の分まで取る
+const MIN_GAP: i16 = JUMP_DISTANCE * 2;
+const MAX_GAP: i16 = JUMP_DISTANCE * 3;
+/// 地面から跳び乗れる一番高い足場の上端
+const HIGHEST_PLATFORM_TOP: i16 = SEGMENT_FLOOR - JUMP_HEIGHT + JUMP_CLEARANCE;
+/// 下を走り抜けられる一番低い足場の上端
+const LOWEST_PLATFORM_TOP: i16 = 380;
+/// 段差の高い方の足場が画面からはみ出さないように、上端はこれより下にする
+const SCREEN_TOP_MARGIN: i16 = 50;
+const MIN_STEP: i16 = 100;
+/// 足場から跳び乗れる一番高い段差
+const MAX_STEP: i16 = JUMP_HEIGHT - JUMP_CLEARANCE;
+/// 一つの組に入れる部品の数の上限
+const MAX_PARTS: usize = 3;
+
+/// 組の部品。x座標は部品の左端からの距離
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    /// 何も置かない隙間
+    Gap(i16),
+    /// 地面の石
+    GroundStone,
+    /// 上端が`top`の足場と、その右に続く`step`だけ高い足場
+    PlatformStepUp { top: i16, step: i16 },
+    /// 上端が`top`の足場と、その上の`stone_x`に乗った石
+    PlatformWithStone { top: i16, stone_x: i16 },
+}
+
+/// 部品を組み合わせて障害物の組を作る
+pub struct SegmentGenerator {
+    rng: ChaCha8Rng,
+}
+
+impl SegmentGenerator {
+    pub fn new(seed: u64) -> Self {
+        SegmentGenerator {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// 次の組の部品の並び。`stone_width`は石の画像の幅
+    pub fn next_layout(&mut self, stone_width: i16) -> Vec<Part> {
+        let count = self.rng.gen_range(1..=MAX_PARTS);
+        (0..count)
+            .flat_map(|_| {
+                let gap = Part::Gap(self.rng.gen_range(MIN_GAP..=MAX_GAP));
+                [gap, self.next_part(stone_width)]
+            })
+            .collect()
+    }
+
+    /// 次の組を作り、左端を`offset_x`にして置く
+    pub fn next_segment(
+        &mut self,
+        stone_element: ImageHandle,
+        sprite_sheet: Rc<SpriteSheet>,
+        offset_x: i16,
+    ) -> Vec<Box<dyn Obstacle<RedHatBoy>>> {
+        let layout = self.next_layout(stone_element.width());
+        build(
+            &layout,
+            SegmentBuilder::new(stone_element, sprite_sheet, offset_x),
+        )
+        .build()
+    }
+
+    fn next_part(&mut self, stone_width: i16) -> Part {
+        let top = self
+            .rng
+            .gen_range(HIGHEST_PLATFORM_TOP..=LOWEST_PLATFORM_TOP);
+        match self.rng.gen_range(0..3) {
+            0 => Part::GroundStone,
+            1 => Part::PlatformStepUp {
+                top,
+                step: self
+                    .rng
+                    .gen_range(MIN_STEP..=MAX_STEP.min(top - SCREEN_TOP_MARGIN)),
+            },
+            _ => Part::PlatformWithStone {
+                top,
+                stone_x: self
+                    .rng
+                    .gen_range(0..=FLOATING_PLATFORM_WIDTH - stone_width),
+            },
+        }
+    }
+}
+
+/// `layout`の部品を左から順に`builder`に加える
+pub fn build(layout: &[Part], builder: SegmentBuilder) -> SegmentBuilder {
+    let stone_width = builder.stone_element.width();
+    let (builder, _, _) =
+        layout.iter().fold(
+            (builder, 0, 0),
+            |(builder, x, platforms), part| match *part {
+                Part::Gap(width) => (builder, x + width, platforms),
+                Part::GroundStone => (builder.stone_on_ground(x), x + stone_width, platforms),
+                Part::PlatformStepUp { top, step } => (
+                    builder
+                        .floating_platform(x, top)
+                        .floating_platform(x + FLOATING_PLATFORM_WIDTH, top - step),
+                    x + FLOATING_PLATFORM_WIDTH * 2,
+                    platforms + 2,
+                ),
+                Part::PlatformWithStone { top, stone_x } => (
+                    builder
+                        .floating_platform(x, top)
+                        .stone_on_platform(platforms, x + stone_x),
+                    x + FLOATING_PLATFORM_WIDTH,
+                    platforms + 1,
+                ),
+            },
+        );
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE_WIDTH: i16 = 90;
+
+    fn layouts(seed: u64, count: usize) -> Vec<Vec<Part>> {
+        let mut generator = SegmentGenerator::new(seed);
+        (0..count)
+            .map(|_| generator.next_layout(STONE_WIDTH))
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_layouts() {
+        assert_eq!(layouts(3, 20), layouts(3, 20));
+        assert_ne!(layouts(3, 20), layouts(4, 20));
+    }
+
+    #[test]
+    fn layouts_follow_the_grammar_and_jump_limits() {
+        layouts(1, 500).iter().for_each(|layout| {
+            assert!((2..=MAX_PARTS * 2).contains(&layout.len()), "{:?}", layout);
+            layout.chunks(2).for_each(|pair| {
+                let Part::Gap(gap) = pair[0] else {
+                    panic!("{:?} does not start with a gap", pair);
+                };
+                assert!((MIN_GAP..=MAX_GAP).contains(&gap));
+                match pair[1] {
+                    Part::Gap(_) => panic!("two gaps in a row in {:?}", layout),
+                    Part::GroundStone => {}
+                    Part::PlatformStepUp { top, step } => {
+                        assert!((HIGHEST_PLATFORM_TOP..=LOWEST_PLATFORM_TOP).contains(&top));
+                        assert!((MIN_STEP..=MAX_STEP).contains(&step));
+                        assert!(top - step >= SCREEN_TOP_MARGIN);
+                    }
+                    Part::PlatformWithStone { top, stone_x } => {
+                        assert!((HIGHEST_PLATFORM_TOP..=LOWEST_PLATFORM_TOP).contains(&top));
+                        assert!((0..=FLOATING_PLATFORM_WIDTH - STONE_WIDTH).contains(&stone_x));
+                    }
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,213 @@
+//! 次に置く障害物の組の選び方
+//!
+//! シーンは[`SegmentPolicy`]に次の組を選ばせる。乱数はシーンのものを渡すので、
+//! 同じシードなら同じ順番で選ばれる。
+
+use rand::{seq::SliceRandom, RngCore};
+
+use super::{Difficulty, SegmentLibrary};
+
+/// この距離を走ると[`Difficulty::Normal`]の組が出てくる。走る速さ3で10秒
+pub const NORMAL_UNLOCK_DISTANCE: i32 = 1800;
+/// この距離を走ると[`Difficulty::Hard`]の組が出てくる。走る速さ3で30秒
+pub const HARD_UNLOCK_DISTANCE: i32 = 5400;
+
+/// 組を選ぶ時のゲームの進み具合
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SelectionContext {
+    /// 走り始めてから進んだ距離
+    pub distance: i32,
+    /// 直前に選んだ組の番号
+    pub previous: Option<usize>,
+}
+
+/// 次に置く組の選び方
+pub trait SegmentPolicy {
+    /// `segments`の中から次に置く組の番号を選ぶ
+    fn choose(
+        &self,
+        segments: &SegmentLibrary,
+        context: &SelectionContext,
+        rng: &mut dyn RngCore,
+    ) -> usize;
+}
+
+/// 走った距離に応じて難しい組を出し、易しい組ほど多く選ぶ。
+///
+/// - 解禁されていない難しさの組は選ばない
+/// - 難しい組の次は易しい組にする
+/// - 直前と同じ組は選ばない
+///
+/// 規則を守ると選べる組がなくなる時は、その規則を諦める。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DifficultyPolicy;
+
+impl DifficultyPolicy {
+    fn unlock_distance(difficulty: Difficulty) -> i32 {
+        match difficulty {
+            Difficulty::Easy => 0,
+            Difficulty::Normal => NORMAL_UNLOCK_DISTANCE,
+            Difficulty::Hard => HARD_UNLOCK_DISTANCE,
+        }
+    }
+
+    /// 選ばれやすさ
+    fn weight(difficulty: Difficulty) -> u32 {
+        match difficulty {
+            Difficulty::Easy => 3,
+            Difficulty::Normal => 2,
+            Difficulty::Hard => 1,
+        }
+    }
+}
+
+impl SegmentPolicy for DifficultyPolicy {
+    fn choose(
+        &self,
+        segments: &SegmentLibrary,
+        context: &SelectionContext,
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        let previous_difficulty = context
+            .previous
+            .map(|previous| segments.difficulty(previous));
+        let rules: [&dyn Fn(usize) -> bool; 3] = [
+            &|index| Self::unlock_distance(segments.difficulty(index)) <= context.distance,
+            &|index| {
+                previous_difficulty != Some(Difficulty::Hard)
+                    || segments.difficulty(index) == Difficulty::Easy
+            },
+            &|index| context.previous != Some(index),
+        ];
+        let candidates = rules.iter().fold(
+            (0..segments.len()).collect::<Vec<usize>>(),
+            |candidates, rule| {
+                let allowed: Vec<usize> = candidates
+                    .iter()
+                    .copied()
+                    .filter(|&index| rule(index))
+                    .collect();
+                if allowed.is_empty() {
+                    candidates
+                } else {
+                    allowed
+                }
+            },
+        );
+        *candidates
+            .choose_weighted(rng, |&index| Self::weight(segments.difficulty(index)))
+            .expect("segment library has no segments")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    /// 番号と難しさが`difficulties`の順に並んだ組
+    fn library(difficulties: &[&str]) -> SegmentLibrary {
+        let segments: Vec<String> = difficulties
+            .iter()
+            .enumerate()
+            .map(|(index, difficulty)| {
+                format!(
+                    r#"{{ "name": "{}", "difficulty": "{}", "barriers": [{{ "x": 0, "y": 546 }}] }}"#,
+                    index, difficulty
+                )
+            })
+            .collect();
+        SegmentLibrary::from_json(&format!(
+            r#"{{ "platforms": {{}}, "segments": [{}] }}"#,
+            segments.join(",")
+        ))
+        .unwrap()
+    }
+
+    /// `distance`を走った後から、`length`個の組を選ぶ
+    fn sequence(segments: &SegmentLibrary, distance: i32, length: usize) -> Vec<usize> {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut context = SelectionContext {
+            distance,
+            previous: None,
+        };
+        (0..length)
+            .map(|_| {
+                let index = DifficultyPolicy.choose(segments, &context, &mut rng);
+                context.previous = Some(index);
+                index
+            })
+            .collect()
+    }
+
+    #[test]
+    fn harder_segments_are_unlocked_by_distance() {
+        let segments = library(&["easy", "easy", "normal", "hard"]);
+
+        let start = sequence(&segments, 0, 50);
+        assert!(start.iter().all(|&index| index < 2));
+
+        let middle = sequence(&segments, NORMAL_UNLOCK_DISTANCE, 50);
+        assert!(middle.contains(&2));
+        assert!(!middle.contains(&3));
+
+        assert!(sequence(&segments, HARD_UNLOCK_DISTANCE, 50).contains(&3));
+    }
+
+    #[test]
+    fn the_same_segment_is_never_chosen_twice_in_a_row() {
+        let segments = library(&["easy", "easy", "normal", "normal", "hard"]);
+
+        let chosen = sequence(&segments, HARD_UNLOCK_DISTANCE, 200);
+
+        assert!(chosen.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn a_hard_segment_is_followed_by_an_easy_one() {
+        let segments = library(&["easy", "normal", "hard", "hard"]);
+
+        let chosen = sequence(&segments, HARD_UNLOCK_DISTANCE, 200);
+
+        assert!(chosen.iter().any(|&index| index >= 2));
+        chosen
+            .windows(2)
+            .filter(|pair| segments.difficulty(pair[0]) == Difficulty::Hard)
+            .for_each(|pair| assert_eq!(segments.difficulty(pair[1]), Difficulty::Easy));
+    }
+
+    #[test]
+    fn easier_segments_are_chosen_more_often() {
+        let segments = library(&["easy", "normal", "hard"]);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let context = SelectionContext {
+            distance: HARD_UNLOCK_DISTANCE,
+            previous: None,
+        };
+
+        let mut counts = [0; 3];
+        (0..600).for_each(|_| counts[DifficultyPolicy.choose(&segments, &context, &mut rng)] += 1);
+
+        assert!(counts[0] > counts[1], "{:?}", counts);
+        assert!(counts[1] > counts[2], "{:?}", counts);
+    }
+
+    #[test]
+    fn rules_give_way_when_nothing_else_is_left() {
+        let segments = library(&["easy"]);
+
+        assert_eq!(sequence(&segments, 0, 3), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn the_same_seed_chooses_the_same_sequence() {
+        let segments = library(&["easy", "easy", "normal", "normal", "hard"]);
+
+        assert_eq!(
+            sequence(&segments, HARD_UNLOCK_DISTANCE, 30),
+            sequence(&segments, HARD_UNLOCK_DISTANCE, 30)
+        );
+    }
+}
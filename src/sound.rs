@@ -34,6 +34,7 @@ pub fn create_track_source(
     connect_with_audio_node(&track_source, &context.destination())?;
     Ok(track_source)
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Looping {
     No,
     Yes,
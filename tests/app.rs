@@ -4,12 +4,14 @@ wasm_bindgen_test_configure!(run_in_browser);
 
 // This runs a unit test in native Rust, so it can only use Rust APIs.
 #[test]
+#[allow(clippy::eq_op)]
 fn rust_test() {
     assert_eq!(1, 1);
 }
 
 // This runs a unit test in the browser, so it can use browser APIs.
 #[wasm_bindgen_test]
+#[allow(clippy::eq_op)]
 fn web_test() {
     assert_eq!(1, 1);
 }